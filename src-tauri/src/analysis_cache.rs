@@ -0,0 +1,423 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use diesel::{
+    connection::SimpleConnection,
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Integer, Text},
+};
+use log::{info, warn};
+use shakmaty::{fen::Epd, san::SanPlus, CastlingMode, Chess, Color, Position};
+use tauri::{path::BaseDirectory, Manager};
+use vampirc_uci::uci::ScoreValue;
+
+use crate::{
+    chess::{AnalysisCacheKey, BestMoves, GoMode},
+    error::{Error, Result},
+    AppState,
+};
+
+const CACHE_FILE: &str = "analysis_cache.db";
+
+const CREATE_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS AnalysisCache (
+        Fen TEXT NOT NULL,
+        Engine TEXT NOT NULL,
+        MultiPV INTEGER NOT NULL,
+        Depth INTEGER NOT NULL,
+        Nodes INTEGER NOT NULL,
+        Time INTEGER NOT NULL,
+        Lines TEXT NOT NULL,
+        PRIMARY KEY (Fen, Engine, MultiPV)
+    ) WITHOUT ROWID;
+";
+
+/// Keeps the deepest analysis of a position. When a search only reaches the
+/// same depth, the longest effort is kept so it keeps satisfying the same limits.
+const UPSERT_SQL: &str = "
+    INSERT INTO AnalysisCache (Fen, Engine, MultiPV, Depth, Nodes, Time, Lines)
+    VALUES (?, ?, ?, ?, ?, ?, ?)
+    ON CONFLICT (Fen, Engine, MultiPV) DO UPDATE SET
+        Nodes = CASE WHEN excluded.Depth = Depth THEN MAX(excluded.Nodes, Nodes) ELSE excluded.Nodes END,
+        Time = CASE WHEN excluded.Depth = Depth THEN MAX(excluded.Time, Time) ELSE excluded.Time END,
+        Depth = excluded.Depth,
+        Lines = excluded.Lines
+    WHERE excluded.Depth >= AnalysisCache.Depth;
+";
+
+diesel::table! {
+    #[sql_name = "AnalysisCache"]
+    analysis_cache (fen, engine, multipv) {
+        #[sql_name = "Fen"]
+        fen -> Text,
+        #[sql_name = "Engine"]
+        engine -> Text,
+        #[sql_name = "MultiPV"]
+        multipv -> Integer,
+        #[sql_name = "Depth"]
+        depth -> Integer,
+        #[sql_name = "Nodes"]
+        nodes -> BigInt,
+        #[sql_name = "Time"]
+        time -> BigInt,
+        #[sql_name = "Lines"]
+        lines -> Text,
+    }
+}
+
+/// An analysis read back from the cache.
+#[derive(Debug, Clone)]
+pub struct CachedAnalysis {
+    pub depth: u32,
+    pub nodes: u32,
+    /// Milliseconds the engine spent on the search
+    pub time: u64,
+    pub lines: Vec<BestMoves>,
+}
+
+impl CachedAnalysis {
+    /// Whether the analysis is at least as thorough as a search with `go_mode`.
+    ///
+    /// Searches on the clock or without a limit can't be compared, they are
+    /// only started from the cached lines.
+    pub fn satisfies(&self, go_mode: &GoMode) -> bool {
+        match go_mode {
+            GoMode::Depth(depth) => self.depth >= *depth,
+            GoMode::Nodes(nodes) => self.nodes >= *nodes,
+            GoMode::Time(time) => self.time >= *time as u64,
+            GoMode::Mate(_) | GoMode::PlayersTime(_) | GoMode::Infinite => false,
+        }
+    }
+}
+
+pub struct AnalysisCache {
+    db: SqliteConnection,
+}
+
+impl AnalysisCache {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut db = SqliteConnection::establish(&path.to_string_lossy())?;
+        db.batch_execute(CREATE_TABLE_SQL)?;
+        Ok(Self { db })
+    }
+
+    fn open_app(app: &tauri::AppHandle) -> Result<Self> {
+        Self::open(&app.path().resolve(CACHE_FILE, BaseDirectory::AppData)?)
+    }
+
+    pub fn get(&mut self, key: &AnalysisCacheKey) -> Result<Option<CachedAnalysis>> {
+        let row: Option<(i32, i64, i64, String)> = analysis_cache::table
+            .filter(analysis_cache::fen.eq(&key.fen))
+            .filter(analysis_cache::engine.eq(&key.engine))
+            .filter(analysis_cache::multipv.eq(key.multipv as i32))
+            .select((
+                analysis_cache::depth,
+                analysis_cache::nodes,
+                analysis_cache::time,
+                analysis_cache::lines,
+            ))
+            .first(&mut self.db)
+            .optional()?;
+
+        let Some((depth, nodes, time, lines)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(CachedAnalysis {
+            depth: depth as u32,
+            nodes: nodes as u32,
+            time: time as u64,
+            lines: serde_json::from_str(&lines)?,
+        }))
+    }
+
+    /// Saves the lines of a search unless a deeper one is already cached.
+    pub fn store(
+        &mut self,
+        key: &AnalysisCacheKey,
+        lines: &[BestMoves],
+        time: Duration,
+    ) -> Result<()> {
+        let Some(depth) = lines.iter().map(|line| line.depth).min() else {
+            return Ok(());
+        };
+        let nodes = lines
+            .iter()
+            .map(|line| line.nodes)
+            .max()
+            .unwrap_or_default();
+
+        sql_query(UPSERT_SQL)
+            .bind::<Text, _>(&key.fen)
+            .bind::<Text, _>(&key.engine)
+            .bind::<Integer, _>(key.multipv as i32)
+            .bind::<Integer, _>(depth as i32)
+            .bind::<BigInt, _>(nodes as i64)
+            .bind::<BigInt, _>(time.as_millis() as i64)
+            .bind::<Text, _>(serde_json::to_string(lines)?)
+            .execute(&mut self.db)?;
+        Ok(())
+    }
+
+    /// Removes the analyses of an engine, or all of them.
+    pub fn clear(&mut self, engine: Option<&str>) -> Result<usize> {
+        let deleted = match engine {
+            Some(engine) => {
+                diesel::delete(analysis_cache::table.filter(analysis_cache::engine.eq(engine)))
+                    .execute(&mut self.db)?
+            }
+            None => diesel::delete(analysis_cache::table).execute(&mut self.db)?,
+        };
+        Ok(deleted)
+    }
+
+    /// Writes the best line of every cached position as an EPD record.
+    pub fn export(&mut self, engine: Option<&str>, writer: &mut impl Write) -> Result<usize> {
+        let mut query = analysis_cache::table
+            .select((
+                analysis_cache::fen,
+                analysis_cache::engine,
+                analysis_cache::depth,
+                analysis_cache::nodes,
+                analysis_cache::lines,
+            ))
+            .order((analysis_cache::engine, analysis_cache::fen))
+            .into_boxed();
+        if let Some(engine) = engine {
+            query = query.filter(analysis_cache::engine.eq(engine));
+        }
+
+        let rows: Vec<(String, String, i32, i64, String)> = query.load(&mut self.db)?;
+        let mut exported = 0;
+        for (fen, engine, depth, nodes, lines) in rows {
+            let lines: Vec<BestMoves> = serde_json::from_str(&lines)?;
+            let Some(best) = lines.first() else {
+                continue;
+            };
+            let Some(record) = epd_record(&fen, &engine, depth, nodes, best) else {
+                warn!("Skipping invalid cached position: {}", fen);
+                continue;
+            };
+            writeln!(writer, "{}", record)?;
+            exported += 1;
+        }
+        Ok(exported)
+    }
+}
+
+/// Formats an analysis with the standard EPD opcodes, the evaluation being
+/// from the point of view of the side to move.
+fn epd_record(fen: &str, engine: &str, depth: i32, nodes: i64, best: &BestMoves) -> Option<String> {
+    let pos: Chess = fen
+        .parse::<Epd>()
+        .ok()?
+        .into_position(CastlingMode::Chess960)
+        .ok()?;
+    let sign = match pos.turn() {
+        Color::White => 1,
+        Color::Black => -1,
+    };
+    let eval = match best.score.value {
+        ScoreValue::Cp(cp) => format!("ce {}", sign * cp),
+        ScoreValue::Mate(moves) => format!("dm {}", sign * moves as i32),
+    };
+    // Check marks aren't part of EPD moves
+    let pv = best
+        .san_moves
+        .iter()
+        .map(|san| san.parse::<SanPlus>().map(|san| san.san.to_string()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .ok()?;
+
+    Some(format!(
+        "{} acd {}; acn {}; {}; pv {}; c0 \"{}\";",
+        fen,
+        depth,
+        nodes,
+        eval,
+        pv.join(" "),
+        engine.replace('"', "'")
+    ))
+}
+
+/// Runs `f` on the cache of the app, which is opened on first use and then
+/// kept in the app state.
+fn with_app_cache<T>(
+    app: &tauri::AppHandle,
+    f: impl FnOnce(&mut AnalysisCache) -> Result<T>,
+) -> Result<T> {
+    let state = app.state::<AppState>();
+    let mut cache = state
+        .analysis_cache
+        .lock()
+        .map_err(|e| Error::MutexLockFailed(format!("Failed to lock analysis cache: {}", e)))?;
+    if let Some(cache) = cache.as_mut() {
+        return f(cache);
+    }
+    f(cache.insert(AnalysisCache::open_app(app)?))
+}
+
+/// Looks up a position, failures to read the cache are treated as misses.
+pub async fn cached_analysis(
+    app: &tauri::AppHandle,
+    key: &AnalysisCacheKey,
+) -> Option<CachedAnalysis> {
+    let (app, key) = (app.clone(), key.clone());
+    let cached = tokio::task::spawn_blocking(move || with_app_cache(&app, |cache| cache.get(&key)));
+    match cached.await {
+        Ok(Ok(cached)) => cached,
+        Ok(Err(e)) => {
+            warn!("Failed to read the analysis cache: {}", e);
+            None
+        }
+        Err(e) => {
+            warn!("Failed to read the analysis cache: {}", e);
+            None
+        }
+    }
+}
+
+/// Saves an analysis in the background, failures only get logged since the
+/// analysis itself succeeded.
+pub fn store_analysis(
+    app: &tauri::AppHandle,
+    key: &AnalysisCacheKey,
+    lines: &[BestMoves],
+    time: Duration,
+) {
+    let (app, key, lines) = (app.clone(), key.clone(), lines.to_vec());
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = with_app_cache(&app, |cache| cache.store(&key, &lines, time)) {
+            warn!("Failed to write to the analysis cache: {}", e);
+        }
+    });
+}
+
+/// Deletes the cached analyses of an engine, or of every engine when `engine` is `None`.
+#[tauri::command]
+#[specta::specta]
+pub async fn clear_analysis_cache(engine: Option<String>, app: tauri::AppHandle) -> Result<usize> {
+    let deleted = with_app_cache(&app, |cache| cache.clear(engine.as_deref()))?;
+    info!("Cleared {} cached analyses", deleted);
+    Ok(deleted)
+}
+
+/// Exports the cached analyses to an EPD file.
+#[tauri::command]
+#[specta::specta]
+pub async fn export_analysis_cache(
+    file: PathBuf,
+    engine: Option<String>,
+    app: tauri::AppHandle,
+) -> Result<usize> {
+    let mut writer = BufWriter::new(File::create(&file)?);
+    let exported = with_app_cache(&app, |cache| cache.export(engine.as_deref(), &mut writer))?;
+    writer.flush()?;
+    info!("Exported {} cached analyses to {:?}", exported, file);
+    Ok(exported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vampirc_uci::uci::Score;
+
+    fn key(moves: &[&str]) -> AnalysisCacheKey {
+        let moves: Vec<String> = moves.iter().map(|m| m.to_string()).collect();
+        AnalysisCacheKey::new(
+            "stockfish",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &moves,
+            1,
+        )
+        .unwrap()
+    }
+
+    fn line(depth: u32, cp: i32, san: &[&str]) -> BestMoves {
+        BestMoves {
+            depth,
+            nodes: depth * 1000,
+            score: Score {
+                value: ScoreValue::Cp(cp),
+                ..Default::default()
+            },
+            san_moves: san.iter().map(|m| m.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn transpositions_share_a_key() {
+        assert_eq!(
+            key(&["g1f3", "g8f6", "b1c3"]),
+            key(&["b1c3", "g8f6", "g1f3"])
+        );
+        assert_ne!(key(&["e2e4"]), key(&["e2e3"]));
+    }
+
+    #[test]
+    fn keeps_the_deepest_analysis() {
+        let mut cache = AnalysisCache::open(Path::new(":memory:")).unwrap();
+        let key = key(&["e2e4"]);
+        assert!(cache.get(&key).unwrap().is_none());
+
+        cache
+            .store(&key, &[line(20, 30, &["e5"])], Duration::from_millis(500))
+            .unwrap();
+        cache
+            .store(&key, &[line(12, 50, &["c5"])], Duration::from_millis(100))
+            .unwrap();
+
+        let cached = cache.get(&key).unwrap().unwrap();
+        assert_eq!(cached.depth, 20);
+        assert_eq!(cached.time, 500);
+
+        // An equally deep but shorter search doesn't lower the effort
+        cache
+            .store(&key, &[line(20, 35, &["e5"])], Duration::from_millis(200))
+            .unwrap();
+        let cached = cache.get(&key).unwrap().unwrap();
+        assert_eq!(cached.time, 500);
+        assert_eq!(cached.lines[0].san_moves, vec!["e5"]);
+        assert!(cached.satisfies(&GoMode::Depth(18)));
+        assert!(!cached.satisfies(&GoMode::Depth(22)));
+        assert!(cached.satisfies(&GoMode::Time(500)));
+        assert!(!cached.satisfies(&GoMode::Infinite));
+
+        let mut other = key.clone();
+        other.multipv = 2;
+        assert!(cache.get(&other).unwrap().is_none());
+    }
+
+    #[test]
+    fn clear_and_export() {
+        let mut cache = AnalysisCache::open(Path::new(":memory:")).unwrap();
+        cache
+            .store(
+                &key(&["e2e4"]),
+                &[line(20, 30, &["e5", "Nf3"])],
+                Duration::ZERO,
+            )
+            .unwrap();
+        let mut other = key(&[]);
+        other.engine = "lc0".to_string();
+        cache
+            .store(&other, &[line(10, 20, &["d4"])], Duration::ZERO)
+            .unwrap();
+
+        let mut epd = Vec::new();
+        assert_eq!(cache.export(Some("stockfish"), &mut epd).unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(epd).unwrap(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - acd 20; acn 20000; ce -30; pv e5 Nf3; c0 \"stockfish\";\n"
+        );
+
+        assert_eq!(cache.clear(Some("lc0")).unwrap(), 1);
+        assert!(cache.get(&other).unwrap().is_none());
+        assert_eq!(cache.clear(None).unwrap(), 1);
+    }
+}
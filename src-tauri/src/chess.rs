@@ -1,7 +1,6 @@
 use std::{
     fmt::Display,
-    path::PathBuf,
-    process::Stdio,
+    path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -13,14 +12,17 @@ use log::{debug, error, info, trace, warn};
 use nonzero_ext::*;
 use serde::{Deserialize, Serialize};
 use shakmaty::{
-    fen::Fen, san::SanPlus, uci::UciMove, ByColor, CastlingMode, Chess, Color, EnPassantMode,
-    Position, Role,
+    fen::{Epd, Fen},
+    san::SanPlus,
+    uci::UciMove,
+    variant::VariantPosition,
+    ByColor, CastlingMode, Chess, Color, EnPassantMode, Move, Position, Role,
 };
 use specta::Type;
 use tauri_specta::Event;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
-    process::{Child, ChildStdin, ChildStdout, Command},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::Child,
     sync::Mutex,
     time::timeout,
 };
@@ -31,8 +33,15 @@ use vampirc_uci::{
 };
 
 use crate::{
+    analysis_cache::{self, CachedAnalysis},
     db::{is_position_in_db, GameQueryJs, PositionQueryJs},
+    engine_health::{self, EngineCrashed},
+    engine_registry,
+    engine_transport::{EngineIo, EngineReader, EngineTransport, EngineWriter},
     error::Error,
+    tablebase,
+    variant::GameVariant,
+    wdl::Wdl,
     AppState,
 };
 
@@ -43,9 +52,10 @@ const MIN_EVENT_INTERVAL: Duration = Duration::from_millis(50); // Target ≤50m
 const MAX_EVENT_INTERVAL: Duration = Duration::from_millis(150); // Increased base timeout threshold
 const ENGINE_STOP_DELAY: Duration = Duration::from_millis(50);
 const EVENTS_PER_SECOND: u32 = 15; // Reduced from 20 to prevent spam
-
-#[cfg(target_os = "windows")]
-const CREATE_NO_WINDOW: u32 = 0x08000000;
+const CONNECT_ATTEMPTS: u32 = 3;
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+pub(crate) const MAX_RECONNECTS: u32 = 3;
+const EXIT_STATUS_TIMEOUT: Duration = Duration::from_millis(200);
 
 #[derive(Debug, Clone, Serialize, Type)]
 #[serde(tag = "type", content = "value", rename_all = "camelCase")]
@@ -54,9 +64,25 @@ pub enum EngineLog {
     Engine(String),
 }
 
-#[derive(Debug)]
+/// What an engine advertised in its reply to `uci`.
+#[derive(Debug, Clone)]
+struct EngineCapabilities {
+    /// Largest MultiPV the engine supports, 1 without the option
+    max_multipv: u16,
+    /// Values of the `UCI_Variant` option, empty for engines that only play chess
+    variants: Vec<String>,
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
 pub struct EngineProcess {
-    stdin: ChildStdin,
+    transport: EngineTransport,
+    #[derivative(Debug = "ignore")]
+    stdin: EngineWriter,
+    /// The local process, if any: the engine itself or the `ssh` client
+    child: Option<Child>,
+    /// Set once the engine was told to quit, so its exit isn't taken for a crash
+    killed: bool,
     last_depth: u32,
     best_moves: Vec<BestMoves>,
     last_best_moves: Vec<BestMoves>,
@@ -66,42 +92,27 @@ pub struct EngineProcess {
     go_mode: GoMode,
     running: bool,
     real_multipv: u16,
+    /// Moves sent with `searchmoves`, empty to search them all
+    search_moves: Vec<String>,
+    capabilities: EngineCapabilities,
     logs: Vec<EngineLog>,
     start: Instant,
 }
 
 impl EngineProcess {
-    async fn new(path: PathBuf) -> Result<(Self, Lines<BufReader<ChildStdout>>), Error> {
-        info!("Initializing engine from path: {:?}", path);
-        
-        let mut child = Self::spawn_engine_process(&path)?;
-        let (mut stdin, mut lines) = Self::get_io_handles(&mut child)?;
-        
-        let mut logs = Vec::new();
-        
-        // Initialize UCI communication with timeout
-        match timeout(ENGINE_INIT_TIMEOUT, Self::initialize_uci(&mut stdin, &mut lines, &mut logs)).await {
-            Ok(Ok(())) => {
-                info!("Engine initialized successfully: {:?}", path);
-            }
-            Ok(Err(e)) => {
-                error!("Failed to initialize engine {:?}: {}", path, e);
-                let _ = child.kill().await;
-                return Err(e);
-            }
-            Err(_) => {
-                error!("Engine initialization timeout: {:?}", path);
-                let _ = child.kill().await;
-                return Err(Error::EngineTimeout);
-            }
-        }
+    pub(crate) async fn new(path: PathBuf) -> Result<(Self, EngineReader), Error> {
+        let transport = EngineTransport::parse(&path)?;
+        info!("Initializing engine: {}", transport);
 
-        // Spawn stderr handler
-        Self::spawn_stderr_handler(child.stderr.take());
+        let mut logs = Vec::new();
+        let (stdin, lines, capabilities, child) = Self::connect(&transport, &mut logs).await?;
 
         Ok((
             Self {
+                transport,
                 stdin,
+                child,
+                killed: false,
                 last_depth: 0,
                 best_moves: Vec::new(),
                 last_best_moves: Vec::new(),
@@ -110,6 +121,8 @@ impl EngineProcess {
                 logs,
                 options: EngineOptions::default(),
                 real_multipv: 0,
+                search_moves: Vec::new(),
+                capabilities,
                 go_mode: GoMode::Infinite,
                 running: false,
                 start: Instant::now(),
@@ -118,61 +131,129 @@ impl EngineProcess {
         ))
     }
 
-    fn spawn_engine_process(path: &PathBuf) -> Result<Child, Error> {
-        debug!("Spawning engine process: {:?}", path);
-        
-        let mut command = Command::new(path);
-        command.current_dir(path.parent().unwrap_or_else(|| std::path::Path::new(".")));
-        command
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .env("TERM", "dumb"); // Prevent terminal feature usage
-
-        #[cfg(target_os = "windows")]
-        command.creation_flags(CREATE_NO_WINDOW);
-
-        let child = command.spawn()
-            .map_err(|e| {
-                error!("Failed to spawn engine process {:?}: {}", path, e);
-                Error::Io(e)
-            })?;
+    /// Opens the transport and waits for the engine to be ready.
+    ///
+    /// Remote engines get a few attempts, as servers can be briefly unreachable.
+    async fn connect(
+        transport: &EngineTransport,
+        logs: &mut Vec<EngineLog>,
+    ) -> Result<(EngineWriter, EngineReader, EngineCapabilities, Option<Child>), Error> {
+        let attempts = if transport.is_remote() { CONNECT_ATTEMPTS } else { 1 };
+        let mut delay = RECONNECT_DELAY;
+        let mut attempt = 1;
+        loop {
+            match Self::try_connect(transport, logs).await {
+                Ok(io) => return Ok(io),
+                Err(e) if attempt < attempts => {
+                    warn!(
+                        "Connection attempt {}/{} to {} failed: {}",
+                        attempt, attempts, transport, e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn try_connect(
+        transport: &EngineTransport,
+        logs: &mut Vec<EngineLog>,
+    ) -> Result<(EngineWriter, EngineReader, EngineCapabilities, Option<Child>), Error> {
+        let EngineIo {
+            writer: mut stdin,
+            reader: mut lines,
+            mut child,
+        } = transport.open().await?;
+
+        // Initialize UCI communication with timeout
+        let capabilities = match timeout(ENGINE_INIT_TIMEOUT, Self::initialize_uci(&mut stdin, &mut lines, logs)).await {
+            Ok(Ok(capabilities)) => {
+                info!("Engine initialized successfully: {}", transport);
+                capabilities
+            }
+            Ok(Err(e)) => {
+                error!("Failed to initialize engine {}: {}", transport, e);
+                if let Some(child) = child.as_mut() {
+                    let _ = child.kill().await;
+                }
+                return Err(e);
+            }
+            Err(_) => {
+                error!("Engine initialization timeout: {}", transport);
+                if let Some(child) = child.as_mut() {
+                    let _ = child.kill().await;
+                }
+                return Err(Error::EngineTimeout);
+            }
+        };
+
+        // Spawn stderr handler
+        if let Some(child) = child.as_mut() {
+            Self::spawn_stderr_handler(child.stderr.take());
+        }
 
-        debug!("Engine process spawned successfully");
-        Ok(child)
+        Ok((stdin, lines, capabilities, child))
     }
 
-    fn get_io_handles(child: &mut Child) -> Result<(ChildStdin, Lines<BufReader<ChildStdout>>), Error> {
-        let stdin = child.stdin.take().ok_or_else(|| {
-            error!("Failed to get stdin handle from engine process");
-            Error::NoStdin
-        })?;
-        
-        let stdout = child.stdout.take().ok_or_else(|| {
-            error!("Failed to get stdout handle from engine process");
-            Error::NoStdout
-        })?;
-        
-        // Use a smaller buffer for more responsive reading
-        let reader = BufReader::with_capacity(1024, stdout);
-        let lines = reader.lines();
-        Ok((stdin, lines))
+    /// Starts a crashed engine again, or connects again to a remote one that
+    /// dropped the connection.
+    ///
+    /// The engine lost its state, so the options and position are sent again
+    /// and an interrupted search is restarted.
+    pub(crate) async fn reconnect(&mut self) -> Result<EngineReader, Error> {
+        info!("Reconnecting to engine: {}", self.transport);
+        let (stdin, lines, capabilities, child) = Self::connect(&self.transport, &mut self.logs).await?;
+        self.stdin = stdin;
+        self.capabilities = capabilities;
+        if let Some(mut old) = std::mem::replace(&mut self.child, child) {
+            let _ = old.start_kill();
+        }
+
+        let options = std::mem::take(&mut self.options);
+        self.set_options(options).await?;
+        if self.running {
+            let go_mode = self.go_mode.clone();
+            self.go(&go_mode).await?;
+        }
+        Ok(lines)
     }
 
     async fn initialize_uci(
-        stdin: &mut ChildStdin,
-        lines: &mut Lines<BufReader<ChildStdout>>,
+        stdin: &mut EngineWriter,
+        lines: &mut EngineReader,
         logs: &mut Vec<EngineLog>
-    ) -> Result<(), Error> {
+    ) -> Result<EngineCapabilities, Error> {
         debug!("Starting UCI initialization");
         
         // Send UCI command
         Self::send_command_with_log(stdin, "uci\n", logs).await?;
         
+        let mut capabilities = EngineCapabilities {
+            max_multipv: 1,
+            variants: Vec::new(),
+        };
         // Wait for uciok
         while let Some(line) = lines.next_line().await? {
             trace!("Engine response: {}", line);
             logs.push(EngineLog::Engine(line.clone()));
+
+            match parse_one(&line) {
+                UciMessage::Option(UciOptionConfig::Spin { name, max, .. })
+                    if name.eq_ignore_ascii_case("MultiPV") =>
+                {
+                    capabilities.max_multipv =
+                        max.map_or(u16::MAX, |max| max.clamp(1, u16::MAX as i64) as u16);
+                }
+                UciMessage::Option(UciOptionConfig::Combo { name, var, .. })
+                    if name.eq_ignore_ascii_case("UCI_Variant") =>
+                {
+                    capabilities.variants = var;
+                }
+                _ => {}
+            }
             
             if line == "uciok" {
                 debug!("Received uciok, sending isready");
@@ -185,7 +266,7 @@ impl EngineProcess {
                     
                     if ready_line == "readyok" {
                         debug!("Engine is ready");
-                        return Ok(());
+                        return Ok(capabilities);
                     }
                 }
                 break;
@@ -207,7 +288,7 @@ impl EngineProcess {
     }
 
     async fn send_command_with_log(
-        stdin: &mut ChildStdin,
+        stdin: &mut EngineWriter,
         command: &str,
         logs: &mut Vec<EngineLog>
     ) -> Result<(), Error> {
@@ -241,7 +322,7 @@ impl EngineProcess {
         Self::send_command_with_log(&mut self.stdin, &msg, &mut self.logs).await
     }
 
-    async fn set_options(&mut self, options: EngineOptions) -> Result<(), Error> {
+    pub(crate) async fn set_options(&mut self, options: EngineOptions) -> Result<(), Error> {
         debug!("Setting engine options for position: {}", options.fen);
         
         // Parse and validate position
@@ -251,7 +332,8 @@ impl EngineProcess {
                 e
             })?;
         
-        let mut pos: Chess = match fen.into_position(CastlingMode::Chess960) {
+        let variant = options.variant();
+        let mut pos = match VariantPosition::from_setup(variant.into(), fen.into_setup(), CastlingMode::Chess960) {
             Ok(p) => p,
             Err(e) => {
                 warn!("Position error, attempting to ignore extra material: {}", e);
@@ -276,11 +358,28 @@ impl EngineProcess {
             pos.play_unchecked(&mv);
         }
         
+        self.search_moves = restricted_moves(&pos, &options)?;
+        // The XBoard adapter has no way to pass the moves on
+        if !self.search_moves.is_empty() && self.transport.is_xboard() {
+            return Err(Error::SearchMovesUnsupported);
+        }
+        let candidates = if self.search_moves.is_empty() {
+            pos.legal_moves().len()
+        } else {
+            self.search_moves.len()
+        };
+
         // Calculate effective MultiPV
-        let multipv = self.calculate_multipv(&options, &pos);
+        let multipv = self.calculate_multipv(&options, candidates);
         self.real_multipv = multipv;
         
-        debug!("Calculated MultiPV: {} (legal moves: {})", multipv, pos.legal_moves().len());
+        debug!("Calculated MultiPV: {} (candidate moves: {})", multipv, candidates);
+
+        // Engines reset their board when the variant changes
+        let variant_changed = variant != self.options.variant();
+        if variant_changed {
+            self.set_variant(variant).await?;
+        }
 
         // Set options that have changed
         let changed_options: Vec<_> = options.extra_options.iter()
@@ -296,7 +395,7 @@ impl EngineProcess {
         }
 
         // Update position if needed
-        if options.fen != self.options.fen || options.moves != self.options.moves {
+        if variant_changed || options.fen != self.options.fen || options.moves != self.options.moves {
             self.set_position(&options.fen, &options.moves).await?;
         }
         
@@ -308,14 +407,65 @@ impl EngineProcess {
         Ok(())
     }
 
-    fn calculate_multipv(&self, options: &EngineOptions, pos: &Chess) -> u16 {
-        options
-            .extra_options
-            .iter()
-            .find(|x| x.name == "MultiPV")
-            .and_then(|x| x.value.parse().ok())
-            .unwrap_or(1)
-            .min(pos.legal_moves().len() as u16)
+    /// Selects the variant with `UCI_Variant`, using the name the engine advertised.
+    async fn set_variant(&mut self, variant: GameVariant) -> Result<(), Error> {
+        let name = self.capabilities.variants.iter().find(|name| {
+            variant
+                .uci_names()
+                .iter()
+                .any(|uci| name.eq_ignore_ascii_case(uci))
+        });
+        match name {
+            Some(name) => {
+                let name = name.clone();
+                self.set_option("UCI_Variant", name).await
+            }
+            // Engines without the option only play standard chess
+            None if variant.is_standard() => Ok(()),
+            None => {
+                error!("Engine {} doesn't play {}", self.transport, variant.pgn_name());
+                Err(Error::UnsupportedVariant(variant.pgn_name().to_string()))
+            }
+        }
+    }
+
+    fn calculate_multipv(&self, options: &EngineOptions, candidates: usize) -> u16 {
+        multipv_option(&options.extra_options)
+            .min(self.capabilities.max_multipv)
+            .min(candidates as u16)
+    }
+
+    /// Saves the last complete lines of the search to the analysis cache.
+    fn cache_results(&self, app: &tauri::AppHandle, engine: &str) {
+        if self.last_best_moves.is_empty() || !self.options.is_cacheable() {
+            return;
+        }
+        let multipv = multipv_option(&self.options.extra_options);
+        match AnalysisCacheKey::new(engine, &self.options.fen, &self.options.moves, multipv) {
+            Ok(key) => analysis_cache::store_analysis(
+                app,
+                &key,
+                &self.last_best_moves,
+                self.start.elapsed(),
+            ),
+            Err(e) => warn!("Failed to build analysis cache key: {}", e),
+        }
+    }
+
+    /// Starts from a cached analysis, only deeper results of the engine are reported.
+    fn seed_from_cache(
+        &mut self,
+        app: &tauri::AppHandle,
+        id: &str,
+        tab: &str,
+        go_mode: &GoMode,
+        cached: CachedAnalysis,
+    ) {
+        debug!("Resuming from cached analysis at depth {}", cached.depth);
+        let progress = calculate_progress(go_mode, cached.depth, cached.nodes, Duration::ZERO);
+        self.last_depth = cached.depth;
+        self.last_progress = progress as f32;
+        self.last_best_moves = emit_cached(app, id, tab, &self.options, cached.lines, progress);
     }
 
     fn reset_analysis_state(&mut self) {
@@ -339,9 +489,9 @@ impl EngineProcess {
         Ok(())
     }
 
-    async fn go(&mut self, mode: &GoMode) -> Result<(), Error> {
+    pub(crate) async fn go(&mut self, mode: &GoMode) -> Result<(), Error> {
         self.go_mode = mode.clone();
-        let msg = self.format_go_command(mode);
+        let msg = format_go_command(mode, &self.search_moves);
         
         info!("Starting engine analysis: {}", msg.trim());
         Self::send_command_with_log(&mut self.stdin, &msg, &mut self.logs).await?;
@@ -352,19 +502,36 @@ impl EngineProcess {
         Ok(())
     }
 
-    fn format_go_command(&self, mode: &GoMode) -> String {
-        match mode {
-            GoMode::Depth(depth) => format!("go depth {depth}\n"),
-            GoMode::Time(time) => format!("go movetime {time}\n"),
-            GoMode::Nodes(nodes) => format!("go nodes {nodes}\n"),
-            GoMode::PlayersTime(PlayersTime { white, black, winc, binc }) => {
-                format!("go wtime {white} btime {black} winc {winc} binc {binc} movetime 1000\n")
-            }
-            GoMode::Infinite => "go infinite\n".to_string(),
-        }
+    /// Searches using the players' clocks, as when playing a game.
+    ///
+    /// Unlike `GoMode::PlayersTime` analysis, the search isn't capped to a fixed move time.
+    pub(crate) async fn go_with_clock(&mut self, time: &PlayersTime) -> Result<(), Error> {
+        self.go_mode = GoMode::PlayersTime(time.clone());
+        let msg = format!(
+            "go wtime {} btime {} winc {} binc {}\n",
+            time.white, time.black, time.winc, time.binc
+        );
+
+        debug!("Starting engine search on the clock: {}", msg.trim());
+        Self::send_command_with_log(&mut self.stdin, &msg, &mut self.logs).await?;
+
+        self.running = true;
+        self.start = Instant::now();
+        self.last_event_sent = None;
+        Ok(())
     }
 
-    async fn stop(&mut self) -> Result<(), Error> {
+    /// Tells the engine that the next search belongs to a new game.
+    pub(crate) async fn new_game(&mut self) -> Result<(), Error> {
+        Self::send_command_with_log(&mut self.stdin, "ucinewgame\n", &mut self.logs).await?;
+        // Force the position to be sent again on the next search
+        self.options.fen.clear();
+        self.options.moves.clear();
+        self.reset_analysis_state();
+        Ok(())
+    }
+
+    pub(crate) async fn stop(&mut self) -> Result<(), Error> {
         if self.running {
             info!("Stopping engine analysis");
             Self::send_command_with_log(&mut self.stdin, "stop\n", &mut self.logs).await?;
@@ -373,36 +540,86 @@ impl EngineProcess {
         Ok(())
     }
 
-    async fn kill(&mut self) -> Result<(), Error> {
+    pub(crate) fn pid(&self) -> Option<u32> {
+        self.child.as_ref().and_then(Child::id)
+    }
+
+    pub(crate) fn options(&self) -> &EngineOptions {
+        &self.options
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Exit code of a local process that stopped, `None` if it's still running
+    /// or was killed by a signal.
+    async fn exit_code(&mut self) -> Option<i32> {
+        let child = self.child.as_mut()?;
+        let status = timeout(EXIT_STATUS_TIMEOUT, child.wait()).await.ok()?.ok()?;
+        status.code()
+    }
+
+    pub(crate) async fn kill(&mut self) -> Result<(), Error> {
         info!("Terminating engine process");
+        self.killed = true;
         Self::send_command_with_log(&mut self.stdin, "quit\n", &mut self.logs).await?;
         self.running = false;
         Ok(())
     }
 }
 
-// Rest of the structs remain the same...
+/// Identifies an analysis in the persistent analysis cache.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AnalysisCacheKey {
-    pub tab: String,
+    /// Position without the move counters, so transpositions share their analysis
     pub fen: String,
     pub engine: String,
     pub multipv: u16,
 }
 
-#[derive(Clone, Serialize, Debug, Derivative, Type)]
+impl AnalysisCacheKey {
+    pub fn new(engine: &str, fen: &str, moves: &[String], multipv: u16) -> Result<Self, Error> {
+        let fen: Fen = fen.parse()?;
+        let mut pos: Chess = match fen.into_position(CastlingMode::Chess960) {
+            Ok(p) => p,
+            Err(e) => e.ignore_too_much_material()?,
+        };
+        for move_str in moves {
+            let uci = UciMove::from_ascii(move_str.as_bytes())?;
+            let mv = uci.to_move(&pos)?;
+            pos.play_unchecked(&mv);
+        }
+
+        Ok(Self {
+            fen: Epd::from_position(pos, EnPassantMode::Legal).to_string(),
+            engine: engine.to_string(),
+            multipv,
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Derivative, Type)]
 #[derivative(Default)]
 pub struct BestMoves {
-    nodes: u32,
-    depth: u32,
-    score: Score,
+    pub(crate) nodes: u32,
+    pub(crate) depth: u32,
+    pub(crate) score: Score,
     #[serde(rename = "uciMoves")]
-    uci_moves: Vec<String>,
+    pub(crate) uci_moves: Vec<String>,
     #[serde(rename = "sanMoves")]
-    san_moves: Vec<String>,
+    pub(crate) san_moves: Vec<String>,
     #[derivative(Default(value = "1"))]
-    multipv: u16,
+    pub(crate) multipv: u16,
     nps: u32,
+    /// The score is the exact outcome from the endgame tablebases
+    #[serde(default)]
+    pub(crate) tablebase: bool,
+    /// Outcome probabilities reported by the engine, or from the win rate model
+    #[serde(default)]
+    pub(crate) wdl: Wdl,
+    #[serde(default, rename = "expectedScore")]
+    pub(crate) expected_score: f64,
 }
 
 #[derive(Serialize, Debug, Clone, Type, Event)]
@@ -416,6 +633,55 @@ pub struct BestMovesPayload {
     pub progress: f64,
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, Type, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SprtSettings {
+    /// Elo difference of the null hypothesis
+    pub elo0: f64,
+    /// Elo difference of the alternative hypothesis
+    pub elo1: f64,
+    /// Probability of accepting H1 when H0 is true
+    pub alpha: f64,
+    /// Probability of accepting H0 when H1 is true
+    pub beta: f64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Type, PartialEq, Eq)]
+pub enum SprtResult {
+    AcceptH0,
+    AcceptH1,
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SprtStatus {
+    pub llr: f64,
+    pub lower_bound: f64,
+    pub upper_bound: f64,
+    pub result: Option<SprtResult>,
+}
+
+/// Results of an engine against its opponents, from that engine's point of view.
+#[derive(Serialize, Debug, Clone, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchStatistics {
+    pub id: String,
+    pub engine: String,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    /// Game pairs scoring 0, 0.5, 1, 1.5 and 2 points
+    pub pentanomial: [u32; 5],
+    pub score: f64,
+    /// `None` while the engine has won or lost every game
+    pub elo: Option<f64>,
+    /// Half width of the 95% confidence interval
+    pub elo_error: Option<f64>,
+    /// Likelihood of superiority
+    pub los: f64,
+    pub sprt: Option<SprtStatus>,
+}
+
 fn invert_score(score: Score) -> Score {
     let new_value = match score.value {
         ScoreValue::Cp(x) => ScoreValue::Cp(-x),
@@ -429,10 +695,12 @@ fn invert_score(score: Score) -> Score {
     }
 }
 
-fn parse_uci_info(
+pub(crate) fn parse_uci_info(
     attrs: Vec<UciInfoAttribute>,
     fen: &Fen,
     moves: &[String],
+    variant: GameVariant,
+    elo: Option<u32>,
 ) -> Result<BestMoves, Error> {
     trace!("Parsing UCI info attributes: {} attributes", attrs.len());
     
@@ -441,7 +709,8 @@ fn parse_uci_info(
     let mut has_multipv = false;
     let mut has_pv = false;
 
-    let mut pos: Chess = match fen.clone().into_position(CastlingMode::Chess960) {
+    let setup = fen.clone().into_setup();
+    let mut pos = match VariantPosition::from_setup(variant.into(), setup, CastlingMode::Chess960) {
         Ok(p) => p,
         Err(e) => {
             warn!("Position error in parse_uci_info, attempting to ignore extra material");
@@ -461,6 +730,7 @@ fn parse_uci_info(
     }
     
     let turn = pos.turn();
+    let ply = (pos.fullmoves().get() - 1) * 2 + (turn == Color::Black) as u32;
 
     for attr in attrs {
         match attr {
@@ -511,6 +781,13 @@ fn parse_uci_info(
         best_moves.score = invert_score(best_moves.score);
     }
 
+    best_moves.wdl = best_moves
+        .score
+        .wdl
+        .and_then(|(w, d, l)| Wdl::from_counts(w as f64, d as f64, l as f64))
+        .unwrap_or_else(|| Wdl::from_score(&best_moves.score.value, ply, elo));
+    best_moves.expected_score = best_moves.wdl.expected_score();
+
     debug!("Successfully parsed UCI info: depth={} (has_depth={}), multipv={} (has_multipv={}), moves={}", 
            best_moves.depth, has_depth, best_moves.multipv, has_multipv, best_moves.san_moves.len());
     
@@ -621,8 +898,25 @@ pub async fn get_best_moves(
     info!("Getting best moves: id={}, engine={}, tab={}", id, engine, tab);
     debug!("Analysis options: FEN={}, moves={}", options.fen, options.moves.len());
 
+    let mut options = engine_registry::resolve_preset(&app, &engine, options).await?;
+    engine_health::apply_limits(&state, &key, &mut options).await;
+
+    let cache_key = AnalysisCacheKey::new(
+        &engine,
+        &options.fen,
+        &options.moves,
+        multipv_option(&options.extra_options),
+    )
+    .ok()
+    .filter(|_| options.is_cacheable());
+    let cached = match cache_key {
+        Some(key) => analysis_cache::cached_analysis(&app, &key).await,
+        None => None,
+    };
+
     // Check if engine is already running with same parameters
-    if let Some(process_guard) = state.engine_processes.get(&key) {
+    let existing = state.engine_processes.get(&key).map(|p| p.clone());
+    if let Some(process_guard) = &existing {
         let mut process = process_guard.lock().await;
         
         if options == process.options && go_mode == process.go_mode && process.running {
@@ -631,11 +925,23 @@ pub async fn get_best_moves(
         }
         
         info!("Stopping existing engine for parameter change");
+        process.cache_results(&app, &engine);
         if let Err(e) = process.stop().await {
             warn!("Failed to stop existing engine: {}", e);
         }
-        
-        drop(process); // Release lock before delay
+    }
+
+    // Positions already analyzed deep enough don't need the engine
+    let cached = match cached {
+        Some(cached) if cached.satisfies(&go_mode) => {
+            debug!("Using cached analysis at depth {}", cached.depth);
+            let lines = emit_cached(&app, &id, &tab, &options, cached.lines, 100.0);
+            return Ok(Some((100.0, lines)));
+        }
+        cached => cached,
+    };
+
+    if let Some(process_guard) = existing {
         tokio::time::sleep(ENGINE_STOP_DELAY).await;
         
         let mut process = process_guard.lock().await;
@@ -643,6 +949,9 @@ pub async fn get_best_moves(
             error!("Failed to set engine options: {}", e);
             return Err(e);
         }
+        if let Some(cached) = cached {
+            process.seed_from_cache(&app, &id, &tab, &go_mode, cached);
+        }
         
         if let Err(e) = process.go(&go_mode).await {
             error!("Failed to start engine analysis: {}", e);
@@ -666,6 +975,9 @@ pub async fn get_best_moves(
         error!("Failed to set initial options: {}", e);
         return Err(e);
     }
+    if let Some(cached) = cached {
+        process.seed_from_cache(&app, &id, &tab, &go_mode, cached);
+    }
 
     if let Err(e) = process.go(&go_mode).await {
         error!("Failed to start initial analysis: {}", e);
@@ -690,9 +1002,32 @@ pub async fn get_best_moves(
     Ok(None)
 }
 
+/// Sends cached lines to the frontend as if the engine had just found them.
+fn emit_cached(
+    app: &tauri::AppHandle,
+    id: &str,
+    tab: &str,
+    options: &EngineOptions,
+    lines: Vec<BestMoves>,
+    progress: f64,
+) -> Vec<BestMoves> {
+    let payload = BestMovesPayload {
+        best_lines: lines,
+        engine: id.to_string(),
+        tab: tab.to_string(),
+        fen: options.fen.clone(),
+        moves: options.moves.clone(),
+        progress,
+    };
+    if let Err(e) = payload.emit(app) {
+        warn!("Failed to emit cached analysis: {}", e);
+    }
+    payload.best_lines
+}
+
 async fn engine_communication_loop(
     process: Arc<Mutex<EngineProcess>>,
-    mut reader: Lines<BufReader<ChildStdout>>,
+    mut reader: EngineReader,
     key: (String, String),
     id: String,
     tab: String,
@@ -706,6 +1041,7 @@ async fn engine_communication_loop(
     let mut first_result_sent = false;
     let mut timeout_count = 0;
     let mut last_timeout_emit = Instant::now();
+    let mut reconnects = 0;
 
     let result = async {
         loop {
@@ -713,8 +1049,9 @@ async fn engine_communication_loop(
                 Ok(Ok(Some(line))) => {
                     debug!("Raw engine output: {}", line);
                     
-                    // Reset timeout counter on successful read
+                    // Reset timeout and reconnection counters on successful read
                     timeout_count = 0;
+                    reconnects = 0;
                     
                     let mut proc = process.lock().await;
                     proc.logs.push(EngineLog::Engine(line.clone()));
@@ -760,6 +1097,7 @@ async fn engine_communication_loop(
                             }
 
                             info!("Analysis complete, cleaning up engine process");
+                            proc.cache_results(&app, &key.1);
                             
                             proc.last_progress = 100.0;
                             proc.running = false;
@@ -771,11 +1109,17 @@ async fn engine_communication_loop(
                 }
                 Ok(Ok(None)) => {
                     debug!("Engine closed stdout");
-                    break;
+                    match try_restart(&process, &mut reconnects, &id, &tab, &app).await {
+                        Some(new_reader) => reader = new_reader,
+                        None => break,
+                    }
                 }
                 Ok(Err(e)) => {
                     error!("Error reading from engine stdout: {}", e);
-                    break;
+                    match try_restart(&process, &mut reconnects, &id, &tab, &app).await {
+                        Some(new_reader) => reader = new_reader,
+                        None => break,
+                    }
                 }
                 Err(_) => {
                     timeout_count += 1;
@@ -830,6 +1174,58 @@ async fn engine_communication_loop(
     engine_processes.remove(&key);
 }
 
+/// Restarts an engine that crashed or lost its connection during a search,
+/// restoring its options and position, and tells the frontend about it.
+///
+/// Idle engines aren't restarted, the next search starts a new process.
+async fn try_restart(
+    process: &Mutex<EngineProcess>,
+    reconnects: &mut u32,
+    id: &str,
+    tab: &str,
+    app: &tauri::AppHandle,
+) -> Option<EngineReader> {
+    let mut proc = process.lock().await;
+    let (exit_code, reader) = restart_stopped(&mut proc, reconnects).await?;
+
+    let event = EngineCrashed {
+        engine: id.to_string(),
+        tab: tab.to_string(),
+        exit_code,
+        restarted: reader.is_some(),
+    };
+    if let Err(e) = event.emit(app) {
+        warn!("Failed to emit engine crash: {}", e);
+    }
+    reader
+}
+
+/// Returns the exit code of an engine that stopped without being killed, and
+/// the reader of the new process if it was searching and could be restarted.
+pub(crate) async fn restart_stopped(
+    proc: &mut EngineProcess,
+    reconnects: &mut u32,
+) -> Option<(Option<i32>, Option<EngineReader>)> {
+    if proc.killed {
+        return None;
+    }
+    let exit_code = proc.exit_code().await;
+    warn!(
+        "Engine {} stopped unexpectedly (exit code {:?}, restart {}/{})",
+        proc.transport, exit_code, reconnects, MAX_RECONNECTS
+    );
+
+    let mut reader = None;
+    if proc.running && *reconnects < MAX_RECONNECTS {
+        *reconnects += 1;
+        match proc.reconnect().await {
+            Ok(new_reader) => reader = Some(new_reader),
+            Err(e) => error!("Failed to restart engine {}: {}", proc.transport, e),
+        }
+    }
+    Some((exit_code, reader))
+}
+
 async fn handle_info_message(
     proc: &mut EngineProcess,
     attrs: Vec<UciInfoAttribute>,
@@ -845,7 +1241,13 @@ async fn handle_info_message(
     tab: &str,
     app: &tauri::AppHandle,
 ) -> Result<(), Error> {
-    let best_moves = parse_uci_info(attrs, &proc.options.fen.parse()?, &proc.options.moves)?;
+    let best_moves = parse_uci_info(
+        attrs,
+        &proc.options.fen.parse()?,
+        &proc.options.moves,
+        proc.options.variant(),
+        limited_elo(&proc.options.extra_options),
+    )?;
     
     let multipv = best_moves.multipv;
     let cur_depth = best_moves.depth;
@@ -864,6 +1266,16 @@ async fn handle_info_message(
                    all_same_depth, cur_depth, proc.last_depth);
             
             if all_same_depth && cur_depth >= proc.last_depth {
+                // Syzygy tables only know the standard rules
+                if proc.options.variant().is_standard() {
+                    tablebase::rescore_with_app_tables(
+                        app,
+                        &proc.options.fen,
+                        &proc.options.moves,
+                        &mut proc.best_moves,
+                    )
+                    .await;
+                }
                 let progress = calculate_progress(&proc.go_mode, cur_depth, cur_nodes, proc.start.elapsed());
                 
                 let payload = BestMovesPayload {
@@ -931,6 +1343,10 @@ fn calculate_progress(go_mode: &GoMode, depth: u32, nodes: u32, elapsed: Duratio
         GoMode::Nodes(target_nodes) => {
             (nodes as f64 / *target_nodes as f64) * 100.0
         }
+        GoMode::Mate(moves) => {
+            // The search ends early when it finds the mate
+            (depth as f64 / (2 * moves).max(1) as f64).min(0.99) * 100.0
+        }
         GoMode::PlayersTime(_) => {
             (depth as f64 / 20.0).min(0.99) * 100.0 // Assume ~20 depth target
         }
@@ -950,12 +1366,88 @@ pub struct EngineOptions {
     pub fen: String,
     pub moves: Vec<String>,
     pub extra_options: Vec<EngineOption>,
+    /// Standard chess if missing
+    #[serde(default)]
+    #[specta(optional)]
+    pub variant: Option<GameVariant>,
+    /// Name of a preset of the engine, applied over `extra_options`
+    #[serde(default)]
+    #[specta(optional)]
+    pub preset: Option<String>,
+    /// Only these moves are searched, in UCI notation
+    #[serde(default)]
+    #[specta(optional)]
+    pub search_moves: Vec<String>,
+    /// Moves left out of the search, to find the best alternative to them
+    #[serde(default)]
+    #[specta(optional)]
+    pub exclude_moves: Vec<String>,
 }
 
-#[derive(Deserialize, Debug, Clone, Type, PartialEq, Eq)]
+impl EngineOptions {
+    pub fn variant(&self) -> GameVariant {
+        self.variant.unwrap_or_default()
+    }
+
+    /// Whether the analysis can go to the analysis cache, which only holds
+    /// unrestricted searches of standard chess positions.
+    pub fn is_cacheable(&self) -> bool {
+        self.variant().is_standard() && self.search_moves.is_empty() && self.exclude_moves.is_empty()
+    }
+}
+
+fn format_go_command(mode: &GoMode, search_moves: &[String]) -> String {
+    let mut command = match mode {
+        GoMode::Depth(depth) => format!("go depth {depth}"),
+        GoMode::Time(time) => format!("go movetime {time}"),
+        GoMode::Nodes(nodes) => format!("go nodes {nodes}"),
+        GoMode::Mate(moves) => format!("go mate {moves}"),
+        GoMode::PlayersTime(PlayersTime { white, black, winc, binc }) => {
+            format!("go wtime {white} btime {black} winc {winc} binc {binc} movetime 1000")
+        }
+        GoMode::Infinite => "go infinite".to_string(),
+    };
+    if !search_moves.is_empty() {
+        command.push_str(" searchmoves ");
+        command.push_str(&search_moves.join(" "));
+    }
+    command.push('\n');
+    command
+}
+
+/// Moves to send with `searchmoves`, empty when the search isn't restricted.
+fn restricted_moves<P: Position>(pos: &P, options: &EngineOptions) -> Result<Vec<String>, Error> {
+    if options.search_moves.is_empty() && options.exclude_moves.is_empty() {
+        return Ok(Vec::new());
+    }
+    let parse = |uci: &String| -> Result<Move, Error> {
+        Ok(UciMove::from_ascii(uci.as_bytes())?.to_move(pos)?)
+    };
+    let excluded = options.exclude_moves.iter().map(parse).collect::<Result<Vec<_>, _>>()?;
+    let candidates: Vec<Move> = if options.search_moves.is_empty() {
+        pos.legal_moves().into_iter().collect()
+    } else {
+        options.search_moves.iter().map(parse).collect::<Result<_, _>>()?
+    };
+
+    // Moves are sent in the notation the engine was told to use
+    let chess960 = options.extra_options.iter()
+        .any(|x| x.name == "UCI_Chess960" && x.value == "true");
+    let mode = if chess960 { CastlingMode::Chess960 } else { CastlingMode::Standard };
+    let moves: Vec<String> = candidates.iter()
+        .filter(|m| !excluded.contains(*m))
+        .map(|m| m.to_uci(mode).to_string())
+        .collect();
+    if moves.is_empty() {
+        return Err(Error::NoMovesFound);
+    }
+    Ok(moves)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Type, PartialEq, Eq)]
 pub struct EngineOption {
-    name: String,
-    value: String,
+    pub name: String,
+    pub value: String,
 }
 
 #[derive(Deserialize, Debug, Clone, Type, PartialEq, Eq)]
@@ -965,22 +1457,24 @@ pub enum GoMode {
     Depth(u32),
     Time(u32),
     Nodes(u32),
+    /// Looks for a mate in the given number of moves
+    Mate(u32),
     Infinite,
 }
 
 #[derive(Deserialize, Debug, Clone, Type, PartialEq, Eq)]
 pub struct PlayersTime {
-    white: u32,
-    black: u32,
-    winc: u32,
-    binc: u32,
+    pub white: u32,
+    pub black: u32,
+    pub winc: u32,
+    pub binc: u32,
 }
 
 #[derive(Serialize, Debug, Default, Type)]
 pub struct MoveAnalysis {
-    best: Vec<BestMoves>,
-    novelty: bool,
-    is_sacrifice: bool,
+    pub(crate) best: Vec<BestMoves>,
+    pub(crate) novelty: bool,
+    pub(crate) is_sacrifice: bool,
 }
 
 #[derive(Deserialize, Debug, Default, Type)]
@@ -1007,7 +1501,7 @@ pub async fn analyze_game(
     engine: String,
     go_mode: GoMode,
     options: AnalysisOptions,
-    uci_options: Vec<EngineOption>,
+    mut uci_options: Vec<EngineOption>,
     state: tauri::State<'_, AppState>,
     app: tauri::AppHandle,
 ) -> Result<Vec<MoveAnalysis>, Error> {
@@ -1018,6 +1512,9 @@ pub async fn analyze_game(
     let path = PathBuf::from(&engine);
     let mut analysis: Vec<MoveAnalysis> = Vec::new();
 
+    let key = (id.clone(), engine.clone());
+    let _reservation = engine_health::reserve(&state, key, &mut uci_options).await;
+
     let (mut proc, mut reader) = match EngineProcess::new(path.clone()).await {
         Ok((p, r)) => (p, r),
         Err(e) => {
@@ -1066,31 +1563,58 @@ pub async fn analyze_game(
         let mut analysis_options = uci_options.clone();
         ensure_multipv_option(&mut analysis_options);
 
-        if let Err(e) = proc.set_options(EngineOptions {
-            fen: options.fen.clone(),
-            moves: moves.clone(),
-            extra_options: analysis_options,
-        }).await {
-            warn!("Failed to set options for position {}: {}", i, e);
-            continue;
-        }
-
-        if let Err(e) = proc.go(&go_mode).await {
-            warn!("Failed to start analysis for position {}: {}", i, e);
-            continue;
-        }
+        let cache_key = AnalysisCacheKey::new(
+            &engine,
+            &options.fen,
+            moves,
+            multipv_option(&analysis_options),
+        )
+        .map_err(|e| warn!("Failed to build analysis cache key for position {}: {}", i, e))
+        .ok();
+        let cached = match &cache_key {
+            Some(key) => analysis_cache::cached_analysis(&app, key)
+                .await
+                .filter(|cached| cached.satisfies(&go_mode)),
+            None => None,
+        };
 
-        // Analyze this position
         let mut current_analysis = MoveAnalysis::default();
-        match analyze_single_position(&mut proc, &mut reader).await {
-            Ok(best_moves) => {
-                current_analysis.best = best_moves;
-            }
-            Err(e) => {
-                warn!("Failed to analyze position {}: {}", i, e);
-                continue;
+        if let Some(cached) = cached {
+            debug!("Using cached analysis for position {}", i);
+            current_analysis.best = cached.lines;
+        } else {
+            let result = async {
+                proc.set_options(EngineOptions {
+                    fen: options.fen.clone(),
+                    moves: moves.clone(),
+                    extra_options: analysis_options,
+                    variant: None,
+                    preset: None,
+                    search_moves: Vec::new(),
+                    exclude_moves: Vec::new(),
+                }).await?;
+                proc.go(&go_mode).await?;
+                analyze_single_position(&mut proc, &mut reader).await
+            }.await;
+
+            // A failed position still gets an entry, so that the others stay at their ply
+            match result {
+                Ok(best_moves) => {
+                    if let Some(key) = &cache_key {
+                        analysis_cache::store_analysis(
+                            &app,
+                            key,
+                            &best_moves,
+                            proc.start.elapsed(),
+                        );
+                    }
+                    current_analysis.best = best_moves;
+                }
+                Err(e) => warn!("Failed to analyze position {}: {}", i, e),
             }
         }
+        tablebase::rescore_with_app_tables(&app, &options.fen, moves, &mut current_analysis.best)
+            .await;
 
         // Set sacrifice flag
         current_analysis.is_sacrifice = *is_sacrifice;
@@ -1171,6 +1695,23 @@ fn build_analysis_positions(
     Ok(positions)
 }
 
+/// Strength the engine plays at with `UCI_LimitStrength`, if it's limited.
+pub(crate) fn limited_elo(options: &[EngineOption]) -> Option<u32> {
+    if !options.iter().any(|x| x.name == "UCI_LimitStrength" && x.value == "true") {
+        return None;
+    }
+    options.iter().find(|x| x.name == "UCI_Elo").and_then(|x| x.value.parse().ok())
+}
+
+/// Number of lines requested with the `MultiPV` option.
+fn multipv_option(options: &[EngineOption]) -> u16 {
+    options
+        .iter()
+        .find(|x| x.name == "MultiPV")
+        .and_then(|x| x.value.parse().ok())
+        .unwrap_or(1)
+}
+
 fn ensure_multipv_option(options: &mut Vec<EngineOption>) {
     const DEFAULT_MULTIPV: &str = "2";
     
@@ -1186,7 +1727,7 @@ fn ensure_multipv_option(options: &mut Vec<EngineOption>) {
 
 async fn analyze_single_position(
     proc: &mut EngineProcess,
-    reader: &mut Lines<BufReader<ChildStdout>>
+    reader: &mut EngineReader
 ) -> Result<Vec<BestMoves>, Error> {
     trace!("Starting single position analysis");
     
@@ -1195,7 +1736,14 @@ async fn analyze_single_position(
         
         match parse_one(&line) {
             UciMessage::Info(attrs) => {
-                if let Ok(best_moves) = parse_uci_info(attrs, &proc.options.fen.parse()?, &proc.options.moves) {
+                let fen = proc.options.fen.parse()?;
+                if let Ok(best_moves) = parse_uci_info(
+                    attrs,
+                    &fen,
+                    &proc.options.moves,
+                    proc.options.variant(),
+                    limited_elo(&proc.options.extra_options),
+                ) {
                     let multipv = best_moves.multipv;
                     let cur_depth = best_moves.depth;
                     
@@ -1205,19 +1753,17 @@ async fn analyze_single_position(
                         if multipv == proc.real_multipv {
                             if proc.best_moves.iter().all(|x| x.depth == cur_depth) && cur_depth >= proc.last_depth {
                                 proc.last_depth = cur_depth;
-                                // Don't clear here, wait for bestmove
-                            }
-                            
-                            if proc.best_moves.len() == proc.real_multipv as usize {
-                                proc.best_moves.clear();
+                                // Keep the last complete set of lines for bestmove
+                                proc.last_best_moves = proc.best_moves.clone();
                             }
+                            proc.best_moves.clear();
                         }
                     }
                 }
             }
             UciMessage::BestMove { .. } => {
                 trace!("Received bestmove, analysis complete");
-                return Ok(proc.best_moves.clone());
+                return Ok(proc.last_best_moves.clone());
             }
             _ => {}
         }
@@ -1243,6 +1789,7 @@ async fn check_novelty(
         let query = PositionQueryJs {
             fen: fen.to_string(),
             type_: "exact".to_string(),
+            variant: None,
         };
         
         let is_in_db = is_position_in_db(
@@ -1352,28 +1899,51 @@ pub struct EngineConfig {
 #[specta::specta]
 pub async fn get_engine_config(path: PathBuf) -> Result<EngineConfig, Error> {
     info!("Getting engine configuration from: {:?}", path);
-    
-    let mut child = spawn_engine_process(&path)?;
-    let (mut stdin, mut stdout) = get_engine_io_handles(&mut child)?;
+    let (config, _) = query_engine_config(&path).await?;
+    debug!("Engine config result: name='{}', options={}", config.name, config.options.len());
+    Ok(config)
+}
+
+/// Reads the configuration of an engine, failing unless it completes the UCI handshake.
+pub(crate) async fn read_engine_config(path: &Path) -> Result<EngineConfig, Error> {
+    match query_engine_config(path).await? {
+        (config, true) => Ok(config),
+        (_, false) => Err(Error::InvalidEngine(path.display().to_string())),
+    }
+}
+
+/// Returns the configuration the engine reported, and whether it got to `uciok`.
+async fn query_engine_config(path: &Path) -> Result<(EngineConfig, bool), Error> {
+    let EngineIo {
+        writer: mut stdin,
+        reader: mut stdout,
+        mut child,
+    } = EngineTransport::parse(path)?.open().await?;
 
     let mut config = EngineConfig::default();
     
     // Send UCI command with timeout
-    match timeout(ENGINE_INIT_TIMEOUT, get_uci_config(&mut stdin, &mut stdout, &mut config)).await {
-        Ok(Ok(())) => {
+    let complete = match timeout(ENGINE_INIT_TIMEOUT, get_uci_config(&mut stdin, &mut stdout, &mut config)).await {
+        Ok(Ok(complete)) => {
             info!("Successfully retrieved engine config: name={}, options={}", 
                   config.name, config.options.len());
+            complete
         }
         Ok(Err(e)) => {
             warn!("Failed to get engine config: {}", e);
+            false
         }
         Err(_) => {
             warn!("Timeout getting engine config from: {:?}", path);
+            false
         }
-    }
+    };
     
-    // Ensure child process is terminated
-    let _ = child.kill().await;
+    // Ensure the engine is terminated, remote ones only get the quit command
+    let _ = send_engine_command(&mut stdin, "quit\n").await;
+    if let Some(child) = child.as_mut() {
+        let _ = child.kill().await;
+    }
     
     // Fallback name if not provided
     if config.name.is_empty() {
@@ -1383,51 +1953,10 @@ pub async fn get_engine_config(path: PathBuf) -> Result<EngineConfig, Error> {
             .to_string();
     }
     
-    debug!("Engine config result: name='{}', options={}", config.name, config.options.len());
-    Ok(config)
+    Ok((config, complete))
 }
 
-fn spawn_engine_process(path: &PathBuf) -> Result<Child, Error> {
-    debug!("Spawning engine process: {:?}", path);
-    
-    let mut command = Command::new(path);
-    
-    // Set working directory intelligently
-    if let Some(parent) = path.parent() {
-        command.current_dir(parent);
-    } else if let Some(home_dir) = std::env::var_os("HOME") {
-        // Fallback for Homebrew engines
-        command.current_dir(home_dir);
-    }
-    
-    command
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .env("TERM", "dumb"); // Prevent terminal features
-
-    #[cfg(target_os = "windows")]
-    command.creation_flags(CREATE_NO_WINDOW);
-
-    let child = command.spawn()
-        .map_err(|e| {
-            error!("Failed to spawn engine process {:?}: {}", path, e);
-            Error::Io(e)
-        })?;
-
-    debug!("Engine process spawned successfully");
-    Ok(child)
-}
-
-fn get_engine_io_handles(child: &mut Child) -> Result<(ChildStdin, Lines<BufReader<ChildStdout>>), Error> {
-    let stdin = child.stdin.take().ok_or(Error::NoStdin)?;
-    let stdout = child.stdout.take().ok_or(Error::NoStdout)?;
-    // Use smaller buffer for more responsive reading
-    let stdout = BufReader::with_capacity(1024, stdout).lines();
-    Ok((stdin, stdout))
-}
-
-async fn send_engine_command(stdin: &mut ChildStdin, command: &str) -> Result<(), Error> {
+async fn send_engine_command(stdin: &mut EngineWriter, command: &str) -> Result<(), Error> {
     trace!("Sending command: {}", command.trim());
     stdin
         .write_all(command.as_bytes())
@@ -1444,11 +1973,12 @@ async fn send_engine_command(stdin: &mut ChildStdin, command: &str) -> Result<()
     Ok(())
 }
 
+/// Collects the name and options of the engine, returning whether it sent `uciok`.
 async fn get_uci_config(
-    stdin: &mut ChildStdin,
-    stdout: &mut Lines<BufReader<ChildStdout>>,
+    stdin: &mut EngineWriter,
+    stdout: &mut EngineReader,
     config: &mut EngineConfig
-) -> Result<(), Error> {
+) -> Result<bool, Error> {
     debug!("Requesting UCI configuration");
     send_engine_command(stdin, "uci\n").await?;
 
@@ -1466,11 +1996,90 @@ async fn get_uci_config(
             }
             UciMessage::UciOk => {
                 debug!("UCI configuration complete");
-                break;
+                return Ok(true);
             }
             _ => {}
         }
     }
     
-    Ok(())
-}
\ No newline at end of file
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(fen: &str, search_moves: &[&str], exclude_moves: &[&str], chess960: bool) -> EngineOptions {
+        EngineOptions {
+            fen: fen.to_string(),
+            moves: Vec::new(),
+            extra_options: vec![EngineOption {
+                name: "UCI_Chess960".to_string(),
+                value: chess960.to_string(),
+            }],
+            variant: None,
+            preset: None,
+            search_moves: search_moves.iter().map(|m| m.to_string()).collect(),
+            exclude_moves: exclude_moves.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    fn position(fen: &str) -> Chess {
+        Fen::from_ascii(fen.as_bytes())
+            .unwrap()
+            .into_position(CastlingMode::Chess960)
+            .unwrap()
+    }
+
+    #[test]
+    fn restricted_moves_exclude_only() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let pos = position(fen);
+        assert!(restricted_moves(&pos, &options(fen, &[], &[], false)).unwrap().is_empty());
+
+        let moves = restricted_moves(&pos, &options(fen, &[], &["e2e4"], false)).unwrap();
+        assert_eq!(moves.len(), 19);
+        assert!(!moves.contains(&"e2e4".to_string()));
+    }
+
+    #[test]
+    fn restricted_moves_castling_notation() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let pos = position(fen);
+        assert_eq!(
+            restricted_moves(&pos, &options(fen, &["e1g1", "a1b1"], &[], false)).unwrap(),
+            vec!["e1g1", "a1b1"]
+        );
+        assert_eq!(
+            restricted_moves(&pos, &options(fen, &["e1g1", "a1b1"], &[], true)).unwrap(),
+            vec!["e1h1", "a1b1"]
+        );
+        // Excluded moves are matched in either notation
+        let moves = restricted_moves(&pos, &options(fen, &[], &["e1h1", "e1c1"], true)).unwrap();
+        assert!(!moves.iter().any(|m| m == "e1h1" || m == "e1a1"));
+    }
+
+    #[test]
+    fn restricted_moves_empty_result() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let pos = position(fen);
+        let result = restricted_moves(&pos, &options(fen, &["e2e4"], &["e2e4"], false));
+        assert!(matches!(result, Err(Error::NoMovesFound)));
+    }
+
+    #[test]
+    fn go_command() {
+        assert_eq!(format_go_command(&GoMode::Depth(20), &[]), "go depth 20\n");
+        assert_eq!(
+            format_go_command(&GoMode::Infinite, &["e2e4".to_string(), "d2d4".to_string()]),
+            "go infinite searchmoves e2e4 d2d4\n"
+        );
+        assert_eq!(
+            format_go_command(
+                &GoMode::PlayersTime(PlayersTime { white: 1000, black: 2000, winc: 10, binc: 20 }),
+                &[]
+            ),
+            "go wtime 1000 btime 2000 winc 10 binc 20 movetime 1000\n"
+        );
+    }
+}
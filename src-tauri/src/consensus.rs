@@ -0,0 +1,432 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures_util::future::join_all;
+use log::{info, warn};
+use serde::Serialize;
+use shakmaty::fen::Fen;
+use specta::Type;
+use tauri_specta::Event;
+use tokio::time::timeout;
+use vampirc_uci::{
+    parse_one,
+    uci::{Score, ScoreValue},
+    UciMessage,
+};
+
+use crate::{
+    chess::{limited_elo, parse_uci_info, BestMoves, EngineOptions, EngineProcess, GoMode},
+    engine_health,
+    engine_match::MatchEngine,
+    error::{Error, Result},
+    variant::GameVariant,
+    AppState,
+};
+
+/// How often the engines' output is checked for a stop request.
+const TICK_DURATION: Duration = Duration::from_millis(50);
+
+/// Minimum time between two updates sent to the frontend.
+const EMIT_INTERVAL: Duration = Duration::from_millis(150);
+
+/// How long to wait for a `bestmove` after stopping an engine.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Centipawn value used to order mate scores among the evaluations.
+const MATE_SCORE: i32 = 100_000;
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineLine {
+    pub engine: String,
+    /// Main line of the engine at its last completed depth
+    pub line: Option<BestMoves>,
+    pub finished: bool,
+    pub error: Option<String>,
+}
+
+/// Engines that chose the same move.
+#[derive(Serialize, Debug, Clone, Type, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveVote {
+    pub uci: String,
+    pub san: String,
+    pub engines: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsensusPayload {
+    pub id: String,
+    pub tab: String,
+    pub fen: String,
+    pub moves: Vec<String>,
+    pub engines: Vec<EngineLine>,
+    /// Moves chosen by the engines, most popular first
+    pub votes: Vec<MoveVote>,
+    pub best_move: Option<String>,
+    /// Share of the engines with a line that chose the best move
+    pub agreement: f64,
+    /// Median of the engines' evaluations, from white's point of view
+    pub score: Option<Score>,
+    pub finished: bool,
+}
+
+fn score_to_cp(value: &ScoreValue) -> i32 {
+    match value {
+        ScoreValue::Cp(cp) => *cp,
+        ScoreValue::Mate(moves) if *moves > 0 => MATE_SCORE - *moves as i32,
+        ScoreValue::Mate(moves) => -MATE_SCORE - *moves as i32,
+    }
+}
+
+/// Groups the engines by the first move of their line, the most popular move
+/// first. Ties go to the move of the engine listed first.
+fn count_votes(engines: &[EngineLine]) -> Vec<MoveVote> {
+    let mut votes: Vec<MoveVote> = Vec::new();
+    for engine in engines {
+        let Some(line) = &engine.line else {
+            continue;
+        };
+        let (Some(uci), Some(san)) = (line.uci_moves.first(), line.san_moves.first()) else {
+            continue;
+        };
+        match votes.iter_mut().find(|vote| &vote.uci == uci) {
+            Some(vote) => vote.engines.push(engine.engine.clone()),
+            None => votes.push(MoveVote {
+                uci: uci.clone(),
+                san: san.clone(),
+                engines: vec![engine.engine.clone()],
+            }),
+        }
+    }
+    // Stable sort, so ties keep the order of the engines
+    votes.sort_by(|a, b| b.engines.len().cmp(&a.engines.len()));
+    votes
+}
+
+/// Median evaluation of the engines. With an even number of engines the two
+/// middle centipawn scores are averaged, a mate score is never averaged.
+fn median_score(engines: &[EngineLine]) -> Option<Score> {
+    let mut scores: Vec<&Score> = engines
+        .iter()
+        .filter_map(|engine| engine.line.as_ref().map(|line| &line.score))
+        .collect();
+    if scores.is_empty() {
+        return None;
+    }
+    scores.sort_by_key(|score| score_to_cp(&score.value));
+
+    let middle = scores.len() / 2;
+    if scores.len() % 2 == 1 {
+        return Some(scores[middle].clone());
+    }
+    match (&scores[middle - 1].value, &scores[middle].value) {
+        (ScoreValue::Cp(a), ScoreValue::Cp(b)) => Some(Score {
+            value: ScoreValue::Cp((a + b) / 2),
+            wdl: None,
+            ..scores[middle].clone()
+        }),
+        _ => Some(scores[middle - 1].clone()),
+    }
+}
+
+struct Consensus {
+    id: String,
+    tab: String,
+    fen: String,
+    moves: Vec<String>,
+    engines: Vec<EngineLine>,
+    last_emit: Option<Instant>,
+}
+
+impl Consensus {
+    fn payload(&self) -> ConsensusPayload {
+        let votes = count_votes(&self.engines);
+        let with_line = self.engines.iter().filter(|e| e.line.is_some()).count();
+        let agreement = match votes.first() {
+            Some(vote) if with_line > 0 => vote.engines.len() as f64 / with_line as f64,
+            _ => 0.0,
+        };
+        ConsensusPayload {
+            id: self.id.clone(),
+            tab: self.tab.clone(),
+            fen: self.fen.clone(),
+            moves: self.moves.clone(),
+            engines: self.engines.clone(),
+            best_move: votes.first().map(|vote| vote.uci.clone()),
+            votes,
+            agreement,
+            score: median_score(&self.engines),
+            finished: self.engines.iter().all(|e| e.finished),
+        }
+    }
+
+    /// Sends the merged view to the frontend, unless one was sent very recently.
+    fn emit(&mut self, app: &tauri::AppHandle, force: bool) {
+        if !force && self.last_emit.is_some_and(|t| t.elapsed() < EMIT_INTERVAL) {
+            return;
+        }
+        self.last_emit = Some(Instant::now());
+        if let Err(e) = self.payload().emit(app) {
+            warn!("Failed to emit consensus payload: {}", e);
+        }
+    }
+}
+
+/// Runs one engine until it finishes its search or is stopped, recording each
+/// completed depth of its main line.
+async fn run_engine(
+    index: usize,
+    engine: &MatchEngine,
+    go_mode: &GoMode,
+    stop: &AtomicBool,
+    consensus: &Mutex<Consensus>,
+    app: &tauri::AppHandle,
+) -> Result<()> {
+    let (fen, moves) = {
+        let consensus = consensus.lock().unwrap();
+        (consensus.fen.clone(), consensus.moves.clone())
+    };
+    let parsed_fen: Fen = fen.parse()?;
+
+    let (mut process, mut reader) = EngineProcess::new(engine.path.clone()).await?;
+    // Quit the engine on every way out of the search
+    let result: Result<()> = async {
+        process
+            .set_options(EngineOptions {
+                fen,
+                moves: moves.clone(),
+                extra_options: engine.options.clone(),
+                variant: None,
+                preset: None,
+                search_moves: Vec::new(),
+                exclude_moves: Vec::new(),
+            })
+            .await?;
+        process.go(go_mode).await?;
+
+        let mut stopped_at: Option<Instant> = None;
+        loop {
+            if stopped_at.is_none() && stop.load(Ordering::Relaxed) {
+                process.stop().await?;
+                stopped_at = Some(Instant::now());
+            }
+            if stopped_at.is_some_and(|t| t.elapsed() > STOP_TIMEOUT) {
+                warn!("{} didn't stop in time", engine.name);
+                break;
+            }
+
+            let line = match timeout(TICK_DURATION, reader.next_line()).await {
+                Ok(line) => line?,
+                Err(_) => continue,
+            };
+            let Some(line) = line else {
+                return Err(Error::NoStdout);
+            };
+
+            match parse_one(&line) {
+                UciMessage::Info(attrs) => {
+                    let elo = limited_elo(&engine.options);
+                    let Ok(best) =
+                        parse_uci_info(attrs, &parsed_fen, &moves, GameVariant::Standard, elo)
+                    else {
+                        continue;
+                    };
+                    if best.multipv != 1 {
+                        continue;
+                    }
+                    let mut consensus = consensus.lock().unwrap();
+                    let entry = &mut consensus.engines[index];
+                    if entry
+                        .line
+                        .as_ref()
+                        .is_some_and(|line| line.depth > best.depth)
+                    {
+                        continue;
+                    }
+                    entry.line = Some(best);
+                    consensus.emit(app, false);
+                }
+                UciMessage::BestMove { .. } => break,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = process.kill().await {
+        warn!("Failed to quit {}: {}", engine.name, e);
+    }
+    result
+}
+
+/// Analyzes a position with several engines at once and reports their lines
+/// together with the move they agree on and a consensus evaluation.
+///
+/// Updates are sent as `ConsensusPayload` events, the final one is also returned.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+#[specta::specta]
+pub async fn run_consensus_analysis(
+    id: String,
+    tab: String,
+    mut engines: Vec<MatchEngine>,
+    go_mode: GoMode,
+    fen: String,
+    moves: Vec<String>,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<ConsensusPayload> {
+    info!(
+        "Starting consensus analysis {} with {} engines",
+        id,
+        engines.len()
+    );
+
+    let mut reservations = Vec::with_capacity(engines.len());
+    for (index, engine) in engines.iter_mut().enumerate() {
+        let key = (id.clone(), index.to_string());
+        reservations.push(engine_health::reserve(&state, key, &mut engine.options).await);
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    state.running_consensus.insert(id.clone(), stop.clone());
+
+    let consensus = Mutex::new(Consensus {
+        id: id.clone(),
+        tab,
+        fen,
+        moves,
+        engines: engines
+            .iter()
+            .map(|engine| EngineLine {
+                engine: engine.name.clone(),
+                line: None,
+                finished: false,
+                error: None,
+            })
+            .collect(),
+        last_emit: None,
+    });
+
+    join_all(engines.iter().enumerate().map(|(index, engine)| {
+        let (go_mode, stop, consensus, app) = (&go_mode, &stop, &consensus, &app);
+        async move {
+            let result = run_engine(index, engine, go_mode, stop, consensus, app).await;
+            let mut consensus = consensus.lock().unwrap();
+            if let Err(e) = result {
+                warn!("Consensus engine {} failed: {}", engine.name, e);
+                consensus.engines[index].error = Some(e.to_string());
+            }
+            consensus.engines[index].finished = true;
+            consensus.emit(app, true);
+        }
+    }))
+    .await;
+
+    state.running_consensus.remove(&id);
+    let payload = consensus.lock().unwrap().payload();
+    Ok(payload)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn stop_consensus_analysis(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(stop) = state.running_consensus.get(&id) {
+        stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine(name: &str, uci: &str, san: &str, score: ScoreValue) -> EngineLine {
+        EngineLine {
+            engine: name.to_string(),
+            line: Some(BestMoves {
+                uci_moves: vec![uci.to_string()],
+                san_moves: vec![san.to_string()],
+                score: Score {
+                    value: score,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            finished: true,
+            error: None,
+        }
+    }
+
+    fn consensus(engines: Vec<EngineLine>) -> ConsensusPayload {
+        Consensus {
+            id: String::new(),
+            tab: String::new(),
+            fen: String::new(),
+            moves: Vec::new(),
+            engines,
+            last_emit: None,
+        }
+        .payload()
+    }
+
+    #[test]
+    fn majority_move() {
+        let payload = consensus(vec![
+            engine("Stockfish", "e2e4", "e4", ScoreValue::Cp(30)),
+            engine("Lc0", "d2d4", "d4", ScoreValue::Cp(20)),
+            engine("Komodo", "d2d4", "d4", ScoreValue::Cp(40)),
+            EngineLine {
+                engine: "Broken".to_string(),
+                line: None,
+                finished: true,
+                error: Some("No stdout".to_string()),
+            },
+        ]);
+
+        assert_eq!(payload.best_move.as_deref(), Some("d2d4"));
+        assert_eq!(payload.votes[0].engines, vec!["Lc0", "Komodo"]);
+        assert_eq!(payload.votes[1].engines, vec!["Stockfish"]);
+        assert!((payload.agreement - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(payload.score.unwrap().value, ScoreValue::Cp(30));
+        assert!(payload.finished);
+    }
+
+    #[test]
+    fn median_with_even_engines() {
+        let payload = consensus(vec![
+            engine("A", "e2e4", "e4", ScoreValue::Cp(10)),
+            engine("B", "e2e4", "e4", ScoreValue::Cp(50)),
+        ]);
+        assert_eq!(payload.agreement, 1.0);
+        assert_eq!(payload.score.unwrap().value, ScoreValue::Cp(30));
+
+        // Mates aren't averaged with centipawns
+        let payload = consensus(vec![
+            engine("A", "e2e4", "e4", ScoreValue::Cp(10)),
+            engine("B", "e2e4", "e4", ScoreValue::Mate(3)),
+        ]);
+        assert_eq!(payload.score.unwrap().value, ScoreValue::Cp(10));
+    }
+
+    #[test]
+    fn no_lines_yet() {
+        let payload = consensus(vec![EngineLine {
+            engine: "A".to_string(),
+            line: None,
+            finished: false,
+            error: None,
+        }]);
+        assert_eq!(payload.best_move, None);
+        assert_eq!(payload.agreement, 0.0);
+        assert!(payload.score.is_none());
+        assert!(!payload.finished);
+    }
+}
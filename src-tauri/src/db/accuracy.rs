@@ -0,0 +1,414 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use diesel::{connection::SimpleConnection, prelude::*};
+use serde::Serialize;
+use shakmaty::{Chess, Color, Position};
+use specta::Type;
+
+use crate::{
+    chess::{analyze_game, EngineOption, GoMode},
+    db::{
+        annotation::{database_game_pgn, prepare_analysis, terminal_eval, Eval, PositionAnalysis},
+        core, get_db_or_create,
+        models::NewGameAnalysis,
+        pgn::GameTree,
+        schema::{game_analysis, games},
+        ConnectionOptions,
+    },
+    error::Result,
+    AppState,
+};
+
+const CREATE_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS GameAnalysis (
+        GameID INTEGER NOT NULL,
+        IsWhite BOOLEAN NOT NULL,
+        Accuracy REAL NOT NULL,
+        ACPL REAL NOT NULL,
+        OpeningAccuracy REAL,
+        OpeningACPL REAL,
+        MiddlegameAccuracy REAL,
+        MiddlegameACPL REAL,
+        EndgameAccuracy REAL,
+        EndgameACPL REAL,
+        PRIMARY KEY (GameID, IsWhite),
+        FOREIGN KEY(GameID) REFERENCES Games
+    );
+";
+
+/// Evaluations are capped so a missed mate doesn't outweigh the rest of the game.
+const MAX_CP: i32 = 1000;
+
+/// The opening lasts at most this many plies.
+const OPENING_PLIES: usize = 30;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Type)]
+pub struct PhaseScore {
+    pub accuracy: f64,
+    pub acpl: f64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Type)]
+pub struct PlayerAccuracy {
+    pub accuracy: f64,
+    pub acpl: f64,
+    pub opening: Option<PhaseScore>,
+    pub middlegame: Option<PhaseScore>,
+    pub endgame: Option<PhaseScore>,
+}
+
+/// Accuracy of both players, `None` for a player without any analyzed move.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Type)]
+pub struct GameAccuracy {
+    pub white: Option<PlayerAccuracy>,
+    pub black: Option<PlayerAccuracy>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Opening,
+    Middlegame,
+    Endgame,
+}
+
+/// Splits the game by the number of queens, rooks, bishops and knights left.
+fn phase(pos: &Chess, ply: usize) -> Phase {
+    let board = pos.board();
+    let pieces = (board.occupied() & !board.pawns() & !board.kings()).count();
+    if pieces <= 6 {
+        Phase::Endgame
+    } else if ply < OPENING_PLIES && pieces > 10 {
+        Phase::Opening
+    } else {
+        Phase::Middlegame
+    }
+}
+
+/// Accuracy of a single move from the winning chances of the player before
+/// and after it, using the formula of Lichess.
+fn move_accuracy(before: f64, after: f64) -> f64 {
+    let loss = (before - after).max(0.0);
+    (103.166_810_071_164_9 * (-0.043_544_153_867_539_51 * loss).exp() - 3.166_924_740_191_411)
+        .clamp(0.0, 100.0)
+}
+
+fn centipawns(eval: Eval) -> i32 {
+    match eval {
+        Eval::Cp(cp) => cp.clamp(-MAX_CP, MAX_CP),
+        Eval::Mate(moves) if moves > 0 => MAX_CP,
+        Eval::Mate(_) => -MAX_CP,
+    }
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    accuracies: Vec<f64>,
+    losses: Vec<f64>,
+}
+
+impl Totals {
+    fn add(&mut self, accuracy: f64, loss: f64) {
+        self.accuracies.push(accuracy);
+        self.losses.push(loss);
+    }
+
+    /// The accuracy is the average of the arithmetic and harmonic means of the
+    /// moves, so a few bad moves weigh more than in a plain average.
+    fn score(&self) -> Option<PhaseScore> {
+        if self.accuracies.is_empty() {
+            return None;
+        }
+        let n = self.accuracies.len() as f64;
+        let mean = self.accuracies.iter().sum::<f64>() / n;
+        let harmonic = n / self
+            .accuracies
+            .iter()
+            .map(|a| 1.0 / a.max(1.0))
+            .sum::<f64>();
+        Some(PhaseScore {
+            accuracy: (mean + harmonic) / 2.0,
+            acpl: self.losses.iter().sum::<f64>() / n,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct PlayerTotals {
+    all: Totals,
+    opening: Totals,
+    middlegame: Totals,
+    endgame: Totals,
+}
+
+impl PlayerTotals {
+    fn accuracy(&self) -> Option<PlayerAccuracy> {
+        let all = self.all.score()?;
+        Some(PlayerAccuracy {
+            accuracy: all.accuracy,
+            acpl: all.acpl,
+            opening: self.opening.score(),
+            middlegame: self.middlegame.score(),
+            endgame: self.endgame.score(),
+        })
+    }
+}
+
+/// Computes the accuracy and average centipawn loss of both players from the
+/// analysis of every position of the main line.
+pub fn game_accuracy(tree: &GameTree, start: Chess, analysis: &[PositionAnalysis]) -> GameAccuracy {
+    let mut white = PlayerTotals::default();
+    let mut black = PlayerTotals::default();
+
+    let mut pos = start;
+    for (ply, san) in tree.main_line().enumerate() {
+        let Ok(m) = san.san.to_move(&pos) else {
+            break;
+        };
+        let phase = phase(&pos, ply);
+        let mover = pos.turn();
+        pos.play_unchecked(&m);
+
+        let before = analysis.get(ply).and_then(|a| a.eval);
+        let after = analysis
+            .get(ply + 1)
+            .and_then(|a| a.eval)
+            .or_else(|| terminal_eval(&pos));
+        let (Some(before), Some(after)) = (before, after) else {
+            continue;
+        };
+
+        let (win_before, win_after, cp_loss) = match mover {
+            Color::White => (
+                before.win_percent(),
+                after.win_percent(),
+                centipawns(before) - centipawns(after),
+            ),
+            Color::Black => (
+                100.0 - before.win_percent(),
+                100.0 - after.win_percent(),
+                centipawns(after) - centipawns(before),
+            ),
+        };
+        let accuracy = move_accuracy(win_before, win_after);
+        let loss = cp_loss.max(0) as f64;
+
+        let totals = match mover {
+            Color::White => &mut white,
+            Color::Black => &mut black,
+        };
+        totals.all.add(accuracy, loss);
+        match phase {
+            Phase::Opening => totals.opening.add(accuracy, loss),
+            Phase::Middlegame => totals.middlegame.add(accuracy, loss),
+            Phase::Endgame => totals.endgame.add(accuracy, loss),
+        }
+    }
+
+    GameAccuracy {
+        white: white.accuracy(),
+        black: black.accuracy(),
+    }
+}
+
+pub fn ensure_table(db: &mut SqliteConnection) -> Result<()> {
+    db.batch_execute(CREATE_TABLE_SQL)?;
+    Ok(())
+}
+
+/// Saves the accuracy of a game, replacing any previous analysis.
+pub fn store(db: &mut SqliteConnection, game_id: i32, accuracy: &GameAccuracy) -> Result<()> {
+    diesel::delete(game_analysis::table.filter(game_analysis::game_id.eq(game_id))).execute(db)?;
+
+    for (is_white, player) in [(true, &accuracy.white), (false, &accuracy.black)] {
+        let Some(player) = player else {
+            continue;
+        };
+        diesel::insert_into(game_analysis::table)
+            .values(NewGameAnalysis {
+                game_id,
+                is_white,
+                accuracy: player.accuracy,
+                acpl: player.acpl,
+                opening_accuracy: player.opening.map(|p| p.accuracy),
+                opening_acpl: player.opening.map(|p| p.acpl),
+                middlegame_accuracy: player.middlegame.map(|p| p.accuracy),
+                middlegame_acpl: player.middlegame.map(|p| p.acpl),
+                endgame_accuracy: player.endgame.map(|p| p.accuracy),
+                endgame_acpl: player.endgame.map(|p| p.acpl),
+            })
+            .execute(db)?;
+    }
+    Ok(())
+}
+
+pub fn remove_game(db: &mut SqliteConnection, game_id: i32) -> Result<()> {
+    diesel::delete(game_analysis::table.filter(game_analysis::game_id.eq(game_id))).execute(db)?;
+    Ok(())
+}
+
+/// Removes the analysis of games that are no longer in the database.
+pub fn prune(db: &mut SqliteConnection) -> Result<()> {
+    db.batch_execute("DELETE FROM GameAnalysis WHERE GameID NOT IN (SELECT ID FROM Games);")?;
+    Ok(())
+}
+
+/// Returns the accuracy and average centipawn loss of a player in each of
+/// their analyzed games.
+pub fn player_accuracies(
+    db: &mut SqliteConnection,
+    player_id: i32,
+) -> Result<HashMap<i32, (f64, f64)>> {
+    let rows: Vec<(i32, f64, f64)> = game_analysis::table
+        .inner_join(games::table)
+        .filter(
+            game_analysis::is_white
+                .eq(true)
+                .and(games::white_id.eq(player_id))
+                .or(game_analysis::is_white
+                    .eq(false)
+                    .and(games::black_id.eq(player_id))),
+        )
+        .select((
+            game_analysis::game_id,
+            game_analysis::accuracy,
+            game_analysis::acpl,
+        ))
+        .load(db)?;
+    Ok(rows
+        .into_iter()
+        .map(|(game_id, accuracy, acpl)| (game_id, (accuracy, acpl)))
+        .collect())
+}
+
+/// Analyzes the main line of a database game and stores the accuracy of
+/// both players.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+#[specta::specta]
+pub async fn analyze_game_accuracy(
+    id: String,
+    engine: String,
+    go_mode: GoMode,
+    uci_options: Vec<EngineOption>,
+    file: PathBuf,
+    game_id: i32,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<GameAccuracy> {
+    let pgn = {
+        let db =
+            &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+        database_game_pgn(&core::get_game(db, game_id)?)
+    };
+
+    let (tree, start, options) = prepare_analysis(&pgn)?;
+    let analysis = analyze_game(
+        id,
+        engine,
+        go_mode,
+        options,
+        uci_options,
+        state.clone(),
+        app,
+    )
+    .await?
+    .iter()
+    .map(PositionAnalysis::from)
+    .collect::<Vec<_>>();
+
+    let accuracy = game_accuracy(&tree, start, &analysis);
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+    store(db, game_id, &accuracy)?;
+    Ok(accuracy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{core::init_db, pgn::Importer};
+    use pgn_reader::BufferedReader;
+
+    fn tree(pgn: &str) -> GameTree {
+        let mut reader = BufferedReader::new_cursor(pgn.as_bytes());
+        let mut importer = Importer::new(None);
+        reader
+            .read_game(&mut importer)
+            .unwrap()
+            .flatten()
+            .unwrap()
+            .tree
+    }
+
+    fn evals(evals: &[i32]) -> Vec<PositionAnalysis> {
+        evals
+            .iter()
+            .map(|cp| PositionAnalysis {
+                eval: Some(Eval::Cp(*cp)),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn perfect_and_bad_moves() {
+        assert!((move_accuracy(50.0, 50.0) - 100.0).abs() < 0.01);
+        assert!(move_accuracy(80.0, 20.0) < 10.0);
+        // Improving the position doesn't give more than 100
+        assert!(move_accuracy(20.0, 80.0) <= 100.0);
+    }
+
+    #[test]
+    fn accuracy_per_player() {
+        let tree = tree("1. e4 e5 2. Qh5 Ke7 *");
+        let accuracy = game_accuracy(&tree, Chess::default(), &evals(&[20, 20, 20, -60, 500]));
+
+        let white = accuracy.white.unwrap();
+        let black = accuracy.black.unwrap();
+        // 2. Qh5 lost 80 centipawns, 2... Ke7 lost 560
+        assert_eq!(white.acpl, 40.0);
+        assert_eq!(black.acpl, 280.0);
+        assert!(white.accuracy > black.accuracy);
+        assert!(white.opening.is_some());
+        assert!(white.endgame.is_none());
+    }
+
+    #[test]
+    fn missing_evaluations() {
+        let tree = tree("1. e4 e5 *");
+        let mut analysis = evals(&[20, 20]);
+        analysis.push(PositionAnalysis::default());
+        let accuracy = game_accuracy(&tree, Chess::default(), &analysis);
+        assert!(accuracy.white.is_some());
+        assert!(accuracy.black.is_none());
+    }
+
+    #[test]
+    fn endgame_phase() {
+        let pos: Chess = "4k3/8/8/8/8/8/4P3/R3K2R w KQ - 0 1"
+            .parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(shakmaty::CastlingMode::Standard)
+            .unwrap();
+        assert_eq!(phase(&pos, 80), Phase::Endgame);
+        assert_eq!(phase(&Chess::default(), 0), Phase::Opening);
+        assert_eq!(phase(&Chess::default(), 40), Phase::Middlegame);
+    }
+
+    #[test]
+    fn store_and_load() {
+        let mut db = SqliteConnection::establish(":memory:").unwrap();
+        init_db(&mut db, "Test", "Test").unwrap();
+        db.batch_execute(
+            "INSERT INTO Games (ID, EventID, SiteID, WhiteID, BlackID) VALUES (1, 0, 0, 0, 0);",
+        )
+        .unwrap();
+
+        let accuracy = game_accuracy(&tree("1. e4 e5 *"), Chess::default(), &evals(&[20, 20, 20]));
+        store(&mut db, 1, &accuracy).unwrap();
+        store(&mut db, 1, &accuracy).unwrap();
+
+        let loaded = player_accuracies(&mut db, 0).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[&1].1, 0.0);
+    }
+}
@@ -0,0 +1,537 @@
+use std::path::PathBuf;
+
+use log::info;
+use pgn_reader::{BufferedReader, Nag, SanPlus};
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, variant::VariantPosition, CastlingMode, Chess, Color, Position};
+use specta::Type;
+use vampirc_uci::uci::ScoreValue;
+
+use crate::{
+    chess::{analyze_game, AnalysisOptions, EngineOption, GoMode, MoveAnalysis},
+    db::{
+        accuracy::{self, game_accuracy},
+        core, get_db_or_create,
+        models::{NormalizedGame, UpdateGame},
+        move_annotations::MoveAnnotations,
+        pgn::{GameTree, GameTreeNode, Importer},
+        ConnectionOptions,
+    },
+    error::{Error, Result},
+    AppState,
+};
+
+const MISTAKE: Nag = Nag(2);
+const BRILLIANT_MOVE: Nag = Nag(3);
+const BLUNDER: Nag = Nag(4);
+const DUBIOUS_MOVE: Nag = Nag(6);
+const NOVELTY: Nag = Nag(146);
+
+/// Centipawn value given to mates when computing winning chances.
+const MATE_CP: i32 = 10_000;
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AnnotationSettings {
+    /// Winning chances (in %) lost from which a move is an inaccuracy
+    pub inaccuracy: f64,
+    /// Winning chances (in %) lost from which a move is a mistake
+    pub mistake: f64,
+    /// Winning chances (in %) lost from which a move is a blunder
+    pub blunder: f64,
+    /// Adds `[%eval]` comments after every move
+    pub eval_comments: bool,
+    /// Maximum length of the engine lines added after bad moves, 0 disables them
+    pub variation_length: u32,
+    pub annotate_novelties: bool,
+    pub reference_db: Option<PathBuf>,
+}
+
+impl Default for AnnotationSettings {
+    fn default() -> Self {
+        AnnotationSettings {
+            inaccuracy: 5.0,
+            mistake: 10.0,
+            blunder: 20.0,
+            eval_comments: true,
+            variation_length: 6,
+            annotate_novelties: false,
+            reference_db: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(tag = "t", content = "c")]
+pub enum AnnotationTarget {
+    /// A game of a database, which is saved back once annotated
+    Database { file: PathBuf, game_id: i32 },
+    /// The movetext of a game, with its headers
+    Pgn(String),
+}
+
+/// Engine evaluation from white's point of view.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(tag = "t", content = "c")]
+pub enum Eval {
+    Cp(i32),
+    Mate(i32),
+}
+
+impl Eval {
+    /// Winning chances of white in percent, as used by Lichess.
+    pub(super) fn win_percent(self) -> f64 {
+        let cp = match self {
+            Eval::Cp(cp) => cp,
+            Eval::Mate(moves) if moves > 0 => MATE_CP,
+            Eval::Mate(_) => -MATE_CP,
+        };
+        50.0 + 50.0 * (2.0 / (1.0 + (-0.003_682_08 * cp as f64).exp()) - 1.0)
+    }
+}
+
+/// What the engine found in a position of the game.
+#[derive(Debug, Clone, Default)]
+pub struct PositionAnalysis {
+    pub eval: Option<Eval>,
+    /// Best line in SAN
+    pub best_line: Vec<String>,
+    /// The move leading to this position gave up material
+    pub is_sacrifice: bool,
+    /// The position isn't in the reference database
+    pub novelty: bool,
+}
+
+impl From<&MoveAnalysis> for PositionAnalysis {
+    fn from(analysis: &MoveAnalysis) -> Self {
+        let best = analysis.best.first();
+        PositionAnalysis {
+            eval: best.map(|best| match best.score.value {
+                ScoreValue::Cp(cp) => Eval::Cp(cp as i32),
+                ScoreValue::Mate(moves) => Eval::Mate(moves as i32),
+            }),
+            best_line: best.map(|best| best.san_moves.clone()).unwrap_or_default(),
+            is_sacrifice: analysis.is_sacrifice,
+            novelty: analysis.novelty,
+        }
+    }
+}
+
+/// Evaluation of a position the engine didn't analyze because the game is over.
+pub(super) fn terminal_eval(pos: &Chess) -> Option<Eval> {
+    if pos.is_checkmate() {
+        Some(Eval::Cp(match pos.turn() {
+            Color::White => -MATE_CP,
+            Color::Black => MATE_CP,
+        }))
+    } else if pos.is_game_over() {
+        Some(Eval::Cp(0))
+    } else {
+        None
+    }
+}
+
+/// Classifies a move by the winning chances lost by the player who made it.
+fn move_nag(
+    settings: &AnnotationSettings,
+    before: f64,
+    after: f64,
+    is_best: bool,
+    is_sacrifice: bool,
+) -> Option<Nag> {
+    let loss = before - after;
+    if loss >= settings.blunder {
+        Some(BLUNDER)
+    } else if loss >= settings.mistake {
+        Some(MISTAKE)
+    } else if loss >= settings.inaccuracy {
+        Some(DUBIOUS_MOVE)
+    } else if is_best && is_sacrifice && after >= 50.0 {
+        Some(BRILLIANT_MOVE)
+    } else {
+        None
+    }
+}
+
+fn best_line_tree(line: &[String], length: u32) -> GameTree {
+    let mut tree = GameTree::new();
+    for san in line.iter().take(length as usize) {
+        match san.parse::<SanPlus>() {
+            Ok(san) => tree.push(GameTreeNode::Move(san)),
+            Err(_) => break,
+        }
+    }
+    tree
+}
+
+/// Pushes a move followed by its annotations, in the order they appear in a PGN.
+fn push_move(
+    tree: &mut GameTree,
+    san: SanPlus,
+    nags: Vec<Nag>,
+    annotations: MoveAnnotations,
+    comments: Vec<String>,
+    variations: Vec<GameTreeNode>,
+) {
+    tree.push(GameTreeNode::Move(san));
+    for nag in nags {
+        tree.push(GameTreeNode::Nag(nag));
+    }
+    if !annotations.is_empty() {
+        tree.push(GameTreeNode::Annotations(annotations));
+    }
+    for comment in comments {
+        tree.push(GameTreeNode::Comment(comment));
+    }
+    for variation in variations {
+        tree.push(variation);
+    }
+}
+
+/// Adds NAGs, evaluation comments and engine lines to the main line of a game.
+///
+/// `analysis` holds the analysis of every position of the main line, starting
+/// with the initial one. Annotations already in the game are kept, apart from
+/// previous evaluations which are replaced.
+pub fn annotate_tree(
+    tree: GameTree,
+    start: Chess,
+    analysis: &[PositionAnalysis],
+    settings: &AnnotationSettings,
+) -> GameTree {
+    let mut annotated = GameTree::new();
+    let mut nodes = tree.into_nodes().into_iter().peekable();
+
+    // Comments before the first move
+    while let Some(node) = nodes.next_if(|node| !matches!(node, GameTreeNode::Move(_))) {
+        annotated.push(node);
+    }
+
+    let mut pos = start;
+    let mut ply = 0;
+    while let Some(node) = nodes.next() {
+        let GameTreeNode::Move(san) = node else {
+            annotated.push(node);
+            continue;
+        };
+
+        let mut nags = Vec::new();
+        let mut annotations = MoveAnnotations::default();
+        let mut comments = Vec::new();
+        let mut variations = Vec::new();
+        while let Some(node) = nodes.next_if(|node| !matches!(node, GameTreeNode::Move(_))) {
+            match node {
+                GameTreeNode::Nag(nag) => nags.push(nag),
+                GameTreeNode::Annotations(node) => annotations.merge(node),
+                GameTreeNode::Comment(comment) => comments.push(comment),
+                node => variations.push(node),
+            }
+        }
+        annotations.eval = None;
+        annotations.depth = None;
+
+        let Ok(m) = san.san.to_move(&pos) else {
+            // Not a legal move, keep the rest of the game untouched
+            push_move(&mut annotated, san, nags, annotations, comments, variations);
+            nodes.by_ref().for_each(|node| annotated.push(node));
+            break;
+        };
+
+        let mover = pos.turn();
+        let played = m.to_uci(CastlingMode::Standard);
+        let before = analysis.get(ply);
+        let is_best = before
+            .and_then(|before| before.best_line.first())
+            .and_then(|best| best.parse::<SanPlus>().ok())
+            .and_then(|best| best.san.to_move(&pos).ok())
+            .is_some_and(|best| best.to_uci(CastlingMode::Standard) == played);
+        pos.play_unchecked(&m);
+        let after = analysis.get(ply + 1);
+
+        let before_eval = before.and_then(|before| before.eval);
+        let after_eval = after
+            .and_then(|after| after.eval)
+            .or_else(|| terminal_eval(&pos));
+
+        let mut bad_move = false;
+        if let (Some(before_eval), Some(after_eval)) = (before_eval, after_eval) {
+            let win_percent = |eval: Eval| match mover {
+                Color::White => eval.win_percent(),
+                Color::Black => 100.0 - eval.win_percent(),
+            };
+            let is_sacrifice = after.is_some_and(|after| after.is_sacrifice);
+            let nag = move_nag(
+                settings,
+                win_percent(before_eval),
+                win_percent(after_eval),
+                is_best,
+                is_sacrifice,
+            );
+            bad_move = matches!(nag, Some(BLUNDER | MISTAKE | DUBIOUS_MOVE));
+
+            // Don't override the assessment of a move that was already annotated
+            if let Some(nag) = nag {
+                if !nags.iter().any(|nag| (1..=6).contains(&nag.0)) {
+                    nags.insert(0, nag);
+                }
+            }
+        }
+
+        if settings.annotate_novelties
+            && after.is_some_and(|after| after.novelty)
+            && !nags.contains(&NOVELTY)
+        {
+            nags.push(NOVELTY);
+        }
+
+        if settings.eval_comments && !pos.is_game_over() {
+            annotations.eval = after.and_then(|after| after.eval);
+        }
+
+        if bad_move && !is_best && settings.variation_length > 0 {
+            if let Some(before) = before {
+                let line = best_line_tree(&before.best_line, settings.variation_length);
+                if line.count_main_line_moves() > 0 {
+                    variations.push(GameTreeNode::Variation(line));
+                }
+            }
+        }
+
+        push_move(&mut annotated, san, nags, annotations, comments, variations);
+        ply += 1;
+    }
+
+    annotated
+}
+
+fn parse_game(pgn: &str) -> Result<(GameTree, Chess, Option<String>)> {
+    let mut reader = BufferedReader::new_cursor(pgn.as_bytes());
+    let mut importer = Importer::new(None);
+    let game = reader
+        .read_game(&mut importer)?
+        .flatten()
+        .ok_or(Error::NoMovesFound)?;
+    // Annotations rely on standard chess evaluations
+    let VariantPosition::Chess(position) = game.position else {
+        return Err(Error::UnsupportedVariant(
+            game.variant.pgn_name().to_string(),
+        ));
+    };
+    Ok((game.tree, position, game.fen))
+}
+
+/// Returns a database game as a PGN the importer can read back.
+pub(super) fn database_game_pgn(game: &NormalizedGame) -> String {
+    match game.variant {
+        Some(variant) => format!(
+            "[Variant \"{}\"]\n[FEN \"{}\"]\n\n{}",
+            variant.pgn_name(),
+            game.fen,
+            game.moves
+        ),
+        None => format!("[FEN \"{}\"]\n\n{}", game.fen, game.moves),
+    }
+}
+
+/// Reads a game and builds the options to analyze every position of its main line.
+pub(super) fn prepare_analysis(pgn: &str) -> Result<(GameTree, Chess, AnalysisOptions)> {
+    let (tree, start, fen) = parse_game(pgn)?;
+    let mut pos = start.clone();
+    let mut moves = Vec::new();
+    for san in tree.main_line() {
+        let m = san.san.to_move(&pos)?;
+        moves.push(m.to_uci(CastlingMode::Standard).to_string());
+        pos.play_unchecked(&m);
+    }
+
+    let options = AnalysisOptions {
+        fen: fen.unwrap_or_else(|| Fen::default().to_string()),
+        moves,
+        annotate_novelties: false,
+        reference_db: None,
+        reversed: false,
+    };
+    Ok((tree, start, options))
+}
+
+/// Analyzes a game with an engine and annotates it with NAGs, evaluations
+/// and the engine's lines after bad moves.
+///
+/// Database games are saved back along with the accuracy of the players; the
+/// annotated movetext is returned in both cases.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+#[specta::specta]
+pub async fn annotate_game(
+    id: String,
+    engine: String,
+    go_mode: GoMode,
+    uci_options: Vec<EngineOption>,
+    settings: AnnotationSettings,
+    target: AnnotationTarget,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<String> {
+    let (pgn, game) = match &target {
+        AnnotationTarget::Database { file, game_id } => {
+            let db = &mut get_db_or_create(
+                &state,
+                file.to_str().unwrap(),
+                ConnectionOptions::default(),
+            )?;
+            let game = core::get_game(db, *game_id)?;
+            (database_game_pgn(&game), Some(game))
+        }
+        AnnotationTarget::Pgn(pgn) => (pgn.clone(), None),
+    };
+
+    let (tree, start, mut options) = prepare_analysis(&pgn)?;
+    options.annotate_novelties = settings.annotate_novelties;
+    options.reference_db = settings.reference_db.clone();
+    let analysis = analyze_game(
+        id,
+        engine,
+        go_mode,
+        options,
+        uci_options,
+        state.clone(),
+        app,
+    )
+    .await?
+    .iter()
+    .map(PositionAnalysis::from)
+    .collect::<Vec<_>>();
+
+    let accuracy = game_accuracy(&tree, start.clone(), &analysis);
+    let annotated = annotate_tree(tree, start, &analysis, &settings).to_string();
+
+    if let (AnnotationTarget::Database { file, game_id }, Some(game)) = (&target, game) {
+        info!("saving annotated game {} to {:?}", game_id, file);
+        let db =
+            &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+        accuracy::store(db, *game_id, &accuracy)?;
+        core::update_game(
+            db,
+            *game_id,
+            &UpdateGame {
+                fen: game.fen,
+                event: game.event,
+                site: game.site,
+                date: game.date,
+                time: game.time,
+                round: game.round,
+                white: game.white,
+                white_elo: game.white_elo,
+                black: game.black,
+                black_elo: game.black_elo,
+                result: game.result,
+                time_control: game.time_control,
+                eco: game.eco,
+                ply_count: game.ply_count,
+                moves: annotated.clone(),
+                tags: None,
+            },
+        )?;
+    }
+
+    Ok(annotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analysis(eval: Eval, best_line: &[&str]) -> PositionAnalysis {
+        PositionAnalysis {
+            eval: Some(eval),
+            best_line: best_line.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn win_percent() {
+        assert_eq!(Eval::Cp(0).win_percent(), 50.0);
+        assert!(Eval::Mate(3).win_percent() > 99.9);
+        assert!(Eval::Mate(-3).win_percent() < 0.1);
+        assert!((Eval::Cp(100).win_percent() - 59.1).abs() < 0.1);
+    }
+
+    #[test]
+    fn annotate_blunder() {
+        let (tree, start, _) = parse_game("1. e4 {Best by test} e5 2. Qh5 Ke7 *").unwrap();
+        let analysis = [
+            analysis(Eval::Cp(30), &["e4", "e5"]),
+            analysis(Eval::Cp(30), &["e5", "Nf3"]),
+            analysis(Eval::Cp(25), &["Nf3", "Nc6"]),
+            analysis(Eval::Cp(-60), &["Nc6", "Bc4"]),
+            analysis(Eval::Cp(500), &["Qxe5#"]),
+        ];
+        let settings = AnnotationSettings::default();
+        let annotated = annotate_tree(tree, start, &analysis, &settings).to_string();
+
+        assert_eq!(
+            annotated,
+            "1.e4 {[%eval 0.30] Best by test}  e5 {[%eval 0.25]}  2.Qh5 $6 {[%eval -0.60]}  \
+             ( 2.Nf3 Nc6 ) 2...Ke7 $4 {[%eval 5.00]}  ( 2...Nc6 3.Bc4 ) "
+        );
+    }
+
+    #[test]
+    fn failed_position_is_skipped() {
+        let (tree, start, _) = parse_game("1. e4 e5 2. Qh5 Ke7 *").unwrap();
+        // The engine failed to analyze the position after 1. e4
+        let analysis = [
+            analysis(Eval::Cp(30), &["e4", "e5"]),
+            PositionAnalysis::from(&MoveAnalysis::default()),
+            analysis(Eval::Cp(25), &["Nf3", "Nc6"]),
+            analysis(Eval::Cp(-60), &["Nc6", "Bc4"]),
+            analysis(Eval::Cp(500), &["Qxe5#"]),
+        ];
+        let settings = AnnotationSettings::default();
+        let annotated = annotate_tree(tree, start, &analysis, &settings).to_string();
+
+        assert_eq!(
+            annotated,
+            "1.e4 e5 {[%eval 0.25]}  2.Qh5 $6 {[%eval -0.60]}  ( 2.Nf3 Nc6 ) \
+             2...Ke7 $4 {[%eval 5.00]}  ( 2...Nc6 3.Bc4 ) "
+        );
+    }
+
+    #[test]
+    fn existing_annotations_are_kept() {
+        let (tree, start, _) = parse_game("1. e4 $1 {[%eval 0.10]} *").unwrap();
+        let analysis = [
+            analysis(Eval::Cp(30), &["d4"]),
+            analysis(Eval::Cp(-300), &["e5"]),
+        ];
+        let settings = AnnotationSettings {
+            variation_length: 0,
+            ..Default::default()
+        };
+        let annotated = annotate_tree(tree, start, &analysis, &settings).to_string();
+        assert_eq!(annotated, "1.e4 $1 {[%eval -3.00]} ");
+    }
+
+    #[test]
+    fn checkmate_is_not_a_blunder() {
+        let (tree, start, _) = parse_game("1. f3 e5 2. g4 Qh4# 0-1").unwrap();
+        let analysis = [
+            analysis(Eval::Cp(20), &["e4"]),
+            analysis(Eval::Cp(-50), &["e5"]),
+            analysis(Eval::Cp(-60), &["d4"]),
+            analysis(Eval::Mate(-1), &["Qh4#"]),
+        ];
+        let annotated = annotate_tree(tree, start, &analysis, &AnnotationSettings::default());
+        let nags: Vec<_> = annotated
+            .into_nodes()
+            .into_iter()
+            .filter_map(|node| match node {
+                GameTreeNode::Nag(nag) => Some(nag.0),
+                _ => None,
+            })
+            .collect();
+        // 1. f3 is dubious and 2. g4 a blunder, but Qh4# is just the best move
+        assert_eq!(nags, vec![6, 4]);
+    }
+}
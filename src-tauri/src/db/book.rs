@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use diesel::{connection::DefaultLoadingMode, prelude::*};
+use log::info;
+use serde::{Deserialize, Serialize};
+use shakmaty::{Chess, Color, Position};
+use specta::Type;
+use tauri_specta::Event as _;
+
+use crate::{
+    db::{
+        game_variant, get_db_or_create, pgn::GameTree, position_index::start_position,
+        schema::games, ConnectionOptions, DatabaseProgress,
+    },
+    error::Result,
+    polyglot::{encode_move, polyglot_key, sort_entries, BookEntry},
+    AppState,
+};
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Type, PartialEq, Eq)]
+pub enum BookWeighting {
+    /// Moves are weighted by how often they were played
+    Frequency,
+    /// Moves are weighted by their results, 2 points per win and 1 per draw
+    Results,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct BookExportOptions {
+    /// Only positions up to this ply are added to the book
+    pub max_ply: u32,
+    /// Moves played in fewer games are left out of the book
+    pub min_games: u32,
+    pub weighting: BookWeighting,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct MoveStats {
+    games: u32,
+    wins: u32,
+    draws: u32,
+}
+
+/// Collects the statistics of every (position, move) pair of the games, up to
+/// the configured depth.
+#[derive(Debug, Default)]
+pub struct BookBuilder {
+    moves: HashMap<(u64, u16), MoveStats>,
+}
+
+impl BookBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the main line of a game to the book.
+    pub fn add_game(&mut self, tree: &GameTree, position: Chess, result: Option<&str>, max_ply: u32) {
+        let winner = match result {
+            Some("1-0") => Some(Color::White),
+            Some("0-1") => Some(Color::Black),
+            _ => None,
+        };
+        let is_draw = result == Some("1/2-1/2");
+
+        let mut pos = position;
+        for san in tree.main_line().take(max_ply as usize) {
+            let Ok(m) = san.san.to_move(&pos) else {
+                break;
+            };
+            let stats = self
+                .moves
+                .entry((polyglot_key(&pos), encode_move(&m)))
+                .or_default();
+            stats.games += 1;
+            if winner == Some(pos.turn()) {
+                stats.wins += 1;
+            } else if is_draw {
+                stats.draws += 1;
+            }
+            pos.play_unchecked(&m);
+        }
+    }
+
+    /// Turns the collected statistics into sorted book entries.
+    pub fn into_entries(self, min_games: u32, weighting: BookWeighting) -> Vec<BookEntry> {
+        let mut by_key: HashMap<u64, Vec<(u16, u64)>> = HashMap::new();
+        for ((key, mv), stats) in self.moves {
+            if stats.games < min_games.max(1) {
+                continue;
+            }
+            let weight = match weighting {
+                BookWeighting::Frequency => stats.games as u64,
+                BookWeighting::Results => 2 * stats.wins as u64 + stats.draws as u64,
+            };
+            // Moves that never scored would never be picked anyway
+            if weight > 0 {
+                by_key.entry(key).or_default().push((mv, weight));
+            }
+        }
+
+        let mut entries = Vec::new();
+        for (key, moves) in by_key {
+            // Weights are 16 bits, scale them down keeping their proportions
+            let max = moves.iter().map(|(_, w)| *w).max().unwrap_or(0);
+            let scale = |w: u64| {
+                if max > u16::MAX as u64 {
+                    (w * u16::MAX as u64 / max).max(1) as u16
+                } else {
+                    w as u16
+                }
+            };
+            entries.extend(moves.into_iter().map(|(mv, weight)| BookEntry {
+                key,
+                mv,
+                weight: scale(weight),
+                learn: 0,
+            }));
+        }
+        sort_entries(&mut entries);
+        entries
+    }
+}
+
+/// Builds a Polyglot opening book from the games of a database.
+///
+/// Returns the number of entries written to the book.
+#[tauri::command]
+#[specta::specta]
+pub async fn export_polyglot_book(
+    file: PathBuf,
+    dest_file: PathBuf,
+    options: BookExportOptions,
+    id: String,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<u32> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let game_count: i64 = games::table.count().get_result(db)?;
+    let mut builder = BookBuilder::new();
+    // Polyglot books are only defined for standard chess
+    let variants = game_variant::load_all(db)?;
+
+    for (i, row) in games::table
+        .select((games::id, games::moves, games::fen, games::result))
+        .load_iter::<(i32, Vec<u8>, Option<String>, Option<String>), DefaultLoadingMode>(db)?
+        .enumerate()
+    {
+        let (game_id, moves, fen, result) = row?;
+        if i % 1000 == 0 {
+            let _ = DatabaseProgress {
+                id: id.clone(),
+                progress: (i as f64 / game_count.max(1) as f64) * 100_f64,
+            }
+            .emit(&app);
+        }
+
+        if variants.contains_key(&game_id) {
+            continue;
+        }
+        let Ok(position) = start_position(fen.as_deref()) else {
+            continue;
+        };
+        let Ok(tree) = GameTree::from_bytes(&moves, position.clone()) else {
+            continue;
+        };
+        builder.add_game(&tree, position, result.as_deref(), options.max_ply);
+    }
+
+    let entries = builder.into_entries(options.min_games, options.weighting);
+    info!("writing {} book entries to {:?}", entries.len(), dest_file);
+
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(dest_file)?;
+    let mut writer = BufWriter::new(file);
+    for entry in &entries {
+        writer.write_all(&entry.to_bytes())?;
+    }
+    writer.flush()?;
+
+    let _ = DatabaseProgress {
+        id,
+        progress: 100_f64,
+    }
+    .emit(&app);
+
+    Ok(entries.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::pgn::Importer;
+    use pgn_reader::BufferedReader;
+
+    fn tree(pgn: &str) -> GameTree {
+        let mut reader = BufferedReader::new_cursor(pgn.as_bytes());
+        let mut importer = Importer::new(None);
+        reader.read_game(&mut importer).unwrap().flatten().unwrap().tree
+    }
+
+    #[test]
+    fn book_weights() {
+        let mut builder = BookBuilder::new();
+        builder.add_game(&tree("1. e4 e5 2. Nf3 1-0"), Chess::default(), Some("1-0"), 2);
+        builder.add_game(&tree("1. e4 c5 0-1"), Chess::default(), Some("0-1"), 2);
+        builder.add_game(&tree("1. d4 d5 1/2-1/2"), Chess::default(), Some("1/2-1/2"), 2);
+
+        let start = polyglot_key(&Chess::default());
+        let entries = builder.into_entries(1, BookWeighting::Results);
+        let root: Vec<_> = entries.iter().filter(|e| e.key == start).collect();
+        // e2e4 won once, d2d4 drew once
+        assert_eq!(root.len(), 2);
+        assert_eq!((root[0].mv, root[0].weight), ((12 << 6) | 28, 2));
+        assert_eq!((root[1].mv, root[1].weight), ((11 << 6) | 27, 1));
+        // e5 never scored and Nf3 is beyond the max ply
+        assert_eq!(entries.len(), 4);
+        assert!(entries.windows(2).all(|w| w[0].key <= w[1].key));
+    }
+
+    #[test]
+    fn min_games_cutoff() {
+        let mut builder = BookBuilder::new();
+        builder.add_game(&tree("1. e4 e5 *"), Chess::default(), None, 10);
+        builder.add_game(&tree("1. e4 c5 *"), Chess::default(), None, 10);
+
+        let entries = builder.into_entries(2, BookWeighting::Frequency);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].weight, 2);
+    }
+}
@@ -1,9 +1,10 @@
 use super::{
-    create_event, create_player, create_site, models::{Event, Game, NewGame, NormalizedGame, Outcome, Player, Site, UpdateGame}, pgn::{GameTree, Importer}, schema::{events, games, players, sites}
+    accuracy, create_event, create_player, create_site, game_hash, game_tags, game_variant, position_index, models::{Event, Game, GameTag, NewGame, NormalizedGame, Outcome, Player, Site, UpdateGame}, pgn::{GameTree, Importer}, schema::{events, games, players, sites}
 };
-use crate::error::{Result};
-use diesel::{connection::SimpleConnection, prelude::*};
-use shakmaty::{Chess, fen::Fen, CastlingMode, FromSetup};
+use crate::{error::Result, variant::GameVariant};
+use diesel::{connection::SimpleConnection, dsl::sql, prelude::*, sql_types::Bool};
+use log::warn;
+use shakmaty::{fen::Fen, variant::VariantPosition, EnPassantMode};
 use std::str::FromStr;
 use std::string::ToString;
 use pgn_reader::BufferedReader;
@@ -25,17 +26,39 @@ pub fn init_db(conn: &mut SqliteConnection, title: &str, description: &str) -> R
     Ok(())
 }
 
+/// Adds the tables introduced since a database was created, `create.sql`
+/// already has them for new databases.
+pub fn migrate(conn: &mut SqliteConnection) -> Result<()> {
+    let initialized = diesel::select(sql::<Bool>(
+        "EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'Games')",
+    ))
+    .get_result::<bool>(conn)?;
+    if !initialized {
+        return Ok(());
+    }
+
+    accuracy::ensure_table(conn)?;
+    game_variant::ensure_table(conn)?;
+    game_tags::ensure_table(conn)?;
+    game_hash::ensure_table(conn)?;
+    Ok(())
+}
+
 pub fn normalize_game(
     game: Game,
     white: Player,
     black: Player,
     event: Event,
     site: Site,
+    variant: GameVariant,
+    tags: Vec<GameTag>,
 ) -> Result<NormalizedGame> {
-    let fen: Fen = game
-        .fen
-        .map(|f| Fen::from_ascii(f.as_bytes()).unwrap())
-        .unwrap_or_default();
+    let position = variant.position(game.fen.as_deref()).unwrap_or_else(|e| {
+        warn!("Invalid FEN of game {}, using the starting position: {}", game.id, e);
+        VariantPosition::new(variant.into())
+    });
+    let fen = Fen::from_position(position.clone(), EnPassantMode::Legal);
+    let tree = GameTree::from_bytes(&game.moves, position.clone())?;
 
     Ok(NormalizedGame {
         id: game.id,
@@ -57,7 +80,10 @@ pub fn normalize_game(
         eco: game.eco,
         ply_count: game.ply_count,
         fen: fen.to_string(),
-        moves: GameTree::from_bytes(&game.moves, Some(Chess::from_setup(fen.into(), CastlingMode::Chess960)?))?.to_string()
+        annotations: tree.main_line_annotations(),
+        moves: tree.to_pgn(position)?,
+        variant: Some(variant).filter(|v| !v.is_standard()),
+        tags,
     })
 }
 
@@ -83,8 +109,10 @@ pub fn get_game(conn: &mut SqliteConnection, id: i32) -> Result<NormalizedGame>
         .inner_join(sites::table.on(games::site_id.eq(sites::id)))
         .filter(games::id.eq(id))
         .first(conn)?;
+    let variant = game_variant::get(conn, id)?;
+    let tags = game_tags::get(conn, id)?;
 
-    normalize_game(game, white, black, event, site)
+    normalize_game(game, white, black, event, site, variant, tags)
 }
 
 pub fn update_game(conn: &mut SqliteConnection, id: i32, data: &UpdateGame) -> Result<()> {
@@ -97,8 +125,10 @@ pub fn update_game(conn: &mut SqliteConnection, id: i32, data: &UpdateGame) -> R
         .ok_or(crate::error::Error::NoMovesFound)?
         .tree;
     
+    let variant = game_variant::get(conn, id)?;
+    let position = variant.position(Some(&data.fen))?;
     let mut moves: Vec<u8> = Vec::new();
-    tree.encode(&mut moves, None);
+    tree.encode(&mut moves, position.clone());
     let ply_count = tree.count_main_line_moves() as i32;
 
     diesel::update(games::dsl::games)
@@ -121,13 +151,29 @@ pub fn update_game(conn: &mut SqliteConnection, id: i32, data: &UpdateGame) -> R
             games::moves.eq(&moves)
         ))
         .execute(conn)?;
-    
+
+    if let Some(tags) = &data.tags {
+        game_tags::store(conn, id, tags)?;
+    }
+    // Hashed again by the next import skipping duplicates
+    game_hash::remove_game(conn, id)?;
+
+    if position_index::is_built(conn)? {
+        position_index::remove_game(conn, id)?;
+        position_index::index_game(conn, id, &tree, position)?;
+    }
+
     Ok(())
 }
 
 
 pub fn remove_game(conn: &mut SqliteConnection, id: i32) -> Result<()> {
     diesel::delete(games::table.filter(games::id.eq(id))).execute(conn)?;
+    position_index::remove_game(conn, id)?;
+    accuracy::remove_game(conn, id)?;
+    game_variant::remove_game(conn, id)?;
+    game_tags::remove_game(conn, id)?;
+    game_hash::remove_game(conn, id)?;
 
     Ok(())
 }
@@ -45,6 +45,48 @@ CREATE TABLE Games (
     FOREIGN KEY(BlackID) REFERENCES Players
 );
 
+CREATE TABLE PositionIndex (
+    Hash INTEGER NOT NULL,
+    GameID INTEGER NOT NULL,
+    Ply INTEGER NOT NULL,
+    PRIMARY KEY (Hash, GameID)
+) WITHOUT ROWID;
+
+CREATE TABLE GameAnalysis (
+    GameID INTEGER NOT NULL,
+    IsWhite BOOLEAN NOT NULL,
+    Accuracy REAL NOT NULL,
+    ACPL REAL NOT NULL,
+    OpeningAccuracy REAL,
+    OpeningACPL REAL,
+    MiddlegameAccuracy REAL,
+    MiddlegameACPL REAL,
+    EndgameAccuracy REAL,
+    EndgameACPL REAL,
+    PRIMARY KEY (GameID, IsWhite),
+    FOREIGN KEY(GameID) REFERENCES Games
+);
+
+CREATE TABLE GameVariants (
+    GameID INTEGER PRIMARY KEY,
+    Variant TEXT NOT NULL
+);
+
+CREATE TABLE GameTags (
+    GameID INTEGER NOT NULL,
+    Ordinal INTEGER NOT NULL,
+    Name TEXT NOT NULL,
+    Value TEXT NOT NULL,
+    PRIMARY KEY (GameID, Ordinal)
+);
+
+CREATE TABLE GameHashes (
+    GameID INTEGER PRIMARY KEY,
+    Hash INTEGER NOT NULL
+);
+
+CREATE INDEX game_hashes_hash_idx ON GameHashes(Hash);
+
 INSERT INTO Players (ID, Name, Elo) VALUES (0, 'Unknown', NULL);
 INSERT INTO Events (ID, Name) VALUES (0, 'Unknown');
 INSERT INTO Sites (ID, Name) VALUES (0, 'Unknown');
\ No newline at end of file
@@ -6,4 +6,7 @@ DROP INDEX IF EXISTS games_white_elo_idx;
 DROP INDEX IF EXISTS games_black_elo_idx;
 DROP INDEX IF EXISTS games_plycount_idx;
 
+DROP TABLE IF EXISTS PositionIndex;
+DELETE FROM Info WHERE Name = 'PositionIndex';
+
 VACUUM;
\ No newline at end of file
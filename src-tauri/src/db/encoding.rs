@@ -1,6 +1,34 @@
-use shakmaty::{Chess, Move, Position};
+use shakmaty::{Move, Position};
 
-pub fn decode_move(byte: u8, chess: &Chess) -> Option<Move> {
+/// Marks a move index that doesn't fit in a single byte, followed by the
+/// index on two bytes.
+///
+/// Standard chess never has more than 218 legal moves, but Crazyhouse drops
+/// can go past the bytes reserved for comments, NAGs and variations.
+const EXTENDED_MOVE: u8 = 250;
+
+pub fn decode_move<P: Position>(byte: u8, chess: &P) -> Option<Move> {
     let legal_moves = chess.legal_moves();
     legal_moves.get(byte as usize).cloned()
-}
\ No newline at end of file
+}
+
+/// Appends the index of a move in the list of legal moves.
+pub fn encode_move_index(bytes: &mut Vec<u8>, index: usize) {
+    if index < EXTENDED_MOVE as usize {
+        bytes.push(index as u8);
+    } else {
+        bytes.push(EXTENDED_MOVE);
+        bytes.extend((index as u16).to_be_bytes());
+    }
+}
+
+/// Reads the move at the start of `bytes`, along with the number of bytes it used.
+pub fn read_move<P: Position>(bytes: &[u8], pos: &P) -> Option<(Move, usize)> {
+    match *bytes.first()? {
+        EXTENDED_MOVE => {
+            let index = u16::from_be_bytes([*bytes.get(1)?, *bytes.get(2)?]);
+            Some((pos.legal_moves().get(index as usize).cloned()?, 3))
+        }
+        byte => Some((decode_move(byte, pos)?, 1)),
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+
+use diesel::{connection::SimpleConnection, prelude::*};
+
+use crate::{
+    db::schema::{game_hashes, games},
+    error::Result,
+};
+
+/// Hashes identifying the games, filled for the games of a database the first
+/// time a PGN is imported into it without duplicates. New databases get the
+/// table from `create.sql`.
+const CREATE_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS GameHashes (
+        GameID INTEGER PRIMARY KEY,
+        Hash INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS game_hashes_hash_idx ON GameHashes(Hash);
+";
+
+/// Games hashed at once when filling the table.
+const BACKFILL_PAGE_SIZE: i64 = 10_000;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+pub fn ensure_table(db: &mut SqliteConnection) -> Result<()> {
+    db.batch_execute(CREATE_TABLE_SQL)?;
+    Ok(())
+}
+
+/// The fields telling two games of a database apart.
+pub struct GameContent<'a> {
+    pub white_id: i32,
+    pub black_id: i32,
+    pub date: Option<&'a str>,
+    pub round: Option<&'a str>,
+    pub moves: &'a [u8],
+}
+
+impl GameContent<'_> {
+    /// FNV-1a hash of the fields, which unlike the std hasher is stable across
+    /// releases.
+    pub fn hash(&self) -> i64 {
+        // The round column has an integer affinity, which turns "01" into 1
+        let round = self.round.map(|round| {
+            round
+                .parse::<i64>()
+                .map_or(round.to_string(), |n| n.to_string())
+        });
+
+        let mut hash = FNV_OFFSET;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash = (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME);
+            }
+        };
+        write(&self.white_id.to_le_bytes());
+        write(&self.black_id.to_le_bytes());
+        for field in [self.date, round.as_deref()] {
+            match field {
+                Some(field) => {
+                    write(&[1]);
+                    write(&(field.len() as u64).to_le_bytes());
+                    write(field.as_bytes());
+                }
+                None => write(&[0]),
+            }
+        }
+        write(self.moves);
+        hash as i64
+    }
+}
+
+pub fn store_many(db: &mut SqliteConnection, hashes: &[(i32, i64)]) -> Result<()> {
+    let rows: Vec<_> = hashes
+        .iter()
+        .map(|(game_id, hash)| {
+            (
+                game_hashes::game_id.eq(*game_id),
+                game_hashes::hash.eq(*hash),
+            )
+        })
+        .collect();
+    // Keep below SQLite's bound parameter limit
+    for chunk in rows.chunks(400) {
+        diesel::replace_into(game_hashes::table)
+            .values(chunk)
+            .execute(db)?;
+    }
+    Ok(())
+}
+
+/// Returns the given hashes that some game of the database already has.
+pub fn existing(db: &mut SqliteConnection, hashes: &[i64]) -> Result<HashSet<i64>> {
+    let mut existing = HashSet::new();
+    // Keep below SQLite's bound parameter limit
+    for chunk in hashes.chunks(500) {
+        let found: Vec<i64> = game_hashes::table
+            .filter(game_hashes::hash.eq_any(chunk))
+            .select(game_hashes::hash)
+            .load(db)?;
+        existing.extend(found);
+    }
+    Ok(existing)
+}
+
+/// Hashes the games that don't have a hash yet, such as games added before
+/// the table existed or edited since.
+pub fn backfill(db: &mut SqliteConnection) -> Result<()> {
+    let mut last_id = 0;
+    loop {
+        let page: Vec<(i32, i32, i32, Option<String>, Option<String>, Vec<u8>)> = games::table
+            .left_join(game_hashes::table.on(game_hashes::game_id.eq(games::id)))
+            .filter(games::id.gt(last_id))
+            .filter(game_hashes::game_id.is_null())
+            .order(games::id)
+            .limit(BACKFILL_PAGE_SIZE)
+            .select((
+                games::id,
+                games::white_id,
+                games::black_id,
+                games::date,
+                games::round,
+                games::moves,
+            ))
+            .load(db)?;
+        let Some(&(id, ..)) = page.last() else {
+            return Ok(());
+        };
+        last_id = id;
+
+        let hashes: Vec<_> = page
+            .iter()
+            .map(|(id, white_id, black_id, date, round, moves)| {
+                let content = GameContent {
+                    white_id: *white_id,
+                    black_id: *black_id,
+                    date: date.as_deref(),
+                    round: round.as_deref(),
+                    moves,
+                };
+                (*id, content.hash())
+            })
+            .collect();
+        store_many(db, &hashes)?;
+    }
+}
+
+pub fn remove_game(db: &mut SqliteConnection, game_id: i32) -> Result<()> {
+    diesel::delete(game_hashes::table.filter(game_hashes::game_id.eq(game_id))).execute(db)?;
+    Ok(())
+}
+
+/// Removes the hashes of the games played by a player, so they're recomputed
+/// on the next backfill after the player is merged into another one.
+pub fn remove_player_games(db: &mut SqliteConnection, player_id: i32) -> Result<()> {
+    let game_ids = games::table
+        .filter(
+            games::white_id
+                .eq(player_id)
+                .or(games::black_id.eq(player_id)),
+        )
+        .select(games::id);
+    diesel::delete(game_hashes::table.filter(game_hashes::game_id.eq_any(game_ids))).execute(db)?;
+    Ok(())
+}
+
+/// Removes the hashes of games that are no longer in the database.
+pub fn prune(db: &mut SqliteConnection) -> Result<()> {
+    db.batch_execute("DELETE FROM GameHashes WHERE GameID NOT IN (SELECT ID FROM Games);")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content<'a>(round: Option<&'a str>, moves: &'a [u8]) -> GameContent<'a> {
+        GameContent {
+            white_id: 1,
+            black_id: 2,
+            date: Some("2024.01.01"),
+            round,
+            moves,
+        }
+    }
+
+    #[test]
+    fn content_hash() {
+        let hash = content(Some("1"), &[12, 8]).hash();
+        assert_eq!(hash, content(Some("01"), &[12, 8]).hash());
+        assert_ne!(hash, content(Some("1"), &[12, 9]).hash());
+        assert_ne!(hash, content(None, &[12, 8]).hash());
+        // Fields don't run into each other
+        let shifted = GameContent {
+            date: Some("2024.01.011"),
+            round: Some(""),
+            ..content(None, &[12, 8])
+        };
+        assert_ne!(content(Some("1"), &[12, 8]).hash(), shifted.hash());
+        let swapped = GameContent {
+            white_id: 2,
+            black_id: 1,
+            ..content(Some("1"), &[12, 8])
+        };
+        assert_ne!(hash, swapped.hash());
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use diesel::{connection::SimpleConnection, prelude::*};
+
+use crate::{
+    db::{models::GameTag, schema::game_tags},
+    error::Result,
+};
+
+/// Headers of the games that don't have a column of their own, in the order
+/// they appeared in the PGN. New databases get it from `create.sql`.
+const CREATE_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS GameTags (
+        GameID INTEGER NOT NULL,
+        Ordinal INTEGER NOT NULL,
+        Name TEXT NOT NULL,
+        Value TEXT NOT NULL,
+        PRIMARY KEY (GameID, Ordinal)
+    );
+";
+
+/// Headers stored in the columns of the games, or derived from them. Variants
+/// played with the standard rules, such as Chess960, are kept as tags.
+const STORED_HEADERS: &[&str] = &[
+    "Event",
+    "Site",
+    "Date",
+    "Round",
+    "White",
+    "Black",
+    "Result",
+    "WhiteElo",
+    "BlackElo",
+    "TimeControl",
+    "ECO",
+    "PlyCount",
+    "Variant",
+    "SetUp",
+    "FEN",
+];
+
+/// Whether a header has to be kept as a tag to be exported again.
+pub fn is_extra_header(name: &str) -> bool {
+    !STORED_HEADERS.contains(&name)
+}
+
+pub fn ensure_table(db: &mut SqliteConnection) -> Result<()> {
+    db.batch_execute(CREATE_TABLE_SQL)?;
+    Ok(())
+}
+
+/// Replaces the tags of a game.
+pub fn store(db: &mut SqliteConnection, game_id: i32, tags: &[GameTag]) -> Result<()> {
+    remove_game(db, game_id)?;
+    insert_many(db, &[(game_id, tags)])
+}
+
+/// Adds the tags of games that don't have any yet.
+pub fn insert_many(db: &mut SqliteConnection, games: &[(i32, &[GameTag])]) -> Result<()> {
+    let rows: Vec<_> = games
+        .iter()
+        .flat_map(|(game_id, tags)| {
+            tags.iter().enumerate().map(|(i, tag)| {
+                (
+                    game_tags::game_id.eq(*game_id),
+                    game_tags::ordinal.eq(i as i32),
+                    game_tags::name.eq(&tag.name),
+                    game_tags::value.eq(&tag.value),
+                )
+            })
+        })
+        .collect();
+    // Keep below SQLite's bound parameter limit
+    for chunk in rows.chunks(200) {
+        diesel::insert_into(game_tags::table)
+            .values(chunk)
+            .execute(db)?;
+    }
+    Ok(())
+}
+
+pub fn get(db: &mut SqliteConnection, game_id: i32) -> Result<Vec<GameTag>> {
+    let rows: Vec<(String, String)> = game_tags::table
+        .filter(game_tags::game_id.eq(game_id))
+        .order(game_tags::ordinal)
+        .select((game_tags::name, game_tags::value))
+        .load(db)?;
+    Ok(rows
+        .into_iter()
+        .map(|(name, value)| GameTag { name, value })
+        .collect())
+}
+
+fn group(rows: Vec<(i32, String, String)>, tags: &mut HashMap<i32, Vec<GameTag>>) {
+    for (id, name, value) in rows {
+        tags.entry(id).or_default().push(GameTag { name, value });
+    }
+}
+
+/// Returns the tags of the given games that have any.
+pub fn load_many(
+    db: &mut SqliteConnection,
+    game_ids: &[i32],
+) -> Result<HashMap<i32, Vec<GameTag>>> {
+    let mut tags = HashMap::new();
+    // Keep below SQLite's bound parameter limit
+    for ids in game_ids.chunks(500) {
+        let rows = game_tags::table
+            .filter(game_tags::game_id.eq_any(ids))
+            .order((game_tags::game_id, game_tags::ordinal))
+            .select((game_tags::game_id, game_tags::name, game_tags::value))
+            .load(db)?;
+        group(rows, &mut tags);
+    }
+    Ok(tags)
+}
+
+pub fn remove_game(db: &mut SqliteConnection, game_id: i32) -> Result<()> {
+    diesel::delete(game_tags::table.filter(game_tags::game_id.eq(game_id))).execute(db)?;
+    Ok(())
+}
+
+/// Removes the tags of games that are no longer in the database.
+pub fn prune(db: &mut SqliteConnection) -> Result<()> {
+    db.batch_execute("DELETE FROM GameTags WHERE GameID NOT IN (SELECT ID FROM Games);")?;
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use diesel::{connection::SimpleConnection, prelude::*};
+
+use crate::{db::schema::game_variants, error::Result, variant::GameVariant};
+
+/// Games without a row are standard chess games, which keeps the table empty
+/// for most databases. New databases get it from `create.sql`.
+const CREATE_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS GameVariants (
+        GameID INTEGER PRIMARY KEY,
+        Variant TEXT NOT NULL
+    );
+";
+
+pub fn ensure_table(db: &mut SqliteConnection) -> Result<()> {
+    db.batch_execute(CREATE_TABLE_SQL)?;
+    Ok(())
+}
+
+/// Records the variant of a game.
+pub fn store(db: &mut SqliteConnection, game_id: i32, variant: GameVariant) -> Result<()> {
+    if variant.is_standard() {
+        return remove_game(db, game_id);
+    }
+    diesel::replace_into(game_variants::table)
+        .values((
+            game_variants::game_id.eq(game_id),
+            game_variants::variant.eq(variant.pgn_name()),
+        ))
+        .execute(db)?;
+    Ok(())
+}
+
+pub fn get(db: &mut SqliteConnection, game_id: i32) -> Result<GameVariant> {
+    let variant: Option<String> = game_variants::table
+        .filter(game_variants::game_id.eq(game_id))
+        .select(game_variants::variant)
+        .first(db)
+        .optional()?;
+    Ok(variant
+        .and_then(|v| GameVariant::from_pgn(&v))
+        .unwrap_or_default())
+}
+
+/// Returns the variant of the given games that aren't standard chess.
+pub fn load_many(db: &mut SqliteConnection, game_ids: &[i32]) -> Result<HashMap<i32, GameVariant>> {
+    let mut variants = HashMap::new();
+    // Keep below SQLite's bound parameter limit
+    for ids in game_ids.chunks(500) {
+        let rows: Vec<(i32, String)> = game_variants::table
+            .filter(game_variants::game_id.eq_any(ids))
+            .select((game_variants::game_id, game_variants::variant))
+            .load(db)?;
+        variants.extend(
+            rows.into_iter()
+                .filter_map(|(id, variant)| Some((id, GameVariant::from_pgn(&variant)?))),
+        );
+    }
+    Ok(variants)
+}
+
+/// Returns the variant of every game that isn't standard chess.
+pub fn load_all(db: &mut SqliteConnection) -> Result<HashMap<i32, GameVariant>> {
+    let rows: Vec<(i32, String)> = game_variants::table
+        .select((game_variants::game_id, game_variants::variant))
+        .load(db)?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, variant)| Some((id, GameVariant::from_pgn(&variant)?)))
+        .collect())
+}
+
+pub fn remove_game(db: &mut SqliteConnection, game_id: i32) -> Result<()> {
+    diesel::delete(game_variants::table.filter(game_variants::game_id.eq(game_id))).execute(db)?;
+    Ok(())
+}
+
+/// Removes the variants of games that are no longer in the database.
+pub fn prune(db: &mut SqliteConnection) -> Result<()> {
+    db.batch_execute("DELETE FROM GameVariants WHERE GameID NOT IN (SELECT ID FROM Games);")?;
+    Ok(())
+}
@@ -0,0 +1,504 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+};
+
+use diesel::{dsl::sql, prelude::*, sql_types::BigInt};
+use pgn_reader::BufferedReader;
+use rayon::prelude::*;
+
+use crate::{
+    db::{
+        create_event, create_player, create_site,
+        game_hash::{self, GameContent},
+        game_tags, game_variant,
+        import_report::{ImportReport, SkipReason},
+        models::GameTag,
+        new_game,
+        pgn::{Importer, TempGame},
+        position_index,
+        schema::games,
+    },
+    error::Result,
+};
+
+/// Games sent at once from one stage of the import to the next.
+const BATCH_SIZE: usize = 512;
+
+/// Batches waiting between two stages, which bounds the memory used when the
+/// database is slower than the parsing.
+const QUEUED_BATCHES: usize = 8;
+
+/// Games inserted per statement, to stay below SQLite's bound parameter limit.
+const INSERT_CHUNK_SIZE: usize = 50;
+
+/// Names kept in each cache before it's cleared.
+const MAX_CACHED_NAMES: usize = 1_000_000;
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+
+const RESULTS: &[&[u8]] = &[b"1-0", b"0-1", b"1/2-1/2", b"*"];
+
+/// The text of a game, or of several games missing both their headers and
+/// their results.
+struct RawGame {
+    /// In bytes, from the start of the uncompressed file
+    offset: u64,
+    text: Vec<u8>,
+}
+
+struct ParsedGame {
+    offset: u64,
+    game: std::result::Result<TempGame, (SkipReason, Vec<GameTag>)>,
+}
+
+/// Whether a line of movetext ends with the result of the game, outside of
+/// comments. `in_comment` carries `{}` comments over to the next line.
+fn ends_game(line: &[u8], in_comment: &mut bool) -> bool {
+    let mut text = Vec::with_capacity(line.len());
+    for &byte in line {
+        match byte {
+            b'}' if *in_comment => {
+                *in_comment = false;
+                text.push(b' ');
+            }
+            _ if *in_comment => {}
+            b'{' => {
+                *in_comment = true;
+                text.push(b' ');
+            }
+            // The rest of the line is a comment
+            b';' => break,
+            _ => text.push(byte),
+        }
+    }
+    let text = text.trim_ascii_end();
+    RESULTS.iter().any(|result| {
+        text.strip_suffix(*result)
+            .is_some_and(|rest| rest.last().is_none_or(u8::is_ascii_whitespace))
+    })
+}
+
+/// Splits a PGN file into games, starting a new one at the first header
+/// following some movetext, or after the result of a game without headers.
+fn split_games(mut reader: impl BufRead, sender: SyncSender<io::Result<Vec<RawGame>>>) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut game = RawGame {
+        offset: 0,
+        text: Vec::new(),
+    };
+    let mut offset = 0;
+    let mut in_movetext = false;
+    let mut in_comment = false;
+    let mut game_over = false;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let read = match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) => {
+                if !game.text.is_empty() {
+                    batch.push(game);
+                }
+                let _ = sender.send(Ok(batch));
+                let _ = sender.send(Err(e));
+                return;
+            }
+        };
+        if offset == 0 && line.starts_with(UTF8_BOM) {
+            line.drain(..UTF8_BOM.len());
+        }
+
+        let trimmed = line.trim_ascii_start();
+        // Comments may wrap on lines starting with a bracket, such as `[%clk]`
+        let is_header = !in_comment && trimmed.starts_with(b"[");
+        if (is_header && in_movetext) || (game_over && !trimmed.is_empty()) {
+            let next = RawGame {
+                offset,
+                text: Vec::new(),
+            };
+            batch.push(std::mem::replace(&mut game, next));
+            in_movetext = false;
+            in_comment = false;
+            game_over = false;
+            if batch.len() == BATCH_SIZE {
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                if sender.send(Ok(full)).is_err() {
+                    // The import was aborted
+                    return;
+                }
+            }
+        }
+        if !is_header && !trimmed.is_empty() {
+            in_movetext = true;
+            game_over = ends_game(trimmed, &mut in_comment);
+        }
+
+        game.text.extend_from_slice(&line);
+        offset += read as u64;
+    }
+
+    if !game.text.is_empty() {
+        batch.push(game);
+    }
+    let _ = sender.send(Ok(batch));
+}
+
+fn parse_game(importer: &mut Importer, raw: &RawGame) -> Vec<ParsedGame> {
+    let mut reader = BufferedReader::new_cursor(&raw.text[..]);
+    let mut games = Vec::new();
+    loop {
+        let game = match reader.read_game(importer) {
+            Ok(Some(Some(game))) => Ok(game),
+            Ok(Some(None)) => match importer.take_skipped() {
+                Some(skipped) => Err(skipped),
+                None => continue,
+            },
+            Ok(None) => break,
+            Err(e) => {
+                games.push(ParsedGame {
+                    offset: raw.offset,
+                    game: Err((SkipReason::Unreadable(e.to_string()), Vec::new())),
+                });
+                break;
+            }
+        };
+        games.push(ParsedGame {
+            offset: raw.offset,
+            game,
+        });
+    }
+    games
+}
+
+/// Parses the batches of games on the rayon pool, keeping their order.
+fn parse_games(
+    receiver: Receiver<io::Result<Vec<RawGame>>>,
+    sender: SyncSender<Vec<ParsedGame>>,
+    timestamp: Option<i64>,
+) {
+    for batch in receiver {
+        let parsed = match batch {
+            Ok(batch) => batch
+                .par_iter()
+                .map_init(|| Importer::new(timestamp), parse_game)
+                .flatten()
+                .collect(),
+            Err(e) => vec![ParsedGame {
+                offset: 0,
+                game: Err((SkipReason::Unreadable(e.to_string()), Vec::new())),
+            }],
+        };
+        if sender.send(parsed).is_err() {
+            return;
+        }
+    }
+}
+
+/// IDs of the players, events and sites already in the database.
+#[derive(Default)]
+struct NameCache {
+    players: HashMap<String, i32>,
+    events: HashMap<String, i32>,
+    sites: HashMap<String, i32>,
+}
+
+fn cached_id(
+    db: &mut SqliteConnection,
+    ids: &mut HashMap<String, i32>,
+    name: Option<&str>,
+    create: fn(&mut SqliteConnection, &str) -> QueryResult<i32>,
+) -> Result<i32> {
+    let Some(name) = name else {
+        return Ok(0);
+    };
+    if let Some(id) = ids.get(name) {
+        return Ok(*id);
+    }
+    let id = create(db, name)?;
+    if ids.len() == MAX_CACHED_NAMES {
+        ids.clear();
+    }
+    ids.insert(name.to_string(), id);
+    Ok(id)
+}
+
+/// Inserts a batch of games, and returns their IDs in the same order, or
+/// `None` for the duplicates that were skipped.
+fn insert_games(
+    db: &mut SqliteConnection,
+    cache: &mut NameCache,
+    games: &[TempGame],
+    skip_duplicates: bool,
+) -> Result<Vec<Option<i32>>> {
+    let mut rows = Vec::with_capacity(games.len());
+    for game in games {
+        let white_id = cached_id(
+            db,
+            &mut cache.players,
+            game.white_name.as_deref(),
+            |db, name| Ok(create_player(db, name)?.id),
+        )?;
+        let black_id = cached_id(
+            db,
+            &mut cache.players,
+            game.black_name.as_deref(),
+            |db, name| Ok(create_player(db, name)?.id),
+        )?;
+        let event_id = cached_id(
+            db,
+            &mut cache.events,
+            game.event_name.as_deref(),
+            |db, name| Ok(create_event(db, name)?.id),
+        )?;
+        let site_id = cached_id(
+            db,
+            &mut cache.sites,
+            game.site_name.as_deref(),
+            |db, name| Ok(create_site(db, name)?.id),
+        )?;
+        rows.push(new_game(game, white_id, black_id, event_id, site_id));
+    }
+
+    let mut hashes = Vec::new();
+    let mut keep = vec![true; rows.len()];
+    if skip_duplicates {
+        hashes = rows
+            .iter()
+            .map(|row| {
+                let content = GameContent {
+                    white_id: row.white_id,
+                    black_id: row.black_id,
+                    date: row.date,
+                    round: row.round,
+                    moves: row.moves,
+                };
+                content.hash()
+            })
+            .collect();
+        let existing = game_hash::existing(db, &hashes)?;
+        // Games repeated within the batch are duplicates too
+        let mut seen = HashSet::new();
+        for (keep, hash) in keep.iter_mut().zip(&hashes) {
+            *keep = !existing.contains(hash) && seen.insert(*hash);
+        }
+    }
+    let rows: Vec<_> = rows
+        .into_iter()
+        .zip(&keep)
+        .filter_map(|(row, keep)| keep.then_some(row))
+        .collect();
+
+    let mut ids = Vec::with_capacity(rows.len());
+    for chunk in rows.chunks(INSERT_CHUNK_SIZE) {
+        diesel::insert_into(games::table)
+            .values(chunk)
+            .execute(db)?;
+        // The rows of a single statement get consecutive IDs, ending at the last one
+        let last: i64 = diesel::select(sql::<BigInt>("last_insert_rowid()")).get_result(db)?;
+        let last = last as i32;
+        ids.extend(last + 1 - chunk.len() as i32..=last);
+    }
+
+    let mut ids = ids.into_iter();
+    let ids: Vec<_> = keep
+        .iter()
+        .map(|keep| if *keep { ids.next() } else { None })
+        .collect();
+    if skip_duplicates {
+        let hashes: Vec<_> = ids
+            .iter()
+            .zip(&hashes)
+            .filter_map(|(id, hash)| Some(((*id)?, *hash)))
+            .collect();
+        game_hash::store_many(db, &hashes)?;
+    }
+    Ok(ids)
+}
+
+/// Imports the games of a PGN file into the database, in a single transaction
+/// if the caller opened one.
+///
+/// Games are split from the file on a separate thread, parsed and encoded on
+/// the rayon pool, and inserted in batches on the calling thread.
+/// `on_progress` is called with the number of games read so far.
+///
+/// With `skip_duplicates`, games with the same players, date, round and moves
+/// as a game of the database, or an earlier game of the file, are skipped.
+pub fn import_games(
+    db: &mut SqliteConnection,
+    reader: impl BufRead + Send,
+    timestamp: Option<i64>,
+    index_positions: bool,
+    skip_duplicates: bool,
+    mut on_progress: impl FnMut(u32),
+) -> Result<ImportReport> {
+    if skip_duplicates {
+        game_hash::backfill(db)?;
+    }
+
+    let (raw_sender, raw_receiver) = sync_channel(QUEUED_BATCHES);
+    let (parsed_sender, parsed_receiver) = sync_channel(QUEUED_BATCHES);
+    let mut report = ImportReport::default();
+    let mut cache = NameCache::default();
+
+    std::thread::scope(|scope| {
+        scope.spawn(move || split_games(reader, raw_sender));
+        scope.spawn(move || parse_games(raw_receiver, parsed_sender, timestamp));
+
+        // Returning early drops the receiver, which stops the other threads
+        for batch in parsed_receiver {
+            let before = report.games();
+            let mut games = Vec::with_capacity(batch.len());
+            for parsed in batch {
+                let index = report.games() + games.len() as u32 + 1;
+                match parsed.game {
+                    Ok(game) => games.push(game),
+                    Err((reason, headers)) => report.skip(index, parsed.offset, reason, headers),
+                }
+            }
+
+            let ids = insert_games(db, &mut cache, &games, skip_duplicates)?;
+            let mut tags = Vec::new();
+            for (game, id) in games.iter().zip(ids) {
+                let Some(id) = id else {
+                    report.duplicates += 1;
+                    continue;
+                };
+                report.imported += 1;
+                if !game.variant.is_standard() {
+                    game_variant::store(db, id, game.variant)?;
+                }
+                if !game.tags.is_empty() {
+                    tags.push((id, game.tags.as_slice()));
+                }
+                if index_positions {
+                    position_index::index_game(db, id, &game.tree, game.position.clone())?;
+                }
+            }
+            game_tags::insert_many(db, &tags)?;
+
+            if before / 1000 != report.games() / 1000 {
+                on_progress(report.games());
+            }
+        }
+        Ok(report)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{core::init_db, schema::position_index},
+        variant::GameVariant,
+    };
+
+    fn split(pgn: &str) -> Vec<(u64, String)> {
+        let (sender, receiver) = sync_channel(QUEUED_BATCHES);
+        split_games(pgn.as_bytes(), sender);
+        receiver
+            .into_iter()
+            .flat_map(|batch| batch.unwrap())
+            .map(|game| (game.offset, String::from_utf8(game.text).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn split_pgn() {
+        let first = "[Event \"A\"]\n\n1. e4 {Long comment\n[%clk 0:03:00]} e5 *\n\n";
+        let second = "[Event \"B\"]\n[Site \"?\"]\n\n1. d4 *\n";
+        let games = split(&format!("\u{feff}{first}{second}"));
+        assert_eq!(
+            games,
+            vec![
+                (0, first.to_string()),
+                (first.len() as u64 + 3, second.to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn import_pgn() {
+        let mut db = SqliteConnection::establish(":memory:").unwrap();
+        init_db(&mut db, "Test", "Test").unwrap();
+
+        let first = "[Event \"A\"]\n[Annotator \"Z\"]\n\n1. e4 e5 1-0\n\n";
+        let second = "[Event \"B\"]\n[Variant \"Atomic\"]\n\n1. e4 d5 *\n\n";
+        let third = "[Event \"C\"]\n\n1. e4 e4 *\n";
+        let pgn = format!("{first}{second}{third}");
+        let report = import_games(&mut db, pgn.as_bytes(), None, true, false, |_| {}).unwrap();
+
+        assert_eq!((report.imported, report.skipped), (2, 1));
+        let skipped = &report.skipped_games[0];
+        assert_eq!(skipped.index, 3);
+        assert_eq!(skipped.offset, (first.len() + second.len()) as u64);
+        assert_eq!(
+            skipped.reason,
+            SkipReason::IllegalMove {
+                ply: 1,
+                san: "e4".to_string()
+            }
+        );
+
+        let ids: Vec<i32> = games::table
+            .select(games::id)
+            .order(games::id)
+            .load(&mut db)
+            .unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(
+            game_tags::get(&mut db, ids[0]).unwrap(),
+            vec![GameTag {
+                name: "Annotator".to_string(),
+                value: "Z".to_string()
+            }]
+        );
+        assert_eq!(
+            game_variant::get(&mut db, ids[0]).unwrap(),
+            GameVariant::Standard
+        );
+        assert_eq!(
+            game_variant::get(&mut db, ids[1]).unwrap(),
+            GameVariant::Atomic
+        );
+        let indexed: i64 = position_index::table
+            .filter(position_index::game_id.eq(ids[1]))
+            .count()
+            .get_result(&mut db)
+            .unwrap();
+        assert_eq!(indexed, 3);
+    }
+
+    #[test]
+    fn split_games_without_headers() {
+        let first = "1. e4 e5 *\n\n";
+        let second = "1. d4 {White wins 1-0\nin the end} d5 1/2-1/2\n";
+        let games = split(&format!("{first}{second}"));
+        assert_eq!(
+            games,
+            vec![
+                (0, first.to_string()),
+                (first.len() as u64, second.to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn split_games_wrapped_comment() {
+        let first =
+            "[Event \"A\"]\n\n1. e4 {A note\n[White \"quoted\"] and\n[%clk 0:01:00]} e5 1-0\n\n";
+        let second = "[Event \"B\"]\n\n1. d4 *\n";
+        let games = split(&format!("{first}{second}"));
+        assert_eq!(
+            games,
+            vec![
+                (0, first.to_string()),
+                (first.len() as u64, second.to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use log::warn;
+use serde::Serialize;
+use specta::Type;
+
+use crate::{db::models::GameTag, error::Result};
+
+/// Skipped games listed in a report, the others are only counted.
+const MAX_REPORTED_GAMES: usize = 1000;
+
+/// Why a game of a PGN file wasn't imported.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Type)]
+#[serde(tag = "t", content = "c")]
+pub enum SkipReason {
+    /// Played before the timestamp of the import, which isn't an error
+    BeforeTimestamp,
+    UnsupportedVariant(String),
+    InvalidFen(String),
+    /// A move of the main line, `ply` counting from 0
+    IllegalMove {
+        ply: u32,
+        san: String,
+    },
+    /// The rest of the file couldn't be read
+    Unreadable(String),
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedGame {
+    /// Position of the game in the file, counting from 1
+    pub index: u32,
+    /// In bytes, from the start of the uncompressed file
+    pub offset: u64,
+    pub headers: Vec<GameTag>,
+    pub reason: SkipReason,
+}
+
+/// What became of the games of an imported PGN file.
+#[derive(Serialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    /// New games added to the database
+    pub imported: u32,
+    /// Games already in the database, when skipping them
+    pub duplicates: u32,
+    pub skipped: u32,
+    /// Games older than the timestamp of the import
+    pub filtered: u32,
+    /// The first skipped games, with their headers
+    pub skipped_games: Vec<SkippedGame>,
+}
+
+impl ImportReport {
+    /// Number of games read so far.
+    pub fn games(&self) -> u32 {
+        self.imported + self.duplicates + self.skipped + self.filtered
+    }
+
+    pub fn skip(&mut self, index: u32, offset: u64, reason: SkipReason, headers: Vec<GameTag>) {
+        if reason == SkipReason::BeforeTimestamp {
+            self.filtered += 1;
+            return;
+        }
+        warn!(
+            "Skipping game {} of the PGN at byte {}: {:?}",
+            index, offset, reason
+        );
+        self.skipped += 1;
+        if self.skipped_games.len() < MAX_REPORTED_GAMES {
+            self.skipped_games.push(SkippedGame {
+                index,
+                offset,
+                headers,
+                reason,
+            });
+        }
+    }
+
+    /// Writes the report as JSON, for instance next to the imported file.
+    pub fn save(&self, file: &Path) -> Result<()> {
+        std::fs::write(file, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
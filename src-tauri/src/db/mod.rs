@@ -1,10 +1,20 @@
+mod accuracy;
+mod annotation;
+mod book;
 mod encoding;
+mod game_hash;
+mod game_tags;
+mod game_variant;
+mod import;
+mod import_report;
 mod models;
+mod move_annotations;
 mod ops;
 mod schema;
 mod search;
 mod core;
 mod pgn;
+mod position_index;
 
 use crate::{
     db::{
@@ -15,11 +25,12 @@ use crate::{
     },
     error::{Error, Result},
     opening::get_opening_from_setup,
+    variant::GameVariant,
     AppState,
 };
 use dashmap::DashMap;
 use diesel::{
-    connection::{DefaultLoadingMode, SimpleConnection},
+    connection::SimpleConnection,
     insert_into,
     prelude::*,
     r2d2::{ConnectionManager, Pool},
@@ -27,16 +38,17 @@ use diesel::{
     sql_types::Text,
 };
 use pgn_reader::{BufferedReader};
+use import_report::ImportReport;
 use pgn::{GameTree, Importer, TempGame};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use shakmaty::{
-    fen::Fen, Board, Chess, EnPassantMode, Piece, Position, FromSetup, CastlingMode
+    Board, Chess, EnPassantMode, Piece, Position
 };
 use specta::Type;
 use std::{
     fs::{remove_file, File, OpenOptions},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicUsize, Ordering},
     time::{Duration, Instant},
 };
@@ -44,20 +56,27 @@ use std::io::{BufWriter, Write};
 use tauri::{path::BaseDirectory, Manager};
 use tauri::{Emitter, State};
 
-use log::info;
+use log::{info, warn};
 use tauri_specta::Event as _;
 
+pub use self::accuracy::analyze_game_accuracy;
+pub use self::annotation::annotate_game;
+pub use self::book::export_polyglot_book;
 pub use self::models::NormalizedGame;
 pub use self::models::Puzzle;
 pub use self::schema::puzzles;
 pub use self::search::{
-    is_position_in_db, search_position, PositionQuery, PositionQueryJs, PositionStats,
+    explore_position, is_position_in_db, search_position, PositionQuery, PositionQueryJs,
+    PositionStats,
 };
 
 const INDEXES_SQL: &str = include_str!("indexes.sql");
 
 const DELETE_INDEXES_SQL: &str = include_str!("delete_indexes.sql");
 
+/// Games written at once by the PGN export.
+const EXPORT_BATCH_SIZE: i64 = 1000;
+
 const WHITE_PAWN: Piece = Piece {
     color: shakmaty::Color::White,
     role: shakmaty::Role::Pawn,
@@ -134,6 +153,7 @@ fn get_db_or_create(
                 .max_size(16)
                 .connection_customizer(Box::new(options))
                 .build(ConnectionManager::<SqliteConnection>::new(db_path))?;
+            core::migrate(&mut pool.get()?)?;
             state
                 .connection_pool
                 .insert(db_path.to_string(), pool.clone());
@@ -151,9 +171,8 @@ pub struct TempPlayer {
     rating: Option<i32>,
 }
 
-pub fn insert_to_db(db: &mut SqliteConnection, game: &TempGame) -> Result<()> {
-    let pawn_home = get_pawn_home(game.position.board());
-
+/// Inserts a game into the database and returns its ID.
+pub fn insert_to_db(db: &mut SqliteConnection, game: &TempGame) -> Result<i32> {
     let white_id = if let Some(name) = &game.white_name {
         create_player(db, name)?.id
     } else {
@@ -178,12 +197,33 @@ pub fn insert_to_db(db: &mut SqliteConnection, game: &TempGame) -> Result<()> {
         0
     };
 
+    let new_game = new_game(game, white_id, black_id, event_id, site_id);
+    let game_id = core::add_game(db, new_game)?.id;
+    if !game.variant.is_standard() {
+        game_variant::store(db, game_id, game.variant)?;
+    }
+    if !game.tags.is_empty() {
+        game_tags::store(db, game_id, &game.tags)?;
+    }
+
+    Ok(game_id)
+}
+
+/// Builds the row of a game whose players, event and site are already in the database.
+fn new_game(
+    game: &TempGame,
+    white_id: i32,
+    black_id: i32,
+    event_id: i32,
+    site_id: i32,
+) -> NewGame<'_> {
+    let pawn_home = get_pawn_home(game.position.board());
     let ply_count = game.tree.count_main_line_moves() as i32;
     let final_material = pgn::get_material_count(game.position.board());
     let minimal_white_material = game.material_count.white.min(final_material.white) as i32;
     let minimal_black_material = game.material_count.black.min(final_material.black) as i32;
 
-    let new_game = NewGame {
+    NewGame {
         white_id,
         black_id,
         ply_count,
@@ -203,11 +243,7 @@ pub fn insert_to_db(db: &mut SqliteConnection, game: &TempGame) -> Result<()> {
         result: game.result.as_deref(),
         moves: game.moves.as_slice(),
         pawn_home: pawn_home as i32,
-    };
-
-    core::add_game(db, new_game)?;
-
-    Ok(())
+    }
 }
 
 #[tauri::command]
@@ -219,8 +255,10 @@ pub async fn convert_pgn(
     app: tauri::AppHandle,
     title: String,
     description: Option<String>,
+    report_file: Option<PathBuf>,
+    skip_duplicates: Option<bool>,
     state: tauri::State<'_, AppState>,
-) -> Result<()> {
+) -> Result<ImportReport> {
     let description = description.unwrap_or_default();
     let extension = file.extension();
 
@@ -251,29 +289,36 @@ pub async fn convert_pgn(
         Box::new(file)
     };
 
+    // Keep the position index up to date if the database has one, new databases always get one
+    let index_positions = !db_exists || position_index::is_built(db)?;
+
     // start counting time
     let start = Instant::now();
 
-    let mut importer = Importer::new(timestamp.map(|t| t as i64));
-    db.transaction::<_, Error, _>(|db| {
-        for (i, game) in BufferedReader::new(uncompressed)
-            .into_iter(&mut importer)
-            .flatten()
-            .flatten()
-            .enumerate()
-        {
-            if i % 1000 == 0 {
+    app.emit("convert_progress", (0, 0)).unwrap();
+    let reader = std::io::BufReader::new(uncompressed);
+    let report = db.transaction::<_, Error, _>(|db| {
+        import::import_games(
+            db,
+            reader,
+            timestamp.map(|t| t as i64),
+            index_positions,
+            skip_duplicates.unwrap_or(false),
+            |games| {
                 let elapsed = start.elapsed().as_millis() as u32;
-                app.emit("convert_progress", (i, elapsed)).unwrap();
-            }
-            insert_to_db(db, &game)?;
-        }
-        Ok(())
+                app.emit("convert_progress", (games, elapsed)).unwrap();
+            },
+        )
     })?;
+    info!(
+        "Imported {} games, skipped {} and {} duplicates, filtered {}",
+        report.imported, report.skipped, report.duplicates, report.filtered
+    );
 
     if !db_exists {
         // Create all the necessary indexes
         db.batch_execute(INDEXES_SQL)?;
+        position_index::mark_built(db)?;
     }
 
     // get game, player, event and site counts and to the info table
@@ -298,7 +343,54 @@ pub async fn convert_pgn(
             .execute(db)?;
     }
 
-    Ok(())
+    if let Some(report_file) = report_file {
+        report.save(&report_file)?;
+    }
+
+    Ok(report)
+}
+
+/// Adds the games of a PGN string to a database, creating the database if needed.
+///
+/// Returns the number of games added.
+pub fn add_pgn_games(
+    state: &State<AppState>,
+    db_path: &Path,
+    title: &str,
+    pgn: &str,
+) -> Result<usize> {
+    let db_exists = db_path.exists();
+    let db = &mut get_db_or_create(state, db_path.to_str().unwrap(), ConnectionOptions::default())?;
+
+    if !db_exists {
+        core::init_db(db, title, "")?;
+        db.batch_execute(INDEXES_SQL)?;
+        position_index::mark_built(db)?;
+    }
+    let index_positions = position_index::is_built(db)?;
+
+    let mut importer = Importer::new(None);
+    let count = db.transaction::<_, Error, _>(|db| {
+        let mut count = 0;
+        for game in BufferedReader::new_cursor(pgn.as_bytes())
+            .into_iter(&mut importer)
+            .flatten()
+            .flatten()
+        {
+            let game_id = insert_to_db(db, &game)?;
+            if index_positions {
+                position_index::index_game(db, game_id, &game.tree, game.position.clone())?;
+            }
+            count += 1;
+        }
+        Ok(count)
+    })?;
+
+    // Cached search results don't include the new games
+    state.db_cache.lock().unwrap().clear();
+    state.line_cache.retain(|(_, file), _| file != db_path);
+
+    Ok(count)
 }
 
 #[derive(Serialize, Type)]
@@ -385,6 +477,10 @@ pub async fn create_indexes(file: PathBuf, state: tauri::State<'_, AppState>) ->
 
     db.batch_execute(INDEXES_SQL)?;
 
+    if !position_index::is_built(db)? {
+        position_index::build(db)?;
+    }
+
     Ok(())
 }
 
@@ -723,7 +819,7 @@ pub async fn get_games(
     }
 
     let games: Vec<(Game, Player, Player, Event, Site)> = sql_query.load(db)?;
-    let normalized_games = normalize_games(games)?;
+    let normalized_games = normalize_games(db, games)?;
 
     Ok(QueryResponse {
         data: normalized_games,
@@ -731,10 +827,20 @@ pub async fn get_games(
     })
 }
 
-fn normalize_games(games: Vec<(Game, Player, Player, Event, Site)>) -> Result<Vec<NormalizedGame>> {
+fn normalize_games(
+    db: &mut SqliteConnection,
+    games: Vec<(Game, Player, Player, Event, Site)>,
+) -> Result<Vec<NormalizedGame>> {
+    let ids: Vec<i32> = games.iter().map(|(game, ..)| game.id).collect();
+    let variants = game_variant::load_many(db, &ids)?;
+    let mut tags = game_tags::load_many(db, &ids)?;
     games
         .into_iter()
-        .map(|(game, white, black, event, site)| core::normalize_game(game, white, black, event, site))
+        .map(|(game, white, black, event, site)| {
+            let variant = variants.get(&game.id).copied().unwrap_or_default();
+            let tags = tags.remove(&game.id).unwrap_or_default();
+            core::normalize_game(game, white, black, event, site, variant, tags)
+        })
         .collect::<Result<_>>()
 }
 
@@ -945,6 +1051,8 @@ pub struct StatsData {
     pub result: GameOutcome,
     pub time_control: String,
     pub opening: String,
+    pub accuracy: Option<f64>,
+    pub acpl: Option<f64>,
 }
 
 #[derive(Serialize, Debug, Clone, Type, tauri_specta::Event)]
@@ -968,6 +1076,7 @@ pub async fn get_players_game_info(
         .inner_join(sites::table.on(games::site_id.eq(sites::id)))
         .inner_join(players::table.on(players::id.eq(id)))
         .select((
+            games::id,
             games::white_id,
             games::black_id,
             games::result,
@@ -983,6 +1092,7 @@ pub async fn get_players_game_info(
         .filter(games::fen.is_null());
 
     type GameInfo = (
+        i32,
         i32,
         i32,
         Option<String>,
@@ -995,6 +1105,10 @@ pub async fn get_players_game_info(
         Option<String>,
     );
     let info: Vec<GameInfo> = sql_query.load(db)?;
+    let accuracies = accuracy::player_accuracies(db, id)?;
+    // Like games from a custom position, their openings can't be looked up
+    let game_ids: Vec<i32> = info.iter().map(|game| game.0).collect();
+    let variants = game_variant::load_many(db, &game_ids)?;
 
     let mut game_info = PlayerGameInfo::default();
     let progress = AtomicUsize::new(0);
@@ -1002,6 +1116,7 @@ pub async fn get_players_game_info(
         .par_iter()
         .filter_map(
             |(
+                game_id,
                 white_id,
                 black_id,
                 outcome,
@@ -1018,6 +1133,7 @@ pub async fn get_players_game_info(
                 let result = GameOutcome::from_str(outcome.as_deref()?, is_white);
 
                 if !is_white && !is_black
+                    || variants.contains_key(game_id)
                     || is_white && white_elo.is_none()
                     || is_black && black_elo.is_none()
                     || result.is_none()
@@ -1080,6 +1196,8 @@ pub async fn get_players_game_info(
                         result: result.unwrap(),
                         time_control: time_control.clone().unwrap_or_default(),
                         opening,
+                        accuracy: accuracies.get(game_id).map(|(accuracy, _)| *accuracy),
+                        acpl: accuracies.get(game_id).map(|(_, acpl)| *acpl),
                     }],
                 })
             },
@@ -1150,6 +1268,11 @@ pub async fn delete_duplicated_games(
         );
         ",
     )?;
+    position_index::prune(db)?;
+    accuracy::prune(db)?;
+    game_variant::prune(db)?;
+    game_tags::prune(db)?;
+    game_hash::prune(db)?;
 
     Ok(())
 }
@@ -1163,6 +1286,11 @@ pub async fn delete_empty_games(
     let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
 
     diesel::delete(games::table.filter(games::ply_count.eq(0))).execute(db)?;
+    position_index::prune(db)?;
+    accuracy::prune(db)?;
+    game_variant::prune(db)?;
+    game_tags::prune(db)?;
+    game_hash::prune(db)?;
 
     Ok(())
 }
@@ -1181,6 +1309,8 @@ struct PgnGame {
     black_elo: Option<String>,
     ply_count: Option<String>,
     fen: Option<String>,
+    variant: GameVariant,
+    tags: Vec<GameTag>,
     moves: String,
 }
 
@@ -1228,10 +1358,17 @@ impl PgnGame {
         if let Some(ply_count) = self.ply_count.as_deref() {
             writeln!(writer, "[PlyCount \"{}\"]", ply_count)?;
         }
+        if !self.variant.is_standard() {
+            writeln!(writer, "[Variant \"{}\"]", self.variant.pgn_name())?;
+        }
         if let Some(fen) = self.fen.as_deref() {
             writeln!(writer, "[SetUp \"1\"]")?;
             writeln!(writer, "[FEN \"{}\"]", fen)?;
         }
+        for tag in &self.tags {
+            let value = tag.value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(writer, "[{} \"{}\"]", tag.name, value)?;
+        }
         writeln!(writer)?;
         writer.write(self.moves.as_bytes())?;
         match self.result.as_deref() {
@@ -1263,14 +1400,36 @@ pub async fn export_to_pgn(
     let mut writer = BufWriter::new(file);
 
     let (white_players, black_players) = diesel::alias!(players as white, players as black);
-    games::table
-        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
-        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
-        .inner_join(events::table.on(games::event_id.eq(events::id)))
-        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
-        .load_iter::<(Game, Player, Player, Event, Site), DefaultLoadingMode>(db)?
-        .flatten()
-        .map(|(game, white, black, event, site)| {
+    let mut last_id = 0;
+    loop {
+        // Loaded in batches, so that only their tags and variants are in memory
+        let batch: Vec<(Game, Player, Player, Event, Site)> = games::table
+            .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+            .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+            .inner_join(events::table.on(games::event_id.eq(events::id)))
+            .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+            .filter(games::id.gt(last_id))
+            .order(games::id)
+            .limit(EXPORT_BATCH_SIZE)
+            .load(db)?;
+        let Some((last, ..)) = batch.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        let game_ids: Vec<i32> = batch.iter().map(|(game, ..)| game.id).collect();
+        let variants = game_variant::load_many(db, &game_ids)?;
+        let mut tags = game_tags::load_many(db, &game_ids)?;
+
+        for (game, white, black, event, site) in batch {
+            let variant = variants.get(&game.id).copied().unwrap_or_default();
+            let position = match variant.position(game.fen.as_deref()) {
+                Ok(position) => position,
+                Err(e) => {
+                    warn!("Skipping game {} with an invalid FEN: {}", game.id, e);
+                    continue;
+                }
+            };
             let pgn = PgnGame {
                 event: event.name,
                 site: site.name,
@@ -1285,21 +1444,14 @@ pub async fn export_to_pgn(
                 black_elo: game.black_elo.map(|e| e.to_string()),
                 ply_count: game.ply_count.map(|e| e.to_string()),
                 fen: game.fen.clone(),
-                 moves: GameTree::from_bytes(
-                    &game.moves,
-                    game.fen
-                        .map(|fen| Fen::from_ascii(fen.as_bytes()).ok())
-                        .flatten()
-                        .map(|fen| Chess::from_setup(fen.into(), CastlingMode::Chess960).ok())
-                        .flatten()
-                )?.to_string(),
+                variant,
+                tags: tags.remove(&game.id).unwrap_or_default(),
+                moves: GameTree::from_bytes(&game.moves, position.clone())?.to_pgn(position)?,
             };
 
             pgn.write(&mut writer)?;
-
-            Ok(())
-        })
-        .collect::<Result<Vec<_>>>()?;
+        }
+    }
     Ok(())
 }
 
@@ -1366,6 +1518,9 @@ pub async fn merge_players(
         return Err(Error::NotDistinctPlayers);
     }
 
+    // The hashes include the player IDs
+    game_hash::remove_player_games(db, player1)?;
+
     diesel::update(games::table.filter(games::white_id.eq(player1)))
         .set(games::white_id.eq(player2))
         .execute(db)?;
@@ -4,7 +4,10 @@ use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
-use crate::db::schema::*;
+use crate::{
+    db::{move_annotations::MoveAnnotations, schema::*},
+    variant::GameVariant,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, Type)]
 #[diesel(table_name = puzzles)]
@@ -71,8 +74,10 @@ pub struct Game {
     pub pawn_home: i32,
 }
 
+/// Missing values are inserted as `NULL`, which lets SQLite insert several
+/// games in one statement.
 #[derive(Insertable, Debug)]
-#[diesel(table_name = games)]
+#[diesel(table_name = games, treat_none_as_default_value = false)]
 pub struct NewGame<'a> {
     pub event_id: i32,
     pub site_id: i32,
@@ -118,6 +123,29 @@ pub struct NewEvent<'a> {
     pub name: &'a str,
 }
 
+#[derive(Insertable, Debug)]
+#[diesel(table_name = position_index)]
+pub struct NewPositionEntry {
+    pub hash: i64,
+    pub game_id: i32,
+    pub ply: i32,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = game_analysis)]
+pub struct NewGameAnalysis {
+    pub game_id: i32,
+    pub is_white: bool,
+    pub accuracy: f64,
+    pub acpl: f64,
+    pub opening_accuracy: Option<f64>,
+    pub opening_acpl: Option<f64>,
+    pub middlegame_accuracy: Option<f64>,
+    pub middlegame_acpl: Option<f64>,
+    pub endgame_accuracy: Option<f64>,
+    pub endgame_acpl: Option<f64>,
+}
+
 #[derive(Queryable, Serialize, Deserialize)]
 pub struct Info {
     pub name: String,
@@ -193,6 +221,15 @@ pub struct NormalizedGame {
     #[specta(optional)]
     pub ply_count: Option<i32>,
     pub moves: String,
+    /// Clocks, evaluations and drawings of the moves of the main line
+    #[serde(default)]
+    pub annotations: Vec<Option<MoveAnnotations>>,
+    /// `None` for standard chess
+    #[specta(optional)]
+    pub variant: Option<GameVariant>,
+    /// Headers without a column of their own, in their original order
+    #[serde(default)]
+    pub tags: Vec<GameTag>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Type)]
@@ -220,4 +257,15 @@ pub struct UpdateGame {
     #[specta(optional)]
     pub ply_count: Option<i32>,
     pub moves: String,
+    /// Replaces the extra headers of the game, `None` keeps them
+    #[serde(default)]
+    #[specta(optional)]
+    pub tags: Option<Vec<GameTag>>,
+}
+
+/// A PGN header stored as is, such as `Annotator` or `Opening`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Type)]
+pub struct GameTag {
+    pub name: String,
+    pub value: String,
 }
\ No newline at end of file
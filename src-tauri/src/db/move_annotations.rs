@@ -0,0 +1,381 @@
+use std::fmt;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use shakmaty::Square;
+use specta::Type;
+
+use crate::db::annotation::Eval;
+
+lazy_static! {
+    static ref COMMAND: Regex = Regex::new(r"\[%(\w+)\s+([^\]]*)\]").unwrap();
+}
+
+const CLOCK: u8 = 1;
+const ELAPSED: u8 = 2;
+const EVAL_CP: u8 = 4;
+const EVAL_MATE: u8 = 8;
+const DEPTH: u8 = 16;
+const SHAPES: u8 = 32;
+
+/// Destination byte of a highlighted square, which has none.
+const NO_DEST: u8 = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum ShapeColor {
+    Green,
+    Red,
+    Yellow,
+    Blue,
+}
+
+impl ShapeColor {
+    const ALL: [ShapeColor; 4] = [
+        ShapeColor::Green,
+        ShapeColor::Red,
+        ShapeColor::Yellow,
+        ShapeColor::Blue,
+    ];
+
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            'G' => Some(ShapeColor::Green),
+            'R' => Some(ShapeColor::Red),
+            'Y' => Some(ShapeColor::Yellow),
+            'B' => Some(ShapeColor::Blue),
+            _ => None,
+        }
+    }
+
+    fn char(self) -> char {
+        match self {
+            ShapeColor::Green => 'G',
+            ShapeColor::Red => 'R',
+            ShapeColor::Yellow => 'Y',
+            ShapeColor::Blue => 'B',
+        }
+    }
+}
+
+/// A highlighted square (`[%csl]`) or an arrow (`[%cal]`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Type)]
+pub struct Shape {
+    pub color: ShapeColor,
+    pub orig: String,
+    /// `None` for highlighted squares
+    #[specta(optional)]
+    pub dest: Option<String>,
+}
+
+impl Shape {
+    fn parse(s: &str) -> Option<Self> {
+        let color = ShapeColor::from_char(s.chars().next()?)?;
+        let squares = s.get(1..)?;
+        let square = |s: &str| {
+            Square::from_ascii(s.as_bytes())
+                .ok()
+                .map(|sq| sq.to_string())
+        };
+        match squares.len() {
+            2 => Some(Shape {
+                color,
+                orig: square(squares)?,
+                dest: None,
+            }),
+            4 => Some(Shape {
+                color,
+                orig: square(&squares[..2])?,
+                dest: Some(square(&squares[2..])?),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Clock times, evaluation and drawings attached to a move by the commands of
+/// its comments, such as `[%clk 0:03:00]` or `[%cal Ge2e4]`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveAnnotations {
+    /// Time left on the clock after the move, in milliseconds
+    #[specta(optional)]
+    pub clock: Option<u32>,
+    /// Time spent on the move, in milliseconds
+    #[specta(optional)]
+    pub elapsed: Option<u32>,
+    #[specta(optional)]
+    pub eval: Option<Eval>,
+    #[specta(optional)]
+    pub depth: Option<u32>,
+    pub shapes: Vec<Shape>,
+}
+
+impl MoveAnnotations {
+    pub fn is_empty(&self) -> bool {
+        *self == MoveAnnotations::default()
+    }
+
+    /// Extracts the commands it knows from a comment, and returns the rest of
+    /// the comment. Unknown or invalid commands are left in the text.
+    pub fn parse(comment: &str) -> (Self, String) {
+        let mut annotations = MoveAnnotations::default();
+        let text = COMMAND.replace_all(comment, |caps: &regex::Captures| {
+            if annotations.apply(&caps[1], caps[2].trim()).is_some() {
+                String::new()
+            } else {
+                caps[0].to_string()
+            }
+        });
+        if annotations.is_empty() {
+            return (annotations, comment.to_string());
+        }
+        // Drop the spaces left around the commands
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        (annotations, text)
+    }
+
+    fn apply(&mut self, command: &str, args: &str) -> Option<()> {
+        match command {
+            "clk" => self.clock = Some(parse_duration(args)?),
+            "emt" => self.elapsed = Some(parse_duration(args)?),
+            "eval" => {
+                let (eval, depth) = match args.split_once(',') {
+                    Some((eval, depth)) => (eval, Some(depth.trim().parse().ok()?)),
+                    None => (args, None),
+                };
+                self.eval = Some(match eval.strip_prefix('#') {
+                    Some(moves) => Eval::Mate(moves.parse().ok()?),
+                    None => Eval::Cp((eval.parse::<f64>().ok()? * 100.0).round() as i32),
+                });
+                self.depth = depth;
+            }
+            "csl" | "cal" => {
+                let shapes = args
+                    .split(',')
+                    .map(|shape| Shape::parse(shape.trim()))
+                    .collect::<Option<Vec<_>>>()?;
+                let arrows = command == "cal";
+                if shapes.iter().any(|shape| shape.dest.is_some() != arrows) {
+                    return None;
+                }
+                self.shapes.extend(shapes);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Merges the commands of another comment of the same move.
+    pub fn merge(&mut self, other: MoveAnnotations) {
+        self.clock = other.clock.or(self.clock);
+        self.elapsed = other.elapsed.or(self.elapsed);
+        if other.eval.is_some() {
+            self.eval = other.eval;
+            self.depth = other.depth;
+        }
+        self.shapes.extend(other.shapes);
+    }
+
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        let shapes: Vec<_> = self
+            .shapes
+            .iter()
+            .filter_map(|shape| {
+                let orig = Square::from_ascii(shape.orig.as_bytes()).ok()?;
+                let dest = match &shape.dest {
+                    Some(dest) => u8::from(Square::from_ascii(dest.as_bytes()).ok()?),
+                    None => NO_DEST,
+                };
+                let color = ShapeColor::ALL.iter().position(|c| *c == shape.color)? as u8;
+                Some([u8::from(orig) | color << 6, dest])
+            })
+            .take(u8::MAX as usize)
+            .collect();
+
+        let mut flags = 0;
+        for (set, flag) in [
+            (self.clock.is_some(), CLOCK),
+            (self.elapsed.is_some(), ELAPSED),
+            (matches!(self.eval, Some(Eval::Cp(_))), EVAL_CP),
+            (matches!(self.eval, Some(Eval::Mate(_))), EVAL_MATE),
+            (self.depth.is_some(), DEPTH),
+            (!shapes.is_empty(), SHAPES),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+
+        bytes.push(flags);
+        if let Some(clock) = self.clock {
+            bytes.extend(clock.to_be_bytes());
+        }
+        if let Some(elapsed) = self.elapsed {
+            bytes.extend(elapsed.to_be_bytes());
+        }
+        if let Some(Eval::Cp(value) | Eval::Mate(value)) = self.eval {
+            bytes.extend(value.to_be_bytes());
+        }
+        if let Some(depth) = self.depth {
+            bytes.extend((depth.min(u16::MAX as u32) as u16).to_be_bytes());
+        }
+        if !shapes.is_empty() {
+            bytes.push(shapes.len() as u8);
+            bytes.extend(shapes.concat());
+        }
+    }
+
+    /// Reads annotations written by `encode`, along with the number of bytes they used.
+    pub fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let flags = *bytes.first()?;
+        let mut pos = 1;
+        let mut take = |n: usize| {
+            let slice = bytes.get(pos..pos + n)?;
+            pos += n;
+            Some(slice)
+        };
+
+        let mut annotations = MoveAnnotations::default();
+        if flags & CLOCK != 0 {
+            annotations.clock = Some(u32::from_be_bytes(take(4)?.try_into().ok()?));
+        }
+        if flags & ELAPSED != 0 {
+            annotations.elapsed = Some(u32::from_be_bytes(take(4)?.try_into().ok()?));
+        }
+        if flags & EVAL_CP != 0 {
+            annotations.eval = Some(Eval::Cp(i32::from_be_bytes(take(4)?.try_into().ok()?)));
+        }
+        if flags & EVAL_MATE != 0 {
+            annotations.eval = Some(Eval::Mate(i32::from_be_bytes(take(4)?.try_into().ok()?)));
+        }
+        if flags & DEPTH != 0 {
+            annotations.depth = Some(u16::from_be_bytes(take(2)?.try_into().ok()?) as u32);
+        }
+        if flags & SHAPES != 0 {
+            let count = take(1)?[0] as usize;
+            for shape in take(count * 2)?.chunks(2) {
+                annotations.shapes.push(Shape {
+                    color: ShapeColor::ALL[(shape[0] >> 6) as usize],
+                    orig: Square::new(u32::from(shape[0] & 63)).to_string(),
+                    dest: (shape[1] < NO_DEST)
+                        .then(|| Square::new(u32::from(shape[1])).to_string()),
+                });
+            }
+        }
+        Some((annotations, pos))
+    }
+}
+
+/// Parses `H:MM:SS`, with optional fractions of a second, to milliseconds.
+fn parse_duration(s: &str) -> Option<u32> {
+    let (s, millis) = match s.split_once('.') {
+        Some((s, fraction)) if !fraction.is_empty() && fraction.len() <= 3 => {
+            let digits: u32 = fraction.parse().ok()?;
+            (s, digits * 10u32.pow(3 - fraction.len() as u32))
+        }
+        Some(_) => return None,
+        None => (s, 0),
+    };
+    let mut seconds = 0u32;
+    for part in s.split(':') {
+        seconds = seconds.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+    seconds.checked_mul(1000)?.checked_add(millis)
+}
+
+fn format_duration(millis: u32) -> String {
+    let seconds = millis / 1000;
+    let time = format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    match millis % 1000 {
+        0 => time,
+        fraction if fraction % 100 == 0 => format!("{}.{}", time, fraction / 100),
+        fraction => format!("{}.{:03}", time, fraction),
+    }
+}
+
+impl fmt::Display for MoveAnnotations {
+    /// Writes the commands back, as they would appear in a comment.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut commands = Vec::new();
+        if let Some(eval) = self.eval {
+            let depth = self
+                .depth
+                .map_or(String::new(), |depth| format!(",{depth}"));
+            commands.push(match eval {
+                Eval::Cp(cp) => format!("[%eval {:.2}{}]", cp as f64 / 100.0, depth),
+                Eval::Mate(moves) => format!("[%eval #{moves}{depth}]"),
+            });
+        }
+        for (command, arrows) in [("csl", false), ("cal", true)] {
+            let shapes: Vec<_> = self
+                .shapes
+                .iter()
+                .filter(|shape| shape.dest.is_some() == arrows)
+                .map(|shape| {
+                    format!(
+                        "{}{}{}",
+                        shape.color.char(),
+                        shape.orig,
+                        shape.dest.as_deref().unwrap_or("")
+                    )
+                })
+                .collect();
+            if !shapes.is_empty() {
+                commands.push(format!("[%{} {}]", command, shapes.join(",")));
+            }
+        }
+        for (command, duration) in [("clk", self.clock), ("emt", self.elapsed)] {
+            if let Some(duration) = duration {
+                commands.push(format!("[%{command} {}]", format_duration(duration)));
+            }
+        }
+        f.write_str(&commands.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        let (annotations, text) = MoveAnnotations::parse(
+            "[%clk 0:02:59.9] [%emt 0:00:01] Good move [%eval #-3,24] [%csl Gd4] [%cal Re2e4,Bg1f3] [%foo bar]",
+        );
+        assert_eq!(text, "Good move [%foo bar]");
+        assert_eq!(annotations.clock, Some(179_900));
+        assert_eq!(annotations.elapsed, Some(1000));
+        assert_eq!(annotations.eval, Some(Eval::Mate(-3)));
+        assert_eq!(annotations.depth, Some(24));
+        assert_eq!(annotations.shapes.len(), 3);
+        assert_eq!(annotations.shapes[1].dest.as_deref(), Some("e4"));
+
+        let (annotations, text) = MoveAnnotations::parse("[%eval -0.35] [%csl Gd4e5]");
+        assert_eq!(annotations.eval, Some(Eval::Cp(-35)));
+        assert_eq!(text, "[%csl Gd4e5]");
+    }
+
+    #[test]
+    fn round_trip() {
+        let comment =
+            "[%eval 0.17,20] [%csl Yd5] [%cal Gb1c3,Rh7h1] [%clk 1:30:00] [%emt 0:00:12.345]";
+        let (annotations, text) = MoveAnnotations::parse(comment);
+        assert!(text.is_empty());
+        assert_eq!(annotations.to_string(), comment);
+
+        let mut bytes = vec![0];
+        annotations.encode(&mut bytes);
+        let (decoded, len) = MoveAnnotations::decode(&bytes[1..]).unwrap();
+        assert_eq!(len, bytes.len() - 1);
+        assert_eq!(decoded, annotations);
+
+        assert!(MoveAnnotations::decode(&bytes[1..bytes.len() - 1]).is_none());
+    }
+}
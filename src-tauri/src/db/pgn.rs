@@ -1,9 +1,14 @@
 use shakmaty::{
-    fen::Fen, ByColor, Chess, FromSetup, Position, PositionError, Board
+    fen::Fen, variant::{Variant, VariantPosition}, ByColor, Chess, EnPassantMode, Position, Board
 };
 use pgn_reader::{Nag, RawComment, RawHeader, SanPlus, Skip, Visitor};
 use chrono::{NaiveDate, NaiveTime};
-use crate::error::{Error, Result};
+use derivative::Derivative;
+use crate::{
+    db::{encoding::{encode_move_index, read_move}, game_tags, import_report::SkipReason, models::GameTag, move_annotations::MoveAnnotations},
+    error::{Error, Result},
+    variant::GameVariant,
+};
 
 pub type MaterialCount = ByColor<u8>;
 
@@ -21,6 +26,8 @@ pub fn get_material_count(board: &Board) -> MaterialCount {
 pub enum GameTreeNode {
     Move(SanPlus),
     Comment(String),
+    /// Commands of the comments, such as `[%clk]`, which precede their text
+    Annotations(MoveAnnotations),
     Nag(Nag),
     Variation(GameTree)
 }
@@ -33,6 +40,7 @@ impl GameTree {
     const END_VARIATION: u8 = 253;
     const COMMENT: u8 = 252;
     const NAG: u8 = 251; 
+    const ANNOTATIONS: u8 = 255;
 
 
     pub fn new() -> Self {
@@ -51,9 +59,38 @@ impl GameTree {
             })
             .sum()
     }
- 
-    pub fn encode(&self, bytes: &mut Vec<u8>, position: Option<Chess>) {
-        let mut cur_position = position.unwrap_or_default();
+
+    pub fn into_nodes(self) -> Vec<GameTreeNode> {
+        self.0
+    }
+
+    /// Returns the annotations of every move of the main line.
+    pub fn main_line_annotations(&self) -> Vec<Option<MoveAnnotations>> {
+        let mut annotations = Vec::new();
+        for node in &self.0 {
+            match node {
+                GameTreeNode::Move(_) => annotations.push(None),
+                GameTreeNode::Annotations(node) => {
+                    if let Some(last) = annotations.last_mut() {
+                        last.get_or_insert_with(MoveAnnotations::default).merge(node.clone());
+                    }
+                },
+                _ => {}
+            }
+        }
+        annotations
+    }
+
+    /// Iterates over the moves of the main line, skipping comments, NAGs and variations.
+    pub fn main_line(&self) -> impl Iterator<Item = &SanPlus> {
+        self.0.iter().filter_map(|node| match node {
+            GameTreeNode::Move(san) => Some(san),
+            _ => None,
+        })
+    }
+
+    pub fn encode<P: Position + Clone>(&self, bytes: &mut Vec<u8>, position: P) {
+        let mut cur_position = position;
         let mut prev_position = cur_position.clone();
         
         for item in &self.0 {
@@ -61,7 +98,7 @@ impl GameTree {
                 GameTreeNode::Move(m) => {
                     if let Ok(m) = m.san.to_move(&cur_position) {
                         if let Some(pos) = cur_position.legal_moves().iter().position(|x| x.eq(&m)) {
-                            bytes.push(pos as u8);
+                            encode_move_index(bytes, pos);
                         }
                         prev_position = cur_position.clone();
                         cur_position.play_unchecked(&m);
@@ -76,31 +113,41 @@ impl GameTree {
                     bytes.extend((comment.len() as u64).to_be_bytes());
                     bytes.extend(comment.as_bytes());
                 },
+                GameTreeNode::Annotations(annotations) => {
+                    bytes.push(Self::ANNOTATIONS);
+                    annotations.encode(bytes);
+                },
                 GameTreeNode::Variation(branch) => {
                     bytes.push(Self::START_VARIATION);
-                    branch.encode(bytes, Some(prev_position.clone()));
+                    branch.encode(bytes, prev_position.clone());
                     bytes.push(Self::END_VARIATION);
                 }
             }
         }
     }
 
-    fn from_bytes_impl(mut bytes: &[u8], position: Chess) -> Result<(Vec<GameTreeNode>, &[u8])> {
-        let mut prev_position: Chess = position.clone();
-        let mut cur_position: Chess = position;
+    fn from_bytes_impl<P: Position + Clone>(mut bytes: &[u8], position: P) -> Result<(Vec<GameTreeNode>, &[u8])> {
+        let mut prev_position = position.clone();
+        let mut cur_position = position;
         let mut tree: Vec<GameTreeNode> = Vec::new();
 
         loop {
             match bytes.first().copied() {
                 Some(Self::NAG) => {
-                    tree.push(GameTreeNode::Nag(Nag(bytes[1])));
+                    tree.push(GameTreeNode::Nag(Nag(*bytes.get(1).ok_or(Error::InvalidBinaryData)?)));
                     bytes = &bytes[2..];
                 },
                 Some(Self::COMMENT) => {
                     let length = u64::from_be_bytes(bytes[1..].first_chunk::<8>().ok_or(Error::InvalidBinaryData)?.to_owned()) as usize;
-                    tree.push(GameTreeNode::Comment(String::from_utf8(bytes[9..9+length].to_owned())?));
+                    let comment = bytes.get(9..9 + length).ok_or(Error::InvalidBinaryData)?;
+                    tree.push(GameTreeNode::Comment(String::from_utf8(comment.to_owned())?));
                     bytes = &bytes[9+length..];
                 },
+                Some(Self::ANNOTATIONS) => {
+                    let (annotations, len) = MoveAnnotations::decode(&bytes[1..]).ok_or(Error::InvalidBinaryData)?;
+                    tree.push(GameTreeNode::Annotations(annotations));
+                    bytes = &bytes[1 + len..];
+                },
                 Some(Self::END_VARIATION) => {
                     bytes = &bytes[1..];
                     break;
@@ -110,15 +157,12 @@ impl GameTree {
                     tree.push(GameTreeNode::Variation(GameTree(branch)));
                     bytes = rest;
                 },
-                Some(byte) => {
-                    if let Some(m) = cur_position.legal_moves().get(byte as usize) {
-                        prev_position = cur_position.clone();
-                        let san = SanPlus::from_move_and_play_unchecked(&mut cur_position, m);
-                        tree.push(GameTreeNode::Move(san));
-                        bytes = &bytes[1..];
-                    } else {
-                        panic!("Invalid move");
-                    }
+                Some(_) => {
+                    let (m, len) = read_move(bytes, &cur_position).ok_or(Error::InvalidBinaryData)?;
+                    prev_position = cur_position.clone();
+                    let san = SanPlus::from_move_and_play_unchecked(&mut cur_position, &m);
+                    tree.push(GameTreeNode::Move(san));
+                    bytes = &bytes[len..];
                 },
                 None => {
                     break;
@@ -130,17 +174,25 @@ impl GameTree {
         Ok((tree, bytes))
     }
 
-    pub fn from_bytes(bytes: &[u8], position: Option<Chess>) -> Result<Self> {
-        Ok(Self(Self::from_bytes_impl(bytes, position.unwrap_or_default())?.0))
+    pub fn from_bytes<P: Position + Clone>(bytes: &[u8], position: P) -> Result<Self> {
+        Ok(Self(Self::from_bytes_impl(bytes, position)?.0))
     }
 
-    pub fn pretty_print(&self, writer: &mut std::fmt::Formatter<'_>, position: Option<Chess>) -> Result<()> {
-        let mut cur_position = position.unwrap_or_default();
+    /// Writes the tree as PGN movetext, numbering the moves from `position`.
+    pub fn to_pgn<P: Position + Clone>(&self, position: P) -> Result<String> {
+        let mut pgn = String::new();
+        self.pretty_print(&mut pgn, position)?;
+        Ok(pgn)
+    }
+
+    pub fn pretty_print<P: Position + Clone>(&self, writer: &mut impl std::fmt::Write, position: P) -> Result<()> {
+        let mut cur_position = position;
         let mut prev_position = cur_position.clone();
 
         let mut is_beginning = true;
+        let mut items = self.0.iter().peekable();
         
-        for item in &self.0 {
+        while let Some(item) = items.next() {
             match item {
                 GameTreeNode::Move(m) => {
                     let i = cur_position.fullmoves().get();
@@ -169,9 +221,16 @@ impl GameTree {
                 GameTreeNode::Comment(comment) => {
                     write!(writer, " {{{}}} ", comment)?;
                 },
+                GameTreeNode::Annotations(annotations) => {
+                    // Written back in the same comment as the text that follows
+                    match items.next_if(|item| matches!(item, GameTreeNode::Comment(_))) {
+                        Some(GameTreeNode::Comment(comment)) => write!(writer, " {{{} {}}} ", annotations, comment)?,
+                        _ => write!(writer, " {{{}}} ", annotations)?,
+                    }
+                },
                 GameTreeNode::Variation(branch) => {
                     writer.write_str(" ( ")?;
-                    branch.pretty_print(writer,  Some(prev_position.clone()))?;
+                    branch.pretty_print(writer, prev_position.clone())?;
                     writer.write_str(" ) ")?;
                     is_beginning = true;
                 }
@@ -184,7 +243,7 @@ impl GameTree {
 
 impl std::fmt::Display for GameTree {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.pretty_print(f, None) {
+        match self.pretty_print(f, Chess::default()) {
             Ok(()) => Ok(()),
             Err(Error::FormatError(err)) => Err(err),
             Err(_) => {
@@ -196,7 +255,8 @@ impl std::fmt::Display for GameTree {
 }
 
 
-#[derive(Default, Debug)]
+#[derive(Derivative)]
+#[derivative(Default, Debug)]
 pub struct TempGame {
     pub event_name: Option<String>,
     pub site_name: Option<String>,
@@ -211,17 +271,42 @@ pub struct TempGame {
     pub time_control: Option<String>,
     pub eco: Option<String>,
     pub fen: Option<String>,
+    pub variant: GameVariant,
+    pub tags: Vec<GameTag>,
     pub moves: Vec<u8>,
-    pub position: Chess,
+    #[derivative(Default(value = "VariantPosition::new(Variant::Chess)"))]
+    pub position: VariantPosition,
     pub material_count: ByColor<u8>,
     pub tree: GameTree,
 }
 
+impl TempGame {
+    /// Headers identifying the game, as read from the PGN.
+    pub fn headers(&self) -> Vec<GameTag> {
+        let known = [
+            ("Event", &self.event_name),
+            ("Site", &self.site_name),
+            ("Date", &self.date),
+            ("Round", &self.round),
+            ("White", &self.white_name),
+            ("Black", &self.black_name),
+            ("Result", &self.result),
+            ("FEN", &self.fen),
+        ];
+        known
+            .into_iter()
+            .filter_map(|(name, value)| Some(GameTag { name: name.to_string(), value: value.clone()? }))
+            .chain(self.tags.iter().cloned())
+            .collect()
+    }
+}
+
 pub struct Importer {
     game: TempGame,
     variants: Vec<GameTree>,
     timestamp: Option<i64>,
-    skip: bool,
+    skip: Option<SkipReason>,
+    skipped: Option<(SkipReason, Vec<GameTag>)>,
 }
 
 
@@ -231,10 +316,27 @@ impl Importer {
             game: TempGame::default(),
             variants: Vec::new(),
             timestamp,
-            skip: false,
+            skip: None,
+            skipped: None,
         }
     }
 
+    /// Why the last game read was skipped, along with its headers.
+    pub fn take_skipped(&mut self) -> Option<(SkipReason, Vec<GameTag>)> {
+        self.skipped.take()
+    }
+
+    /// Skips the current game, keeping the first reason found.
+    fn skip(&mut self, reason: SkipReason) {
+        self.skip.get_or_insert(reason);
+    }
+
+    fn skip_game(&mut self, reason: SkipReason) -> Option<TempGame> {
+        let game = std::mem::take(&mut self.game);
+        self.skipped = Some((reason, game.headers()));
+        None
+    }
+
     #[inline]
     #[must_use]
     fn active_branch(&mut self) -> &mut GameTree {
@@ -246,7 +348,8 @@ impl Visitor for Importer {
     type Result = Option<TempGame>;
 
     fn begin_game(&mut self) {
-        self.skip = false;
+        self.skip = None;
+        self.skipped = None;
     }
 
     fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
@@ -278,25 +381,51 @@ impl Visitor for Importer {
             if value.as_bytes() == b"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1" {
                 self.game.fen = None;
             } else {
-                let fen = Fen::from_ascii(value.as_bytes());
-                if let Ok(fen) = fen {
-                    self.game.fen = Some(value.decode_utf8_lossy().into_owned());
-                    if let Ok(setup) =
-                        Chess::from_setup(fen.into_setup(), shakmaty::CastlingMode::Standard)
-                            .or_else(PositionError::ignore_too_much_material)
+                self.game.fen = Some(value.decode_utf8_lossy().into_owned());
+            }
+        } else if key == b"Variant" {
+            match GameVariant::from_pgn(&value.decode_utf8_lossy()) {
+                // Chess960 and From Position games are played with the standard rules,
+                // the header is kept to tell them apart
+                Some(GameVariant::Standard) => self.game.tags.push(GameTag {
+                    name: "Variant".to_string(),
+                    value: value.decode_utf8_lossy().into_owned(),
+                }),
+                Some(variant) => self.game.variant = variant,
+                None => self.skip(SkipReason::UnsupportedVariant(value.decode_utf8_lossy().into_owned())),
+            }
+        }
+
+        let name = String::from_utf8_lossy(key);
+        if game_tags::is_extra_header(&name) {
+            self.game.tags.push(GameTag {
+                name: name.into_owned(),
+                value: value.decode_utf8_lossy().into_owned(),
+            });
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        // The variant may come after the FEN, so the position is only known now
+        match self.game.variant.position(self.game.fen.as_deref()) {
+            Ok(position) => {
+                // Variants with a different setup often repeat their starting position
+                if self.game.fen.is_some() && !self.game.variant.is_standard() {
+                    let initial = VariantPosition::new(self.game.variant.into());
+                    if Fen::from_position(position.clone(), EnPassantMode::Legal).to_string()
+                        == Fen::from_position(initial, EnPassantMode::Legal).to_string()
                     {
-                        self.game.position = setup;
-                    } else {
-                        self.skip = true;
+                        self.game.fen = None;
                     }
-                } else {
-                    self.skip = true;
                 }
+                self.game.position = position;
+            }
+            Err(_) => {
+                let fen = self.game.fen.clone().unwrap_or_default();
+                self.skip(SkipReason::InvalidFen(fen));
             }
         }
-    }
 
-    fn end_headers(&mut self) -> Skip {
         // Skip games with timestamp before
         let cur_timestamp = self.game.date.as_ref().and_then(|date| {
             let date = NaiveDate::parse_from_str(date, "%Y.%m.%d").ok()?;
@@ -310,13 +439,13 @@ impl Visitor for Importer {
 
         if let (Some(cur_timestamp), Some(timestamp)) = (cur_timestamp, self.timestamp) {
             if cur_timestamp <= timestamp {
-                self.skip = true;
+                self.skip(SkipReason::BeforeTimestamp);
             }
         }
 
         // Skip games without ELO
         // self.skip |= self.current.white_elo.is_none() || self.current.black_elo.is_none();
-        Skip(self.skip)
+        Skip(self.skip.is_some())
     }
 
     fn san(&mut self, san: SanPlus) {
@@ -324,8 +453,20 @@ impl Visitor for Importer {
     }
 
     fn comment(&mut self, comment: RawComment<'_>) {
-        if let Ok(comment) = String::from_utf8(comment.as_bytes().to_owned()) {
-            self.active_branch().push(GameTreeNode::Comment(comment));
+        if let Ok(comment) = std::str::from_utf8(comment.as_bytes()) {
+            let (annotations, text) = MoveAnnotations::parse(comment);
+            let has_commands = !annotations.is_empty();
+            let branch = self.active_branch();
+            if has_commands {
+                // Lichess writes `[%eval]` and `[%clk]` in separate comments
+                match branch.0.last_mut() {
+                    Some(GameTreeNode::Annotations(last)) => last.merge(annotations),
+                    _ => branch.push(GameTreeNode::Annotations(annotations)),
+                }
+            }
+            if !text.is_empty() || !has_commands {
+                branch.push(GameTreeNode::Comment(text));
+            }
         }
     }
 
@@ -345,24 +486,21 @@ impl Visitor for Importer {
     }
 
     fn end_game(&mut self) -> Self::Result {
-        if self.skip {
-            self.game = TempGame::default();
-            None
+        if let Some(reason) = self.skip.take() {
+            self.skip_game(reason)
         } else {
             // encode game tree 
-            self.game.tree.encode(&mut self.game.moves, Some(self.game.position.clone()));
+            self.game.tree.encode(&mut self.game.moves, self.game.position.clone());
 
             // calc material
             let mut cur_position = self.game.position.clone();
-            for item in &self.game.tree.0 {
-                if let GameTreeNode::Move(san) = item {
-                    if let Ok(m) = san.san.to_move(&cur_position) {
-                        cur_position.play_unchecked(&m);
-                    } else {
-                        // Invalid game
-                        self.game = TempGame::default();
-                        return None;
-                    }
+            for (ply, san) in self.game.tree.main_line().enumerate() {
+                if let Ok(m) = san.san.to_move(&cur_position) {
+                    cur_position.play_unchecked(&m);
+                } else {
+                    // Invalid game
+                    let san = san.to_string();
+                    return self.skip_game(SkipReason::IllegalMove { ply: ply as u32, san });
                 }
             }
             self.game.material_count = get_material_count(cur_position.board());
@@ -400,11 +538,11 @@ mod test {
 
             let mut bytes: Vec<u8> = Vec::new();
 
-            game.tree.encode(&mut bytes, None);
+            game.tree.encode(&mut bytes, Chess::default());
 
             assert_eq!(
                 game.tree,
-                GameTree::from_bytes(&bytes, None).unwrap()
+                GameTree::from_bytes(&bytes, Chess::default()).unwrap()
             );
             assert_eq!(game.tree.to_string(), pgn);
         }
@@ -546,12 +684,132 @@ mod test {
         let game = reader.read_game(&mut importer).unwrap().flatten().unwrap();
 
         let mut bytes: Vec<u8> = Vec::new();
-        game.tree.encode(&mut bytes, None);
+        game.tree.encode(&mut bytes, Chess::default());
 
         assert_eq!(
             game.tree,
-            GameTree::from_bytes(&bytes, None).unwrap()
+            GameTree::from_bytes(&bytes, Chess::default()).unwrap()
         );
         assert_eq!(trim(&game.tree.to_string()), trim(pgn));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_variant_pgn() {
+        let pgn = "[Variant \"Crazyhouse\"]\n\n1.e4 e5 2.Nf3 Nc6 3.Bc4 Nf6 4.Ng5 d5 5.exd5 Nxd5 6.Nxf7 Kxf7 7.P@e6+ Kxe6 *";
+
+        let mut reader = BufferedReader::new_cursor(&pgn[..]);
+        let mut importer = Importer::new(None);
+        let game = reader.read_game(&mut importer).unwrap().flatten().unwrap();
+        assert_eq!(game.variant, GameVariant::Crazyhouse);
+        assert_eq!(game.tree.count_main_line_moves(), 14);
+
+        assert_eq!(
+            game.tree,
+            GameTree::from_bytes(&game.moves, game.position.clone()).unwrap()
+        );
+        let movetext = game.tree.to_pgn(game.position).unwrap();
+        assert!(movetext.starts_with("1.e4 e5 2.Nf3"));
+        assert!(movetext.ends_with("@e6+ Kxe6"));
+
+        let mut reader = BufferedReader::new_cursor(&b"[Variant \"Shogi\"]\n\n1.e4 *"[..]);
+        assert!(reader.read_game(&mut importer).unwrap().flatten().is_none());
+    }
+
+    #[test]
+    fn test_standard_variant_header() {
+        let pgn = "[Variant \"Chess960\"]\n[FEN \"bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9\"]\n\n9.g3 *";
+
+        let mut reader = BufferedReader::new_cursor(&pgn[..]);
+        let mut importer = Importer::new(None);
+        let game = reader.read_game(&mut importer).unwrap().flatten().unwrap();
+        assert_eq!(game.variant, GameVariant::Standard);
+        assert_eq!(
+            game.tags,
+            vec![GameTag { name: "Variant".to_string(), value: "Chess960".to_string() }]
+        );
+    }
+
+    #[test]
+    fn test_extra_headers() {
+        let pgn = "[Event \"Casual\"]\n[Annotator \"Carlsen\"]\n[UTCTime \"12:00:00\"]\n[Opening \"Sicilian \\\"Dragon\\\"\"]\n\n1.e4 c5 *";
+
+        let mut reader = BufferedReader::new_cursor(&pgn[..]);
+        let mut importer = Importer::new(None);
+        let game = reader.read_game(&mut importer).unwrap().flatten().unwrap();
+        assert_eq!(game.event_name.as_deref(), Some("Casual"));
+        assert_eq!(game.time.as_deref(), Some("12:00:00"));
+        let tags: Vec<_> = game
+            .tags
+            .iter()
+            .map(|tag| (tag.name.as_str(), tag.value.as_str()))
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                ("Annotator", "Carlsen"),
+                ("UTCTime", "12:00:00"),
+                ("Opening", "Sicilian \"Dragon\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comment_commands() {
+        let pgn = "1. e4 { [%eval 0.17] [%clk 0:03:00] } 1... c5 { [%clk 0:02:58.5] } { Sicilian [%cal Gg1f3] } 2. Nf3 { Main line } *";
+
+        let mut reader = BufferedReader::new_cursor(&pgn[..]);
+        let mut importer = Importer::new(None);
+        let game = reader.read_game(&mut importer).unwrap().flatten().unwrap();
+
+        let tree = GameTree::from_bytes(&game.moves, Chess::default()).unwrap();
+        assert_eq!(tree, game.tree);
+        assert_eq!(
+            tree.to_string(),
+            "1.e4 {[%eval 0.17] [%clk 0:03:00]}  c5 {[%cal Gg1f3] [%clk 0:02:58.5] Sicilian}  2.Nf3 { Main line } "
+        );
+
+        let annotations = tree.main_line_annotations();
+        assert_eq!(annotations.len(), 3);
+        assert_eq!(annotations[0].as_ref().unwrap().clock, Some(180_000));
+        let black = annotations[1].as_ref().unwrap();
+        assert_eq!(black.clock, Some(178_500));
+        assert_eq!(black.shapes[0].dest.as_deref(), Some("f3"));
+        assert_eq!(annotations[2], None);
+    }
+
+    #[test]
+    fn test_skip_reasons() {
+        let pgn = "[White \"Typo\"]\n\n1.e4 e5 2.Nf3 Nf6 3.Bb5 Nf6 *\n\n[FEN \"8/8/8 w - - 0 1\"]\n\n1.e4 *\n\n1.d4 *";
+
+        let mut reader = BufferedReader::new_cursor(&pgn[..]);
+        let mut importer = Importer::new(None);
+        assert!(reader.read_game(&mut importer).unwrap().flatten().is_none());
+        let (reason, headers) = importer.take_skipped().unwrap();
+        assert_eq!(reason, SkipReason::IllegalMove { ply: 5, san: "Nf6".to_string() });
+        assert_eq!(headers, vec![GameTag { name: "White".to_string(), value: "Typo".to_string() }]);
+
+        assert!(reader.read_game(&mut importer).unwrap().flatten().is_none());
+        let (reason, _) = importer.take_skipped().unwrap();
+        assert_eq!(reason, SkipReason::InvalidFen("8/8/8 w - - 0 1".to_string()));
+
+        assert!(reader.read_game(&mut importer).unwrap().flatten().is_some());
+        assert!(importer.take_skipped().is_none());
+    }
+
+    #[test]
+    fn test_encode_many_legal_moves() {
+        // Full pockets give more legal moves than fit in a byte
+        let pos = GameVariant::Crazyhouse
+            .position(Some("4k3/8/8/8/8/8/8/4K3[QRBNPqrbnp] w - - 0 1"))
+            .unwrap();
+        let legal_moves = pos.legal_moves();
+        assert!(legal_moves.len() > 255);
+
+        for m in &legal_moves {
+            let tree = GameTree(vec![GameTreeNode::Move(SanPlus::from_move(pos.clone(), m))]);
+            let mut bytes = Vec::new();
+            tree.encode(&mut bytes, pos.clone());
+            assert_eq!(tree, GameTree::from_bytes(&bytes, pos.clone()).unwrap());
+        }
+    }
+}
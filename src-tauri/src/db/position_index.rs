@@ -0,0 +1,240 @@
+use diesel::{connection::SimpleConnection, prelude::*};
+use log::info;
+use shakmaty::{fen::Fen, Board, CastlingMode, Chess, Color, FromSetup, Position};
+
+use crate::{
+    db::{
+        game_variant,
+        models::NewPositionEntry,
+        pgn::GameTree,
+        schema::{games, info, position_index},
+    },
+    error::{Error, Result},
+};
+
+/// Name of the `Info` row that marks the position index as complete.
+const INFO_KEY: &str = "PositionIndex";
+
+const CREATE_TABLE_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS PositionIndex (
+        Hash INTEGER NOT NULL,
+        GameID INTEGER NOT NULL,
+        Ply INTEGER NOT NULL,
+        PRIMARY KEY (Hash, GameID)
+    ) WITHOUT ROWID;
+";
+
+/// Rows per insert statement, keeps us well below SQLite's bound parameter limit.
+const INSERT_CHUNK_SIZE: usize = 300;
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// Returns the key under which a position is stored in the index.
+///
+/// Only the piece placement and the side to move are hashed, matching the
+/// semantics of an exact `PositionQuery` (castling rights and en passant
+/// squares are ignored). The hash has to be stable across builds since it
+/// is persisted, so we don't rely on `std::hash`.
+pub fn position_key(board: &Board, turn: Color) -> i64 {
+    let mut hash = match turn {
+        Color::White => 0x243F_6A88_85A3_08D3,
+        Color::Black => 0x1319_8A2E_0370_7344,
+    };
+    for bitboard in [
+        board.white(),
+        board.black(),
+        board.pawns(),
+        board.knights(),
+        board.bishops(),
+        board.rooks(),
+        board.queens(),
+        board.kings(),
+    ] {
+        hash = splitmix64(hash ^ bitboard.0);
+    }
+    hash as i64
+}
+
+/// Returns true if the position index is complete and can be used for lookups.
+pub fn is_built(db: &mut SqliteConnection) -> Result<bool> {
+    let value: Option<Option<String>> = info::table
+        .filter(info::name.eq(INFO_KEY))
+        .select(info::value)
+        .first(db)
+        .optional()?;
+    Ok(value.flatten().as_deref() == Some("1"))
+}
+
+/// Marks the position index as complete.
+pub fn mark_built(db: &mut SqliteConnection) -> Result<()> {
+    diesel::insert_into(info::table)
+        .values((info::name.eq(INFO_KEY), info::value.eq("1")))
+        .on_conflict(info::name)
+        .do_update()
+        .set(info::value.eq("1"))
+        .execute(db)?;
+    Ok(())
+}
+
+/// Adds every main line position of a game to the index.
+///
+/// Only the first occurrence of a position in a game is stored.
+pub fn index_game<P: Position>(
+    db: &mut SqliteConnection,
+    game_id: i32,
+    tree: &GameTree,
+    position: P,
+) -> Result<()> {
+    let mut pos = position;
+    let mut entries = vec![NewPositionEntry {
+        hash: position_key(pos.board(), pos.turn()),
+        game_id,
+        ply: 0,
+    }];
+
+    for (i, san) in tree.main_line().enumerate() {
+        let Ok(m) = san.san.to_move(&pos) else {
+            break;
+        };
+        pos.play_unchecked(&m);
+        entries.push(NewPositionEntry {
+            hash: position_key(pos.board(), pos.turn()),
+            game_id,
+            ply: i as i32 + 1,
+        });
+    }
+
+    for chunk in entries.chunks(INSERT_CHUNK_SIZE) {
+        diesel::insert_or_ignore_into(position_index::table)
+            .values(chunk)
+            .execute(db)?;
+    }
+    Ok(())
+}
+
+/// Removes a game from the index.
+pub fn remove_game(db: &mut SqliteConnection, game_id: i32) -> Result<()> {
+    if is_built(db)? {
+        diesel::delete(position_index::table.filter(position_index::game_id.eq(game_id)))
+            .execute(db)?;
+    }
+    Ok(())
+}
+
+/// Removes the entries of games that no longer exist.
+pub fn prune(db: &mut SqliteConnection) -> Result<()> {
+    if is_built(db)? {
+        db.batch_execute("DELETE FROM PositionIndex WHERE GameID NOT IN (SELECT ID FROM Games);")?;
+    }
+    Ok(())
+}
+
+/// Returns the starting position of a game stored in the database.
+pub fn start_position(fen: Option<&str>) -> Result<Chess> {
+    match fen {
+        Some(fen) => {
+            let fen = Fen::from_ascii(fen.as_bytes())?;
+            Ok(Chess::from_setup(fen.into_setup(), CastlingMode::Chess960)?)
+        }
+        None => Ok(Chess::default()),
+    }
+}
+
+/// (Re)builds the position index for every game in the database.
+pub fn build(db: &mut SqliteConnection) -> Result<()> {
+    db.batch_execute(CREATE_TABLE_SQL)?;
+
+    let games: Vec<(i32, Vec<u8>, Option<String>)> = games::table
+        .select((games::id, games::moves, games::fen))
+        .load(db)?;
+    let variants = game_variant::load_all(db)?;
+
+    info!("building position index for {} games", games.len());
+
+    db.transaction::<_, Error, _>(|db| {
+        diesel::delete(position_index::table).execute(db)?;
+        for (id, moves, fen) in &games {
+            let variant = variants.get(id).copied().unwrap_or_default();
+            let Ok(position) = variant.position(fen.as_deref()) else {
+                continue;
+            };
+            let tree = GameTree::from_bytes(moves, position.clone())?;
+            index_game(db, *id, &tree, position)?;
+        }
+        mark_built(db)
+    })?;
+
+    info!("position index built");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::core::init_db;
+    use shakmaty::{san::San, Chess};
+
+    fn play(moves: &[&str]) -> Chess {
+        let mut pos = Chess::default();
+        for m in moves {
+            let m = m.parse::<San>().unwrap().to_move(&pos).unwrap();
+            pos.play_unchecked(&m);
+        }
+        pos
+    }
+
+    #[test]
+    fn transpositions_share_a_key() {
+        let a = play(&["Nf3", "Nf6", "g3"]);
+        let b = play(&["g3", "Nf6", "Nf3"]);
+        assert_eq!(
+            position_key(a.board(), a.turn()),
+            position_key(b.board(), b.turn())
+        );
+
+        let c = play(&["Nf3", "Nf6"]);
+        assert_ne!(
+            position_key(a.board(), a.turn()),
+            position_key(c.board(), c.turn())
+        );
+    }
+
+    #[test]
+    fn side_to_move_changes_key() {
+        let pos = Chess::default();
+        assert_ne!(
+            position_key(pos.board(), Color::White),
+            position_key(pos.board(), Color::Black)
+        );
+    }
+
+    #[test]
+    fn index_game_stores_each_ply() {
+        let mut db = SqliteConnection::establish(":memory:").unwrap();
+        init_db(&mut db, "Test", "Test").unwrap();
+
+        let tree = {
+            use pgn_reader::BufferedReader;
+            let mut reader = BufferedReader::new_cursor(&b"1.e4 e5 2.Nf3 Nc6"[..]);
+            let mut importer = crate::db::pgn::Importer::new(None);
+            reader.read_game(&mut importer).unwrap().flatten().unwrap().tree
+        };
+        index_game(&mut db, 1, &tree, Chess::default()).unwrap();
+
+        let pos = play(&["e4", "e5"]);
+        let ply: i32 = position_index::table
+            .filter(position_index::hash.eq(position_key(pos.board(), pos.turn())))
+            .select(position_index::ply)
+            .first(&mut db)
+            .unwrap();
+        assert_eq!(ply, 2);
+
+        let count: i64 = position_index::table.count().get_result(&mut db).unwrap();
+        assert_eq!(count, 5);
+    }
+}
@@ -110,7 +110,96 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    #[sql_name = "PositionIndex"]
+    position_index (hash, game_id) {
+        #[sql_name = "Hash"]
+        hash -> BigInt,
+        #[sql_name = "GameID"]
+        game_id -> Integer,
+        #[sql_name = "Ply"]
+        ply -> Integer,
+    }
+}
+
+diesel::table! {
+    #[sql_name = "GameAnalysis"]
+    game_analysis (game_id, is_white) {
+        #[sql_name = "GameID"]
+        game_id -> Integer,
+        #[sql_name = "IsWhite"]
+        is_white -> Bool,
+        #[sql_name = "Accuracy"]
+        accuracy -> Double,
+        #[sql_name = "ACPL"]
+        acpl -> Double,
+        #[sql_name = "OpeningAccuracy"]
+        opening_accuracy -> Nullable<Double>,
+        #[sql_name = "OpeningACPL"]
+        opening_acpl -> Nullable<Double>,
+        #[sql_name = "MiddlegameAccuracy"]
+        middlegame_accuracy -> Nullable<Double>,
+        #[sql_name = "MiddlegameACPL"]
+        middlegame_acpl -> Nullable<Double>,
+        #[sql_name = "EndgameAccuracy"]
+        endgame_accuracy -> Nullable<Double>,
+        #[sql_name = "EndgameACPL"]
+        endgame_acpl -> Nullable<Double>,
+    }
+}
+
+diesel::table! {
+    #[sql_name = "GameVariants"]
+    game_variants (game_id) {
+        #[sql_name = "GameID"]
+        game_id -> Integer,
+        #[sql_name = "Variant"]
+        variant -> Text,
+    }
+}
+
+diesel::table! {
+    #[sql_name = "GameTags"]
+    game_tags (game_id, ordinal) {
+        #[sql_name = "GameID"]
+        game_id -> Integer,
+        #[sql_name = "Ordinal"]
+        ordinal -> Integer,
+        #[sql_name = "Name"]
+        name -> Text,
+        #[sql_name = "Value"]
+        value -> Text,
+    }
+}
+
+diesel::table! {
+    #[sql_name = "GameHashes"]
+    game_hashes (game_id) {
+        #[sql_name = "GameID"]
+        game_id -> Integer,
+        #[sql_name = "Hash"]
+        hash -> BigInt,
+    }
+}
+
 diesel::joinable!(games -> events (event_id));
 diesel::joinable!(games -> sites (site_id));
+diesel::joinable!(position_index -> games (game_id));
+diesel::joinable!(game_analysis -> games (game_id));
+diesel::joinable!(game_variants -> games (game_id));
+diesel::joinable!(game_tags -> games (game_id));
+diesel::joinable!(game_hashes -> games (game_id));
 
-diesel::allow_tables_to_appear_in_same_query!(comments, events, games, info, players, sites,);
+diesel::allow_tables_to_appear_in_same_query!(
+    comments,
+    events,
+    game_analysis,
+    game_hashes,
+    game_tags,
+    game_variants,
+    games,
+    info,
+    players,
+    position_index,
+    sites,
+);
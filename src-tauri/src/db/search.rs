@@ -3,9 +3,10 @@ use diesel::prelude::*;
 use log::info;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use shakmaty::{fen::Fen, san::SanPlus, Bitboard, ByColor, Chess, FromSetup, Position, Setup};
+use shakmaty::{fen::Fen, san::SanPlus, Bitboard, Board, ByColor, Color, Position, Setup};
 use specta::Type;
 use std::{
+    collections::HashMap,
     path::PathBuf,
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -17,12 +18,13 @@ use tauri::Emitter;
 
 use crate::{
     db::{
-        encoding::decode_move, get_db_or_create, get_pawn_home, models::*,
-        pgn::{get_material_count, MaterialCount},
-        normalize_games, schema::*, ConnectionOptions,
+        encoding::read_move, game_variant, get_db_or_create, get_pawn_home, models::*,
+        pgn::{get_material_count, GameTree, MaterialCount},
+        normalize_games, position_index as pos_index, schema::*, ConnectionOptions,
     },
     error::Error,
-    AppState,
+    variant::GameVariant,
+    AppState, GameData,
 };
 
 use super::GameQueryJs;
@@ -31,7 +33,9 @@ use super::GameQueryJs;
 pub struct ExactData {
     pawn_home: u16,
     material: MaterialCount,
-    position: Chess,
+    board: Board,
+    turn: Color,
+    variant: GameVariant,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
@@ -39,6 +43,7 @@ pub struct PartialData {
     // piece_counts: Vec<(Piece, u8)>,
     piece_positions: Setup,
     material: MaterialCount,
+    variant: GameVariant,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
@@ -48,25 +53,27 @@ pub enum PositionQuery {
 }
 
 impl PositionQuery {
-    pub fn exact_from_fen(fen: &str) -> Result<PositionQuery, Error> {
-        let position: Chess =
-            Fen::from_ascii(fen.as_bytes())?.into_position(shakmaty::CastlingMode::Chess960)?;
+    pub fn exact_from_fen(fen: &str, variant: GameVariant) -> Result<PositionQuery, Error> {
+        let position = variant.position(Some(fen))?;
         let pawn_home = get_pawn_home(position.board());
         let material = get_material_count(position.board());
         Ok(PositionQuery::Exact(ExactData {
             pawn_home,
             material,
-            position,
+            board: position.board().clone(),
+            turn: position.turn(),
+            variant,
         }))
     }
 
-    pub fn partial_from_fen(fen: &str) -> Result<PositionQuery, Error> {
+    pub fn partial_from_fen(fen: &str, variant: GameVariant) -> Result<PositionQuery, Error> {
         let fen = Fen::from_ascii(fen.as_bytes())?;
         let setup = fen.into_setup();
         let material = get_material_count(&setup.board);
         Ok(PositionQuery::Partial(PartialData {
             piece_positions: setup,
             material,
+            variant,
         }))
     }
 }
@@ -75,21 +82,49 @@ impl PositionQuery {
 pub struct PositionQueryJs {
     pub fen: String,
     pub type_: String,
+    /// Only games of this variant are searched, standard chess if missing
+    #[serde(default)]
+    #[specta(optional)]
+    pub variant: Option<GameVariant>,
 }
 
 fn convert_position_query(query: PositionQueryJs) -> Result<PositionQuery, Error> {
+    let variant = query.variant.unwrap_or_default();
     match query.type_.as_str() {
-        "exact" => PositionQuery::exact_from_fen(&query.fen),
-        "partial" => PositionQuery::partial_from_fen(&query.fen),
+        "exact" => PositionQuery::exact_from_fen(&query.fen, variant),
+        "partial" => PositionQuery::partial_from_fen(&query.fen, variant),
         _ => unreachable!(),
     }
 }
 
 impl PositionQuery {
-    fn matches(&self, position: &Chess) -> bool {
+    /// Side to move in the queried position.
+    fn turn(&self) -> Color {
+        match self {
+            PositionQuery::Exact(ref data) => data.turn,
+            PositionQuery::Partial(ref data) => data.piece_positions.turn,
+        }
+    }
+
+    fn variant(&self) -> GameVariant {
+        match self {
+            PositionQuery::Exact(ref data) => data.variant,
+            PositionQuery::Partial(ref data) => data.variant,
+        }
+    }
+
+    /// Whether pieces can only leave the board and pawns only leave their
+    /// starting squares, which lets us skip games that can't reach the query.
+    fn is_irreversible(&self) -> bool {
+        // Crazyhouse drops can bring both back, Horde pawns can move up from
+        // the first rank
+        !matches!(self.variant(), GameVariant::Crazyhouse | GameVariant::Horde)
+    }
+
+    fn matches<P: Position>(&self, position: &P) -> bool {
         match self {
             PositionQuery::Exact(ref data) => {
-                data.position.board() == position.board() && data.position.turn() == position.turn()
+                &data.board == position.board() && data.turn == position.turn()
             }
             PositionQuery::Partial(ref data) => {
                 let query_board = &data.piece_positions.board;
@@ -108,6 +143,9 @@ impl PositionQuery {
     }
 
     fn is_reachable_by(&self, material: &MaterialCount, pawn_home: u16) -> bool {
+        if !self.is_irreversible() {
+            return true;
+        }
         match self {
             PositionQuery::Exact(ref data) => {
                 is_end_reachable(data.pawn_home, pawn_home)
@@ -118,6 +156,9 @@ impl PositionQuery {
     }
 
     fn can_reach(&self, material: &MaterialCount, pawn_home: u16) -> bool {
+        if !self.is_irreversible() {
+            return true;
+        }
         match self {
             PositionQuery::Exact(ref data) => {
                 is_end_reachable(pawn_home, data.pawn_home)
@@ -153,43 +194,275 @@ pub struct PositionStats {
 }
 
 fn get_move_after_match(
-    move_blob: &Vec<u8>,
+    move_blob: &[u8],
     fen: &Option<String>,
+    variant: GameVariant,
     query: &PositionQuery,
 ) -> Result<Option<String>, Error> {
-    let mut chess = if let Some(fen) = fen {
-        let fen = Fen::from_ascii(fen.as_bytes())?;
-        Chess::from_setup(fen.into_setup(), shakmaty::CastlingMode::Chess960)?
-    } else {
-        Chess::default()
-    };
-
-    if query.matches(&chess) {
-        if move_blob.is_empty() {
-            return Ok(Some("*".to_string()));
-        }
-        let next_move = decode_move(move_blob[0], &chess).unwrap();
-        let san = SanPlus::from_move(chess, &next_move);
-        return Ok(Some(san.to_string()));
+    if variant != query.variant() {
+        return Ok(None);
     }
+    let mut chess = variant.position(fen.as_deref())?;
 
-    for (i, byte) in move_blob.iter().enumerate() {
-        let m = decode_move(*byte, &chess).unwrap();
+    let mut rest = move_blob;
+    loop {
+        if query.matches(&chess) {
+            return Ok(Some(match read_move(rest, &chess) {
+                Some((next_move, _)) => SanPlus::from_move(chess, &next_move).to_string(),
+                None => "*".to_string(),
+            }));
+        }
+        let Some((m, len)) = read_move(rest, &chess) else {
+            return Ok(None);
+        };
+        rest = &rest[len..];
         chess.play_unchecked(&m);
         let board = chess.board();
         if !query.is_reachable_by(&get_material_count(board), get_pawn_home(board)) {
             return Ok(None);
         }
-        if query.matches(&chess) {
-            if i == move_blob.len() - 1 {
-                return Ok(Some("*".to_string()));
+    }
+}
+
+/// Returns the move played from the position at `ply` of the main line, used
+/// to verify and resolve hits from the position index.
+fn get_move_at_ply(
+    move_blob: &[u8],
+    fen: &Option<String>,
+    variant: GameVariant,
+    ply: usize,
+    query: &PositionQuery,
+) -> Result<Option<String>, Error> {
+    if variant != query.variant() {
+        return Ok(None);
+    }
+    let mut chess = variant.position(fen.as_deref())?;
+    let tree = GameTree::from_bytes(move_blob, chess.clone())?;
+    let mut moves = tree.main_line();
+
+    for san in moves.by_ref().take(ply) {
+        let m = san.san.to_move(&chess)?;
+        chess.play_unchecked(&m);
+    }
+
+    if !query.matches(&chess) {
+        return Ok(None);
+    }
+    Ok(Some(
+        moves
+            .next()
+            .map_or_else(|| "*".to_string(), |san| san.to_string()),
+    ))
+}
+
+fn add_move_result(openings: &DashMap<String, PositionStats>, m: String, result: Option<&str>) {
+    let entry = openings.entry(m);
+    match entry {
+        Entry::Occupied(mut e) => {
+            let opening = e.get_mut();
+            match result {
+                Some("1-0") => opening.white += 1,
+                Some("0-1") => opening.black += 1,
+                Some("1/2-1/2") => opening.draw += 1,
+                _ => (),
             }
-            let next_move = decode_move(move_blob[i + 1], &chess).unwrap();
-            let san = SanPlus::from_move(chess, &next_move);
-            return Ok(Some(san.to_string()));
         }
+        Entry::Vacant(e) => {
+            let mut opening = PositionStats {
+                black: 0,
+                white: 0,
+                draw: 0,
+                move_: e.key().to_string(),
+            };
+            match result {
+                Some("1-0") => opening.white = 1,
+                Some("0-1") => opening.black = 1,
+                Some("1/2-1/2") => opening.draw = 1,
+                _ => (),
+            }
+            e.insert(opening);
+        }
+    }
+}
+
+fn load_sample_games(db: &mut SqliteConnection, ids: Vec<i32>) -> Result<Vec<NormalizedGame>, Error> {
+    let (white_players, black_players) = diesel::alias!(players as white, players as black);
+    let games: Vec<(Game, Player, Player, Event, Site)> = games::table
+        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
+        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
+        .inner_join(events::table.on(games::event_id.eq(events::id)))
+        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
+        .filter(games::id.eq_any(ids))
+        .load(db)?;
+    normalize_games(db, games)
+}
+
+/// Returns the exact position query of a search, if it has one.
+fn exact_position_query(query: &GameQueryJs) -> Result<Option<PositionQuery>, Error> {
+    match &query.position {
+        Some(position) if position.type_ == "exact" => {
+            Ok(Some(convert_position_query(position.clone())?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Loads the games used by full scans into the shared cache, if needed.
+fn load_db_cache(db: &mut SqliteConnection, games: &mut Vec<GameData>) -> Result<(), Error> {
+    if games.is_empty() {
+        let start = Instant::now();
+        *games = games::table
+            .select((
+                games::id,
+                games::white_id,
+                games::black_id,
+                games::date,
+                games::result,
+                games::moves,
+                games::fen,
+                games::pawn_home,
+                games::white_material,
+                games::black_material,
+                games::white_elo,
+                games::black_elo,
+            ))
+            .load(db)?;
+
+        info!("got {} games: {:?}", games.len(), start.elapsed());
+    }
+    Ok(())
+}
+
+/// Returns true if the game passes the non positional filters of the query.
+fn matches_game_filters(
+    query: &GameQueryJs,
+    white_id: i32,
+    black_id: i32,
+    date: &Option<String>,
+    result: &Option<String>,
+) -> bool {
+    if let (Some(start_date), Some(date)) = (&query.start_date, date) {
+        if date < start_date {
+            return false;
+        }
+    }
+
+    if let (Some(end_date), Some(date)) = (&query.end_date, date) {
+        if date > end_date {
+            return false;
+        }
+    }
+
+    if query.player1.is_some_and(|white| white != white_id) {
+        return false;
+    }
+
+    if query.player2.is_some_and(|black| black != black_id) {
+        return false;
     }
-    Ok(None)
+
+    if let (Some(result), Some(wanted_result)) = (result, &query.wanted_result) {
+        match wanted_result.as_str() {
+            "whitewon" => return result == "1-0",
+            "blackwon" => return result == "0-1",
+            "draw" => return result == "1/2-1/2",
+            _ => {}
+        }
+    }
+
+    true
+}
+
+/// Returns the games containing an exact position according to the position
+/// index, along with the ply at which the position occurs.
+///
+/// Hits still have to be verified with [`get_move_at_ply`].
+fn load_indexed_candidates(
+    db: &mut SqliteConnection,
+    query: &GameQueryJs,
+    position_query: &PositionQuery,
+) -> Result<Vec<(GameData, i32)>, Error> {
+    let PositionQuery::Exact(data) = position_query else {
+        return Ok(vec![]);
+    };
+    let key = pos_index::position_key(&data.board, data.turn);
+
+    let mut sql_query = position_index::table
+        .inner_join(games::table)
+        .filter(position_index::hash.eq(key))
+        .select((
+            (
+                games::id,
+                games::white_id,
+                games::black_id,
+                games::date,
+                games::result,
+                games::moves,
+                games::fen,
+                games::pawn_home,
+                games::white_material,
+                games::black_material,
+                games::white_elo,
+                games::black_elo,
+            ),
+            position_index::ply,
+        ))
+        .order(games::id)
+        .into_boxed();
+
+    // Same semantics as the full scan: games without a date or result are kept
+    if let Some(start_date) = &query.start_date {
+        sql_query = sql_query.filter(games::date.ge(start_date).or(games::date.is_null()));
+    }
+    if let Some(end_date) = &query.end_date {
+        sql_query = sql_query.filter(games::date.le(end_date).or(games::date.is_null()));
+    }
+    if let Some(white) = query.player1 {
+        sql_query = sql_query.filter(games::white_id.eq(white));
+    }
+    if let Some(black) = query.player2 {
+        sql_query = sql_query.filter(games::black_id.eq(black));
+    }
+    let wanted_result = match query.wanted_result.as_deref() {
+        Some("whitewon") => Some("1-0"),
+        Some("blackwon") => Some("0-1"),
+        Some("draw") => Some("1/2-1/2"),
+        _ => None,
+    };
+    if let Some(wanted_result) = wanted_result {
+        sql_query = sql_query.filter(games::result.eq(wanted_result).or(games::result.is_null()));
+    }
+
+    let candidates: Vec<(GameData, i32)> = sql_query.load(db)?;
+    info!("position index returned {} candidates", candidates.len());
+    Ok(candidates)
+}
+
+/// Looks up an exact position through the position index instead of
+/// replaying every game in the database.
+fn search_indexed(
+    db: &mut SqliteConnection,
+    query: &GameQueryJs,
+    position_query: &PositionQuery,
+) -> Result<(Vec<PositionStats>, Vec<i32>), Error> {
+    let candidates = load_indexed_candidates(db, query, position_query)?;
+    let variants = game_variant::load_all(db)?;
+
+    let openings: DashMap<String, PositionStats> = DashMap::new();
+    let matched: Vec<i32> = candidates
+        .par_iter()
+        .filter_map(|((id, _, _, _, result, moves, fen, ..), ply)| {
+            let variant = variants.get(id).copied().unwrap_or_default();
+            let m = get_move_at_ply(moves, fen, variant, *ply as usize, position_query)
+                .ok()
+                .flatten()?;
+            add_move_result(&openings, m, result.as_deref());
+            Some(*id)
+        })
+        .collect();
+
+    let openings = openings.into_iter().map(|(_, v)| v).collect();
+    Ok((openings, matched.into_iter().take(10).collect()))
 }
 
 #[derive(Clone, serde::Serialize)]
@@ -216,29 +489,33 @@ pub async fn search_position(
 
     // start counting the time
     let start = Instant::now();
+
+    if let Some(position_query) = exact_position_query(&query)? {
+        if pos_index::is_built(db)? {
+            let permit = state.new_request.acquire().await.unwrap();
+            let (openings, ids) = search_indexed(db, &query, &position_query)?;
+            info!("finished indexed search in {:?}", start.elapsed());
+
+            if state.new_request.available_permits() == 0 {
+                drop(permit);
+                return Err(Error::SearchStopped);
+            }
+
+            let normalized_games = load_sample_games(db, ids)?;
+            state
+                .line_cache
+                .insert((query, file), (openings.clone(), normalized_games.clone()));
+            return Ok((openings, normalized_games));
+        }
+    }
+
     info!("start loading games");
 
     let permit = state.new_request.acquire().await.unwrap();
     let mut games = state.db_cache.lock().unwrap();
 
-    if games.is_empty() {
-        *games = games::table
-            .select((
-                games::id,
-                games::white_id,
-                games::black_id,
-                games::date,
-                games::result,
-                games::moves,
-                games::fen,
-                games::pawn_home,
-                games::white_material,
-                games::black_material,
-            ))
-            .load(db)?;
-
-        info!("got {} games: {:?}", games.len(), start.elapsed());
-    }
+    load_db_cache(db, &mut games)?;
+    let variants = game_variant::load_all(db)?;
 
     let openings: DashMap<String, PositionStats> = DashMap::new();
     let sample_games: Mutex<Vec<i32>> = Mutex::new(Vec::new());
@@ -259,6 +536,8 @@ pub async fn search_position(
             end_pawn_home,
             white_material,
             black_material,
+            _white_elo,
+            _black_elo,
         )| {
             if state.new_request.available_permits() == 0 {
                 return;
@@ -282,55 +561,8 @@ pub async fn search_position(
                 .unwrap();
             }
 
-            if let Some(start_date) = &query.start_date {
-                if let Some(date) = date {
-                    if date < start_date {
-                        return;
-                    }
-                }
-            }
-
-            if let Some(end_date) = &query.end_date {
-                if let Some(date) = date {
-                    if date > end_date {
-                        return;
-                    }
-                }
-            }
-
-            if let Some(white) = query.player1 {
-                if white != *white_id {
-                    return;
-                }
-            }
-
-            if let Some(black) = query.player2 {
-                if black != *black_id {
-                    return;
-                }
-            }
-
-            if let Some(result) = result {
-                if let Some(wanted_result) = &query.wanted_result {
-                    match wanted_result.as_str() {
-                        "whitewon" => {
-                            if result != "1-0" {
-                                return
-                            } 
-                        }
-                        "blackwon" => {
-                            if result != "0-1" {
-                                return
-                            } 
-                        }
-                        "draw" => {
-                            if result != "1/2-1/2" {
-                                return
-                            } 
-                        }
-                        &_ => {}
-                    }
-                }
+            if !matches_game_filters(&query, *white_id, *black_id, date, result) {
+                return;
             }
 
             if let Some(position_query) = &query.position {
@@ -339,7 +571,8 @@ pub async fn search_position(
                         Err(_) => return,
                     };
                 if position_query.can_reach(&end_material, *end_pawn_home as u16) {
-                    if let Ok(Some(m)) = get_move_after_match(game, fen, &position_query) {
+                    let variant = variants.get(id).copied().unwrap_or_default();
+                    if let Ok(Some(m)) = get_move_after_match(game, fen, variant, &position_query) {
                         let mut guard = match sample_games.lock() {
                             Ok(guard) => guard,
                             Err(_) => return,
@@ -347,33 +580,7 @@ pub async fn search_position(
                         if guard.len() < 10 {
                             guard.push(*id);
                         }
-                        let entry = openings.entry(m);
-                        match entry {
-                            Entry::Occupied(mut e) => {
-                                let opening = e.get_mut();
-                                match result.as_deref() {
-                                    Some("1-0") => opening.white += 1,
-                                    Some("0-1") => opening.black += 1,
-                                    Some("1/2-1/2") => opening.draw += 1,
-                                    _ => (),
-                                }
-                            }
-                            Entry::Vacant(e) => {
-                                let mut opening = PositionStats {
-                                    black: 0,
-                                    white: 0,
-                                    draw: 0,
-                                    move_: e.key().to_string(),
-                                };
-                                match result.as_deref() {
-                                    Some("1-0") => opening.white = 1,
-                                    Some("0-1") => opening.black = 1,
-                                    Some("1/2-1/2") => opening.draw = 1,
-                                    _ => (),
-                                }
-                                e.insert(opening);
-                            }
-                        }
+                        add_move_result(&openings, m, result.as_deref());
                     }
                 }
             }
@@ -392,15 +599,7 @@ pub async fn search_position(
         return Err(Error::SearchStopped);
     }
 
-    let (white_players, black_players) = diesel::alias!(players as white, players as black);
-    let games: Vec<(Game, Player, Player, Event, Site)> = games::table
-        .inner_join(white_players.on(games::white_id.eq(white_players.field(players::id))))
-        .inner_join(black_players.on(games::black_id.eq(black_players.field(players::id))))
-        .inner_join(events::table.on(games::event_id.eq(events::id)))
-        .inner_join(sites::table.on(games::site_id.eq(sites::id)))
-        .filter(games::id.eq_any(ids))
-        .load(db)?;
-    let normalized_games = normalize_games(games)?;
+    let normalized_games = load_sample_games(db, ids)?;
 
     state
         .line_cache
@@ -420,6 +619,25 @@ pub async fn is_position_in_db(
         return Ok(!pos.0.is_empty());
     }
 
+    let variants = game_variant::load_all(db)?;
+
+    if let Some(position_query) = exact_position_query(&query)? {
+        if pos_index::is_built(db)? {
+            // The hashes can collide, and the filters of the query only apply to the games
+            let exists = load_indexed_candidates(db, &query, &position_query)?
+                .iter()
+                .any(|((id, _, _, _, _, moves, fen, ..), ply)| {
+                    let variant = variants.get(id).copied().unwrap_or_default();
+                    get_move_at_ply(moves, fen, variant, *ply as usize, &position_query)
+                        .is_ok_and(|m| m.is_some())
+                });
+            if !exists {
+                state.line_cache.insert((query, file), (vec![], vec![]));
+            }
+            return Ok(exists);
+        }
+    }
+
     // start counting the time
     let start = Instant::now();
     info!("start loading games");
@@ -427,28 +645,11 @@ pub async fn is_position_in_db(
     let permit = state.new_request.acquire().await.unwrap();
     let mut games = state.db_cache.lock().unwrap();
 
-    if games.is_empty() {
-        *games = games::table
-            .select((
-                games::id,
-                games::white_id,
-                games::black_id,
-                games::date,
-                games::result,
-                games::moves,
-                games::fen,
-                games::pawn_home,
-                games::white_material,
-                games::black_material,
-            ))
-            .load(db)?;
-
-        info!("got {} games: {:?}", games.len(), start.elapsed());
-    }
+    load_db_cache(db, &mut games)?;
 
     let exists = games.par_iter().any(
         |(
-            _id,
+            id,
             _white_id,
             _black_id,
             _date,
@@ -458,6 +659,8 @@ pub async fn is_position_in_db(
             end_pawn_home,
             white_material,
             black_material,
+            _white_elo,
+            _black_elo,
         )| {
             if state.new_request.available_permits() == 0 {
                 return false;
@@ -469,8 +672,9 @@ pub async fn is_position_in_db(
             if let Some(position_query) = &query.position {
                 let position_query =
                     convert_position_query(position_query.clone()).expect("Invalid position query");
+                let variant = variants.get(id).copied().unwrap_or_default();
                 position_query.can_reach(&end_material, *end_pawn_home as u16)
-                    && get_move_after_match(game, fen, &position_query)
+                    && get_move_after_match(game, fen, variant, &position_query)
                         .unwrap_or(None)
                         .is_some()
             } else {
@@ -492,12 +696,274 @@ pub async fn is_position_in_db(
     Ok(exists)
 }
 
+#[derive(Debug, Serialize, Clone, Type)]
+pub struct ExplorerMove {
+    #[serde(rename = "move")]
+    pub move_: String,
+    pub white: i32,
+    pub draw: i32,
+    pub black: i32,
+    /// Average rating of the players of both sides
+    pub average_elo: Option<i32>,
+    /// Performance rating of the side playing the move
+    pub performance: Option<i32>,
+    pub last_played: Option<String>,
+    /// Highest rated games in which the move was played
+    pub top_games: Vec<NormalizedGame>,
+}
+
+/// Running totals for a single move of the explorer.
+#[derive(Debug, Default)]
+struct MoveAggregate {
+    white: i32,
+    draw: i32,
+    black: i32,
+    elo_sum: i64,
+    elo_count: i64,
+    opponent_elo_sum: i64,
+    /// Points scored by the side playing the move, in half points
+    half_points: i64,
+    rated_games: i64,
+    last_played: Option<String>,
+    /// (average rating, game id), sorted from highest to lowest
+    top_games: Vec<(i32, i32)>,
+}
+
+impl MoveAggregate {
+    fn add(
+        &mut self,
+        id: i32,
+        result: Option<&str>,
+        date: Option<&str>,
+        elos: ByColor<Option<i32>>,
+        mover: Color,
+        top_games: usize,
+    ) {
+        let half_points = match result {
+            Some("1-0") => {
+                self.white += 1;
+                Some(if mover == Color::White { 2 } else { 0 })
+            }
+            Some("0-1") => {
+                self.black += 1;
+                Some(if mover == Color::Black { 2 } else { 0 })
+            }
+            Some("1/2-1/2") => {
+                self.draw += 1;
+                Some(1)
+            }
+            _ => None,
+        };
+
+        let known: Vec<i32> = [elos.white, elos.black].into_iter().flatten().collect();
+        for elo in &known {
+            self.elo_sum += *elo as i64;
+            self.elo_count += 1;
+        }
+
+        if let (Some(points), Some(opponent_elo)) = (half_points, elos.get(!mover)) {
+            self.half_points += points;
+            self.opponent_elo_sum += *opponent_elo as i64;
+            self.rated_games += 1;
+        }
+
+        // Dates like "????.??.??" are unknown and would sort after real ones
+        if let Some(date) = date.filter(|d| !d.starts_with('?')) {
+            if self.last_played.as_deref().map_or(true, |last| date > last) {
+                self.last_played = Some(date.to_string());
+            }
+        }
+
+        if top_games > 0 {
+            let average = if known.is_empty() {
+                0
+            } else {
+                known.iter().sum::<i32>() / known.len() as i32
+            };
+            let pos = self.top_games.partition_point(|(elo, _)| *elo >= average);
+            if pos < top_games {
+                self.top_games.insert(pos, (average, id));
+                self.top_games.truncate(top_games);
+            }
+        }
+    }
+
+    fn average_elo(&self) -> Option<i32> {
+        (self.elo_count > 0).then(|| (self.elo_sum / self.elo_count) as i32)
+    }
+
+    /// Performance rating using the logistic Elo curve, capped at +-800 for
+    /// perfect scores.
+    fn performance(&self) -> Option<i32> {
+        if self.rated_games == 0 {
+            return None;
+        }
+        let opponents = self.opponent_elo_sum as f64 / self.rated_games as f64;
+        let score = (self.half_points as f64 / (2 * self.rated_games) as f64).clamp(0.01, 0.99);
+        Some((opponents + 400.0 * (score / (1.0 - score)).log10()).round() as i32)
+    }
+}
+
+fn add_to_explorer(
+    moves: &DashMap<String, MoveAggregate>,
+    m: String,
+    game: &GameData,
+    mover: Color,
+    top_games: usize,
+) {
+    let (id, _, _, date, result, .., white_elo, black_elo) = game;
+    moves.entry(m).or_default().add(
+        *id,
+        result.as_deref(),
+        date.as_deref(),
+        ByColor {
+            white: *white_elo,
+            black: *black_elo,
+        },
+        mover,
+        top_games,
+    );
+}
+
+/// Explores a position: for every move played from it, returns the results,
+/// average rating, performance, last played date and the highest rated games.
+#[tauri::command]
+#[specta::specta]
+pub async fn explore_position(
+    file: PathBuf,
+    query: GameQueryJs,
+    top_games: u32,
+    app: tauri::AppHandle,
+    tab_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<ExplorerMove>, Error> {
+    let db = &mut get_db_or_create(&state, file.to_str().unwrap(), ConnectionOptions::default())?;
+
+    let Some(position_query) = query.position.clone() else {
+        return Ok(vec![]);
+    };
+    let position_query = convert_position_query(position_query)?;
+    let mover = position_query.turn();
+    let top_games = top_games as usize;
+
+    let start = Instant::now();
+    let permit = state.new_request.acquire().await.unwrap();
+    let moves: DashMap<String, MoveAggregate> = DashMap::new();
+
+    let variants = game_variant::load_all(db)?;
+
+    if matches!(position_query, PositionQuery::Exact(_)) && pos_index::is_built(db)? {
+        let candidates = load_indexed_candidates(db, &query, &position_query)?;
+        candidates.par_iter().for_each(|(game, ply)| {
+            if state.new_request.available_permits() == 0 {
+                return;
+            }
+            let (id, _, _, _, _, moves_blob, fen, ..) = game;
+            let variant = variants.get(id).copied().unwrap_or_default();
+            if let Ok(Some(m)) =
+                get_move_at_ply(moves_blob, fen, variant, *ply as usize, &position_query)
+            {
+                add_to_explorer(&moves, m, game, mover, top_games);
+            }
+        });
+    } else {
+        let mut games = state.db_cache.lock().unwrap();
+        load_db_cache(db, &mut games)?;
+
+        let processed = AtomicUsize::new(0);
+        games.par_iter().for_each(|game| {
+            if state.new_request.available_permits() == 0 {
+                return;
+            }
+            let (
+                id,
+                white_id,
+                black_id,
+                date,
+                result,
+                moves_blob,
+                fen,
+                end_pawn_home,
+                white_material,
+                black_material,
+                ..,
+            ) = game;
+
+            let index = processed.fetch_add(1, Ordering::Relaxed);
+            if (index + 1) % 10000 == 0 {
+                app.emit(
+                    "search_progress",
+                    ProgressPayload {
+                        progress: (index as f64 / games.len() as f64) * 100.0,
+                        id: tab_id.clone(),
+                        finished: false,
+                    },
+                )
+                .unwrap();
+            }
+
+            if !matches_game_filters(&query, *white_id, *black_id, date, result) {
+                return;
+            }
+            let end_material: MaterialCount = ByColor {
+                white: *white_material as u8,
+                black: *black_material as u8,
+            };
+            if position_query.can_reach(&end_material, *end_pawn_home as u16) {
+                let variant = variants.get(id).copied().unwrap_or_default();
+                if let Ok(Some(m)) = get_move_after_match(moves_blob, fen, variant, &position_query)
+                {
+                    add_to_explorer(&moves, m, game, mover, top_games);
+                }
+            }
+        });
+    }
+
+    info!("finished exploring position in {:?}", start.elapsed());
+
+    if state.new_request.available_permits() == 0 {
+        drop(permit);
+        return Err(Error::SearchStopped);
+    }
+
+    let ids: Vec<i32> = moves
+        .iter()
+        .flat_map(|entry| entry.top_games.iter().map(|(_, id)| *id).collect::<Vec<_>>())
+        .collect();
+    let games: HashMap<i32, NormalizedGame> = load_sample_games(db, ids)?
+        .into_iter()
+        .map(|game| (game.id, game))
+        .collect();
+
+    let mut result: Vec<ExplorerMove> = moves
+        .into_iter()
+        .map(|(move_, aggregate)| ExplorerMove {
+            move_,
+            white: aggregate.white,
+            draw: aggregate.draw,
+            black: aggregate.black,
+            average_elo: aggregate.average_elo(),
+            performance: aggregate.performance(),
+            last_played: aggregate.last_played.clone(),
+            top_games: aggregate
+                .top_games
+                .iter()
+                .filter_map(|(_, id)| games.get(id).cloned())
+                .collect(),
+        })
+        .collect();
+    result.sort_by_key(|m| std::cmp::Reverse(m.white + m.draw + m.black));
+
+    Ok(result)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use shakmaty::{Chess, FromSetup};
 
     fn assert_partial_match(fen1: &str, fen2: &str) {
-        let query = PositionQuery::partial_from_fen(fen1).unwrap();
+        let query = PositionQuery::partial_from_fen(fen1, GameVariant::Standard).unwrap();
         let fen = Fen::from_ascii(fen2.as_bytes()).unwrap();
         let chess = Chess::from_setup(fen.into_setup(), shakmaty::CastlingMode::Chess960).unwrap();
         assert!(query.matches(&chess));
@@ -505,9 +971,7 @@ mod tests {
 
     #[test]
     fn exact_matches() {
-        let query = PositionQuery::exact_from_fen(
-            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
-        )
+        let query = PositionQuery::exact_from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", GameVariant::Standard)
         .unwrap();
         let chess = Chess::default();
         assert!(query.matches(&chess));
@@ -545,7 +1009,7 @@ mod tests {
     #[test]
     fn correct_exact_is_reachable() {
         let query =
-            PositionQuery::exact_from_fen("rnbqkb1r/pppp1ppp/5n2/4p3/4P3/2N5/PPPP1PPP/R1BQKBNR")
+            PositionQuery::exact_from_fen("rnbqkb1r/pppp1ppp/5n2/4p3/4P3/2N5/PPPP1PPP/R1BQKBNR", GameVariant::Standard)
                 .unwrap();
         let chess = Chess::default();
         assert!(query.is_reachable_by(
@@ -556,7 +1020,7 @@ mod tests {
 
     #[test]
     fn correct_partial_is_reachable() {
-        let query = PositionQuery::partial_from_fen("8/8/8/8/8/8/8/8").unwrap();
+        let query = PositionQuery::partial_from_fen("8/8/8/8/8/8/8/8", GameVariant::Standard).unwrap();
         let chess = Chess::default();
         assert!(query.is_reachable_by(
             &get_material_count(chess.board()),
@@ -566,7 +1030,7 @@ mod tests {
 
     #[test]
     fn correct_partial_can_reach() {
-        let query = PositionQuery::partial_from_fen("8/8/8/8/8/8/8/8").unwrap();
+        let query = PositionQuery::partial_from_fen("8/8/8/8/8/8/8/8", GameVariant::Standard).unwrap();
         let chess = Chess::default();
         assert!(query.can_reach(
             &get_material_count(chess.board()),
@@ -579,19 +1043,19 @@ mod tests {
         let game = vec![12, 12]; // 1. e4 e5
 
         let query =
-            PositionQuery::exact_from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR").unwrap();
-        let result = get_move_after_match(&game, &None, &query).unwrap();
+            PositionQuery::exact_from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR", GameVariant::Standard).unwrap();
+        let result = get_move_after_match(&game, &None, GameVariant::Standard, &query).unwrap();
         assert_eq!(result, Some("e4".to_string()));
 
         let query =
-            PositionQuery::exact_from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR").unwrap();
-        let result = get_move_after_match(&game, &None, &query).unwrap();
+            PositionQuery::exact_from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR", GameVariant::Standard).unwrap();
+        let result = get_move_after_match(&game, &None, GameVariant::Standard, &query).unwrap();
         assert_eq!(result, Some("e5".to_string()));
 
         let query =
-            PositionQuery::exact_from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR")
+            PositionQuery::exact_from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR", GameVariant::Standard)
                 .unwrap();
-        let result = get_move_after_match(&game, &None, &query).unwrap();
+        let result = get_move_after_match(&game, &None, GameVariant::Standard, &query).unwrap();
         assert_eq!(result, Some("*".to_string()));
     }
 
@@ -599,8 +1063,56 @@ mod tests {
     fn get_move_after_partial_match_test() {
         let game = vec![12, 12]; // 1. e4 e5
 
-        let query = PositionQuery::partial_from_fen("8/pppppppp/8/8/8/8/PPPPPPPP/8").unwrap();
-        let result = get_move_after_match(&game, &None, &query).unwrap();
+        let query = PositionQuery::partial_from_fen("8/pppppppp/8/8/8/8/PPPPPPPP/8", GameVariant::Standard).unwrap();
+        let result = get_move_after_match(&game, &None, GameVariant::Standard, &query).unwrap();
         assert_eq!(result, Some("e4".to_string()));
     }
+
+    #[test]
+    fn get_move_after_match_in_variant() {
+        use crate::db::pgn::Importer;
+        use pgn_reader::BufferedReader;
+
+        let pgn = b"[Variant \"Crazyhouse\"]\n\n1.e4 d5 2.exd5 Qxd5 3.P@e4 *";
+        let game = BufferedReader::new_cursor(&pgn[..])
+            .read_game(&mut Importer::new(None))
+            .unwrap()
+            .flatten()
+            .unwrap();
+        let fen = "rnb1kbnr/ppp1pppp/8/3q4/8/8/PPPP1PPP/RNBQKBNR[Pp] w KQkq - 0 3";
+
+        let query = PositionQuery::exact_from_fen(fen, GameVariant::Crazyhouse).unwrap();
+        let result = get_move_after_match(&game.moves, &None, GameVariant::Crazyhouse, &query);
+        assert!(result.unwrap().is_some_and(|m| m.ends_with("@e4")));
+
+        // Standard games never match a variant query and the other way around
+        let result = get_move_after_match(&game.moves, &None, GameVariant::Standard, &query);
+        assert_eq!(result.unwrap(), None);
+        let query = PositionQuery::exact_from_fen(
+            "rnb1kbnr/ppp1pppp/8/3q4/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3",
+            GameVariant::Standard,
+        )
+        .unwrap();
+        let result = get_move_after_match(&game.moves, &None, GameVariant::Crazyhouse, &query);
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[test]
+    fn explorer_aggregates_from_movers_point_of_view() {
+        let mut aggregate = MoveAggregate::default();
+        let elos = |white, black| ByColor {
+            white: Some(white),
+            black: Some(black),
+        };
+        aggregate.add(1, Some("1-0"), Some("2020.01.01"), elos(2000, 1800), Color::White, 2);
+        aggregate.add(2, Some("1/2-1/2"), Some("2021.05.01"), elos(2200, 2000), Color::White, 2);
+        aggregate.add(3, Some("0-1"), Some("????.??.??"), elos(2400, 2400), Color::White, 2);
+
+        assert_eq!((aggregate.white, aggregate.draw, aggregate.black), (1, 1, 1));
+        assert_eq!(aggregate.average_elo(), Some(2133));
+        assert_eq!(aggregate.last_played.as_deref(), Some("2021.05.01"));
+        // Scored 50% against an average of 2066
+        assert_eq!(aggregate.performance(), Some(2067));
+        assert_eq!(aggregate.top_games, vec![(2400, 3), (2100, 2)]);
+    }
 }
@@ -0,0 +1,264 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use dashmap::DashMap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
+use tauri_specta::Event;
+
+use crate::{
+    chess::{EngineOption, EngineOptions},
+    error::Result,
+    AppState,
+};
+
+/// Hash size of engines that don't set it, the usual UCI default.
+const DEFAULT_HASH: u32 = 16;
+
+static NEXT_RESERVATION: AtomicU64 = AtomicU64::new(0);
+
+/// Sent when an engine stops without being asked to.
+#[derive(Serialize, Debug, Clone, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineCrashed {
+    pub engine: String,
+    pub tab: String,
+    /// `None` for remote engines, or processes killed by a signal
+    pub exit_code: Option<i32>,
+    /// Whether the engine was started again and resumed its search
+    pub restarted: bool,
+}
+
+/// Resources of a running engine.
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineStats {
+    pub engine: String,
+    pub tab: String,
+    /// `None` for remote engines
+    pub pid: Option<u32>,
+    /// Percentage of one core used since the previous call
+    pub cpu_usage: Option<f32>,
+    /// Resident memory, in bytes
+    pub memory: Option<u64>,
+    pub threads: u32,
+    /// Hash size, in MB
+    pub hash: u32,
+    pub running: bool,
+}
+
+/// Resources shared by all the engines running at once.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Type, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineLimits {
+    pub max_threads: u32,
+    /// Total hash size, in MB
+    pub max_hash: u32,
+}
+
+impl Default for EngineLimits {
+    /// All the cores, and half of the memory.
+    fn default() -> Self {
+        let mut system = System::new();
+        system.refresh_memory();
+        Self {
+            max_threads: std::thread::available_parallelism().map_or(1, |n| n.get() as u32),
+            max_hash: (system.total_memory() / (1024 * 1024) / 2).max(DEFAULT_HASH as u64) as u32,
+        }
+    }
+}
+
+/// Threads and hash, in MB, of an engine that isn't in `engine_processes`,
+/// such as the players of a match.
+pub struct ReservedEngine {
+    key: (String, String),
+    threads: u32,
+    hash: u32,
+}
+
+/// Keeps an engine counted in the limits of the others until dropped.
+pub struct EngineReservation {
+    reservations: Arc<DashMap<u64, ReservedEngine>>,
+    id: u64,
+}
+
+impl Drop for EngineReservation {
+    fn drop(&mut self) {
+        self.reservations.remove(&self.id);
+    }
+}
+
+fn option_value(options: &[EngineOption], name: &str) -> Option<u32> {
+    options
+        .iter()
+        .find(|option| option.name.eq_ignore_ascii_case(name))
+        .and_then(|option| option.value.parse().ok())
+}
+
+fn threads_and_hash(options: &[EngineOption]) -> (u32, u32) {
+    (
+        option_value(options, "Threads").unwrap_or(1),
+        option_value(options, "Hash").unwrap_or(DEFAULT_HASH),
+    )
+}
+
+/// Lowers an option to `max` if it's set higher.
+fn clamp_option(options: &mut [EngineOption], name: &str, max: u32) {
+    let Some(option) = options
+        .iter_mut()
+        .find(|option| option.name.eq_ignore_ascii_case(name))
+    else {
+        return;
+    };
+    if option.value.parse::<u32>().is_ok_and(|value| value > max) {
+        warn!(
+            "Lowering {} from {} to {} to stay within the engine limits",
+            name, option.value, max
+        );
+        option.value = max.to_string();
+    }
+}
+
+/// Lowers the Threads and Hash of the options so that, added to what the other
+/// engines use, they stay within the limits.
+pub async fn apply_limits(state: &AppState, key: &(String, String), options: &mut EngineOptions) {
+    clamp_to_limits(state, key, &mut options.extra_options).await;
+}
+
+/// Applies the limits to an engine started outside of `engine_processes`, and
+/// counts it in the limits of the engines started while the reservation is kept.
+pub async fn reserve(
+    state: &AppState,
+    key: (String, String),
+    options: &mut [EngineOption],
+) -> EngineReservation {
+    clamp_to_limits(state, &key, options).await;
+    let (threads, hash) = threads_and_hash(options);
+    let id = NEXT_RESERVATION.fetch_add(1, Ordering::Relaxed);
+    state
+        .engine_reservations
+        .insert(id, ReservedEngine { key, threads, hash });
+    EngineReservation {
+        reservations: state.engine_reservations.clone(),
+        id,
+    }
+}
+
+async fn clamp_to_limits(state: &AppState, key: &(String, String), options: &mut [EngineOption]) {
+    let limits = *state.engine_limits.read().await;
+    let others: Vec<_> = state
+        .engine_processes
+        .iter()
+        .filter(|entry| entry.key() != key)
+        .map(|entry| entry.value().clone())
+        .collect();
+
+    let (mut threads, mut hash) = (0, 0);
+    for process in others {
+        let process = process.lock().await;
+        let (process_threads, process_hash) = threads_and_hash(&process.options().extra_options);
+        threads += process_threads;
+        hash += process_hash;
+    }
+    for entry in state.engine_reservations.iter() {
+        if &entry.key != key {
+            threads += entry.threads;
+            hash += entry.hash;
+        }
+    }
+
+    clamp_option(
+        options,
+        "Threads",
+        limits.max_threads.saturating_sub(threads).max(1),
+    );
+    clamp_option(options, "Hash", limits.max_hash.saturating_sub(hash).max(1));
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn get_engine_stats(state: tauri::State<'_, AppState>) -> Result<Vec<EngineStats>> {
+    let processes: Vec<_> = state
+        .engine_processes
+        .iter()
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect();
+
+    let mut stats = Vec::with_capacity(processes.len());
+    for ((tab, engine), process) in processes {
+        let process = process.lock().await;
+        let (threads, hash) = threads_and_hash(&process.options().extra_options);
+        stats.push(EngineStats {
+            engine,
+            tab,
+            pid: process.pid(),
+            cpu_usage: None,
+            memory: None,
+            threads,
+            hash,
+            running: process.is_running(),
+        });
+    }
+
+    let mut system = state.system.lock().await;
+    for stat in &mut stats {
+        let Some(pid) = stat.pid.map(Pid::from_u32) else {
+            continue;
+        };
+        if system.refresh_process(pid) {
+            if let Some(process) = system.process(pid) {
+                stat.cpu_usage = Some(process.cpu_usage());
+                stat.memory = Some(process.memory());
+            }
+        }
+    }
+    Ok(stats)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn get_engine_limits(state: tauri::State<'_, AppState>) -> Result<EngineLimits> {
+    Ok(*state.engine_limits.read().await)
+}
+
+/// Only applies to engines started or reconfigured afterwards.
+#[tauri::command]
+#[specta::specta]
+pub async fn set_engine_limits(
+    limits: EngineLimits,
+    state: tauri::State<'_, AppState>,
+) -> Result<()> {
+    info!(
+        "Engine limits set to {} threads and {} MB of hash",
+        limits.max_threads, limits.max_hash
+    );
+    *state.engine_limits.write().await = limits;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(name: &str, value: &str) -> EngineOption {
+        EngineOption {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn clamp_options() {
+        let mut options = vec![option("Threads", "16"), option("Hash", "256")];
+        clamp_option(&mut options, "threads", 8);
+        clamp_option(&mut options, "Hash", 1024);
+        clamp_option(&mut options, "MultiPV", 1);
+        assert_eq!(options, vec![option("Threads", "8"), option("Hash", "256")]);
+        assert_eq!(option_value(&options, "THREADS"), Some(8));
+        assert_eq!(option_value(&options, "MultiPV"), None);
+    }
+}
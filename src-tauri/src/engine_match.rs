@@ -0,0 +1,1033 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use pgn_reader::{BufferedReader, RawHeader, SanPlus, Skip, Visitor};
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen::Fen,
+    uci::UciMove,
+    zobrist::{Zobrist64, ZobristHash},
+    CastlingMode, Chess, Color, EnPassantMode, Outcome, Position,
+};
+use shakmaty_syzygy::Tablebase;
+use specta::Type;
+use tauri_specta::Event;
+use tokio::{io::AsyncBufReadExt, time::timeout};
+use vampirc_uci::{parse_one, uci::ScoreValue, UciInfoAttribute, UciMessage};
+
+use crate::{
+    chess::{EngineOption, EngineOptions, EngineProcess, GoMode, MatchStatistics, SprtSettings},
+    db::add_pgn_games,
+    engine_health,
+    engine_transport::EngineReader,
+    error::Error,
+    match_stats::ResultCounts,
+    tablebase::TablebaseWdl,
+    AppState,
+};
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Time an engine gets on top of its clock before losing on time, to make up
+/// for the communication overhead.
+pub(crate) const TIME_MARGIN: Duration = Duration::from_millis(100);
+
+/// How long to wait for a `bestmove` after stopping an engine.
+pub(crate) const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Centipawn value used for mate scores by the adjudication.
+const MATE_SCORE: i32 = 100_000;
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchEngine {
+    pub name: String,
+    pub path: PathBuf,
+    pub options: Vec<EngineOption>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Type, PartialEq, Eq)]
+pub enum TournamentFormat {
+    /// Every engine plays every other engine
+    RoundRobin,
+    /// The first engine plays every other engine
+    Gauntlet,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Adjudication {
+    /// Score in centipawns from which a game is adjudicated as won
+    pub win_score: Option<i32>,
+    /// Number of moves of each side the score has to stay above `win_score`
+    pub win_moves: u32,
+    /// Score in centipawns under which a game is adjudicated as drawn
+    pub draw_score: Option<i32>,
+    /// Number of moves of each side the score has to stay under `draw_score`
+    pub draw_moves: u32,
+    /// Move number from which draws can be adjudicated
+    pub draw_move_number: u32,
+    /// Games reaching this move number are adjudicated as drawn
+    pub max_moves: Option<u32>,
+    /// Adjudicates games with the loaded tablebases once few enough pieces are left
+    pub tablebase: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchSettings {
+    pub engines: Vec<MatchEngine>,
+    pub format: TournamentFormat,
+    /// Games played by each pairing, colors alternate and each opening is played twice
+    pub games_per_pairing: u32,
+    pub go_mode: GoMode,
+    /// EPD or PGN file with the starting positions
+    pub openings: Option<PathBuf>,
+    #[serde(default)]
+    pub adjudication: Adjudication,
+    pub event: Option<String>,
+    /// PGN file the games are appended to
+    pub pgn_output: Option<PathBuf>,
+    /// Database the games are added to
+    pub db_output: Option<PathBuf>,
+    /// Stops the match once the test for the first engine is conclusive
+    pub sprt: Option<SprtSettings>,
+}
+
+#[derive(Serialize, Debug, Clone, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchProgress {
+    pub id: String,
+    pub game: u32,
+    pub total_games: u32,
+    pub white: String,
+    pub black: String,
+    pub fen: String,
+    pub moves: Vec<String>,
+    pub result: Option<String>,
+    pub finished: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchGameResult {
+    pub round: u32,
+    pub white: String,
+    pub black: String,
+    pub result: String,
+    pub termination: String,
+    pub plies: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Opening {
+    fen: Option<String>,
+    moves: Vec<String>,
+}
+
+/// Reads the main line and starting position of every game of a PGN.
+#[derive(Default)]
+struct OpeningVisitor {
+    position: Chess,
+    opening: Opening,
+    invalid: bool,
+}
+
+impl Visitor for OpeningVisitor {
+    type Result = Option<Opening>;
+
+    fn begin_game(&mut self) {
+        self.position = Chess::default();
+        self.opening = Opening::default();
+        self.invalid = false;
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        if key == b"FEN" {
+            let fen = value.decode_utf8_lossy().into_owned();
+            match Fen::from_ascii(fen.as_bytes())
+                .ok()
+                .and_then(|fen| fen.into_position(CastlingMode::Chess960).ok())
+            {
+                Some(position) => {
+                    self.position = position;
+                    self.opening.fen = Some(fen);
+                }
+                None => self.invalid = true,
+            }
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        Skip(self.invalid)
+    }
+
+    fn san(&mut self, san_plus: SanPlus) {
+        if self.invalid {
+            return;
+        }
+        match san_plus.san.to_move(&self.position) {
+            Ok(m) => {
+                self.opening
+                    .moves
+                    .push(m.to_uci(CastlingMode::Standard).to_string());
+                self.position.play_unchecked(&m);
+            }
+            Err(_) => self.invalid = true,
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        (!self.invalid).then(|| std::mem::take(&mut self.opening))
+    }
+}
+
+fn parse_epd(data: &str) -> Result<Vec<Opening>, Error> {
+    data.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            // EPD only has the first four FEN fields, followed by operations
+            let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+            let fen = format!("{} 0 1", fields.join(" "));
+            Fen::from_ascii(fen.as_bytes())?;
+            Ok(Opening {
+                fen: Some(fen),
+                moves: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+fn read_openings(path: &Path) -> Result<Vec<Opening>, Error> {
+    let data = std::fs::read_to_string(path)?;
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("epd"))
+    {
+        return parse_epd(&data);
+    }
+
+    let mut reader = BufferedReader::new_cursor(data.as_bytes());
+    let mut visitor = OpeningVisitor::default();
+    let mut openings = Vec::new();
+    while let Some(opening) = reader.read_game(&mut visitor)? {
+        openings.extend(opening);
+    }
+    Ok(openings)
+}
+
+/// Returns the pairs of engines (by index) that play each other.
+fn pairings(engines: usize, format: TournamentFormat) -> Vec<(usize, usize)> {
+    match format {
+        TournamentFormat::RoundRobin => (0..engines)
+            .flat_map(|a| (a + 1..engines).map(move |b| (a, b)))
+            .collect(),
+        TournamentFormat::Gauntlet => (1..engines).map(|b| (0, b)).collect(),
+    }
+}
+
+fn score_to_cp(value: &ScoreValue) -> i32 {
+    match value {
+        ScoreValue::Cp(cp) => *cp as i32,
+        ScoreValue::Mate(moves) => {
+            if *moves > 0 {
+                MATE_SCORE
+            } else {
+                -MATE_SCORE
+            }
+        }
+    }
+}
+
+/// Keeps track of the scores reported by the engines to adjudicate games.
+#[derive(Debug, Default)]
+struct Adjudicator {
+    white_winning: u32,
+    black_winning: u32,
+    drawish: u32,
+}
+
+impl Adjudicator {
+    /// Records the score of the last move, from white's point of view, and
+    /// returns the result if the game can be adjudicated.
+    fn update(
+        &mut self,
+        rules: &Adjudication,
+        score: Option<i32>,
+        move_number: u32,
+    ) -> Option<&'static str> {
+        let Some(score) = score else {
+            *self = Adjudicator::default();
+            return None;
+        };
+
+        if let Some(win_score) = rules.win_score {
+            if score >= win_score {
+                self.white_winning += 1;
+            } else {
+                self.white_winning = 0;
+            }
+            if score <= -win_score {
+                self.black_winning += 1;
+            } else {
+                self.black_winning = 0;
+            }
+            // Both engines have to agree, so count the moves of each side
+            let plies = 2 * rules.win_moves.max(1);
+            if self.white_winning >= plies {
+                return Some("1-0");
+            }
+            if self.black_winning >= plies {
+                return Some("0-1");
+            }
+        }
+
+        if let Some(draw_score) = rules.draw_score {
+            if move_number >= rules.draw_move_number && score.abs() <= draw_score {
+                self.drawish += 1;
+            } else {
+                self.drawish = 0;
+            }
+            if self.drawish >= 2 * rules.draw_moves.max(1) {
+                return Some("1/2-1/2");
+            }
+        }
+
+        None
+    }
+}
+
+/// Result of a tablebase outcome for the side to move, unless the 50 move
+/// rule could still change it.
+fn wdl_result(wdl: TablebaseWdl, turn: Color) -> Option<&'static str> {
+    let winner = match wdl {
+        TablebaseWdl::Win => turn,
+        TablebaseWdl::Loss => !turn,
+        TablebaseWdl::Draw | TablebaseWdl::CursedWin | TablebaseWdl::BlessedLoss => {
+            return Some("1/2-1/2")
+        }
+        TablebaseWdl::MaybeWin | TablebaseWdl::MaybeLoss => return None,
+    };
+    Some(if winner == Color::White { "1-0" } else { "0-1" })
+}
+
+/// Returns the result of the position if the tablebases know it.
+fn tablebase_result(tables: &Tablebase<Chess>, pos: &Chess) -> Option<&'static str> {
+    if pos.board().occupied().count() > tables.max_pieces() {
+        return None;
+    }
+    // Fails on positions with castling rights, which aren't in the tables
+    let wdl = tables.probe_wdl(pos).ok()?;
+    wdl_result(TablebaseWdl::from_ambiguous(wdl), pos.turn())
+}
+
+struct MatchPlayer {
+    name: String,
+    options: Vec<EngineOption>,
+    process: EngineProcess,
+    reader: EngineReader,
+}
+
+pub(crate) enum SearchOutcome {
+    BestMove { uci: String, score: Option<i32> },
+    Timeout,
+    Disconnected,
+}
+
+/// Reads the engine output until it plays a move, keeping the score of the main line.
+pub(crate) async fn read_best_move(
+    reader: &mut EngineReader,
+    limit: Option<Duration>,
+) -> Result<SearchOutcome, Error> {
+    let start = Instant::now();
+    let mut score = None;
+    loop {
+        let line = match limit {
+            Some(limit) => {
+                match timeout(limit.saturating_sub(start.elapsed()), reader.next_line()).await {
+                    Ok(line) => line?,
+                    Err(_) => return Ok(SearchOutcome::Timeout),
+                }
+            }
+            None => reader.next_line().await?,
+        };
+        let Some(line) = line else {
+            return Ok(SearchOutcome::Disconnected);
+        };
+
+        match parse_one(&line) {
+            UciMessage::Info(attrs) => {
+                let multipv = attrs
+                    .iter()
+                    .find_map(|attr| match attr {
+                        UciInfoAttribute::MultiPv(multipv) => Some(*multipv),
+                        _ => None,
+                    })
+                    .unwrap_or(1);
+                if multipv == 1 {
+                    if let Some(value) = attrs.iter().find_map(|attr| match attr {
+                        UciInfoAttribute::Score(score) => Some(score_to_cp(&score.value)),
+                        _ => None,
+                    }) {
+                        score = Some(value);
+                    }
+                }
+            }
+            UciMessage::BestMove { best_move, .. } => {
+                return Ok(SearchOutcome::BestMove {
+                    uci: best_move.to_string(),
+                    score,
+                })
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Why a match game ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum GameEnd {
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    FiftyMoves,
+    Repetition,
+    TimeForfeit,
+    Disconnected(String),
+    IllegalMove(String),
+    Tablebase,
+    Adjudication,
+    MoveLimit,
+}
+
+impl GameEnd {
+    /// Value of the PGN `Termination` header, the details go in a comment.
+    fn termination(&self) -> &'static str {
+        match self {
+            GameEnd::Checkmate
+            | GameEnd::Stalemate
+            | GameEnd::InsufficientMaterial
+            | GameEnd::FiftyMoves
+            | GameEnd::Repetition => "normal",
+            GameEnd::TimeForfeit => "time forfeit",
+            GameEnd::Disconnected(_) => "abandoned",
+            GameEnd::IllegalMove(_) => "rules infraction",
+            GameEnd::Tablebase | GameEnd::Adjudication | GameEnd::MoveLimit => "adjudication",
+        }
+    }
+}
+
+impl fmt::Display for GameEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameEnd::Checkmate => write!(f, "checkmate"),
+            GameEnd::Stalemate => write!(f, "stalemate"),
+            GameEnd::InsufficientMaterial => write!(f, "insufficient material"),
+            GameEnd::FiftyMoves => write!(f, "fifty moves rule"),
+            GameEnd::Repetition => write!(f, "threefold repetition"),
+            GameEnd::TimeForfeit => write!(f, "time forfeit"),
+            GameEnd::Disconnected(name) => write!(f, "{name} disconnected"),
+            GameEnd::IllegalMove(uci) => write!(f, "illegal move {uci}"),
+            GameEnd::Tablebase => write!(f, "tablebase adjudication"),
+            GameEnd::Adjudication => write!(f, "adjudication"),
+            GameEnd::MoveLimit => write!(f, "move limit"),
+        }
+    }
+}
+
+/// Escapes a PGN header value.
+fn escape_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+struct GameRecord {
+    white: String,
+    black: String,
+    fen: Option<String>,
+    sans: Vec<String>,
+    result: &'static str,
+    end: GameEnd,
+}
+
+impl GameRecord {
+    fn to_pgn(&self, event: &str, round: u32) -> String {
+        let mut pgn = String::new();
+        let date = chrono::Local::now().format("%Y.%m.%d");
+        pgn.push_str(&format!("[Event \"{}\"]\n", escape_header(event)));
+        pgn.push_str("[Site \"?\"]\n");
+        pgn.push_str(&format!("[Date \"{date}\"]\n"));
+        pgn.push_str(&format!("[Round \"{round}\"]\n"));
+        pgn.push_str(&format!("[White \"{}\"]\n", escape_header(&self.white)));
+        pgn.push_str(&format!("[Black \"{}\"]\n", escape_header(&self.black)));
+        pgn.push_str(&format!("[Result \"{}\"]\n", self.result));
+        if let Some(fen) = &self.fen {
+            pgn.push_str("[SetUp \"1\"]\n");
+            pgn.push_str(&format!("[FEN \"{fen}\"]\n"));
+        }
+        pgn.push_str(&format!("[Termination \"{}\"]\n\n", self.end.termination()));
+
+        let (mut move_number, mut turn) = self
+            .fen
+            .as_deref()
+            .and_then(|fen| Fen::from_ascii(fen.as_bytes()).ok())
+            .map(|fen| {
+                let setup = fen.into_setup();
+                (setup.fullmoves.get(), setup.turn)
+            })
+            .unwrap_or((1, Color::White));
+        for (i, san) in self.sans.iter().enumerate() {
+            if turn == Color::White {
+                pgn.push_str(&format!("{move_number}. "));
+            } else if i == 0 {
+                pgn.push_str(&format!("{move_number}... "));
+            }
+            pgn.push_str(san);
+            pgn.push(' ');
+            if turn == Color::Black {
+                move_number += 1;
+            }
+            turn = !turn;
+        }
+        // Comments end at the first closing brace
+        let reason = self.end.to_string().replace('}', ")");
+        pgn.push_str(&format!("{{{reason}}} "));
+        pgn.push_str(self.result);
+        pgn.push_str("\n\n");
+        pgn
+    }
+}
+
+/// Plays a single game between two engines. Returns `None` if the match was
+/// stopped before the game ended.
+#[allow(clippy::too_many_arguments)]
+async fn play_game(
+    players: &mut [MatchPlayer],
+    white: usize,
+    black: usize,
+    opening: &Opening,
+    settings: &MatchSettings,
+    tables: Option<&Tablebase<Chess>>,
+    stop: &AtomicBool,
+    progress: impl Fn(&str, &[String], Option<&str>),
+) -> Result<Option<GameRecord>, Error> {
+    let start_fen = opening.fen.clone().unwrap_or_else(|| START_FEN.to_string());
+    let mut pos: Chess =
+        Fen::from_ascii(start_fen.as_bytes())?.into_position(CastlingMode::Chess960)?;
+
+    let mut repetitions: HashMap<u64, u32> = HashMap::new();
+    let mut record_position = |pos: &Chess| {
+        let count = repetitions
+            .entry(pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0)
+            .or_default();
+        *count += 1;
+        *count
+    };
+    record_position(&pos);
+
+    let mut moves: Vec<String> = Vec::new();
+    let mut sans: Vec<String> = Vec::new();
+    for uci in &opening.moves {
+        let m = UciMove::from_ascii(uci.as_bytes())?.to_move(&pos)?;
+        sans.push(SanPlus::from_move_and_play_unchecked(&mut pos, &m).to_string());
+        moves.push(uci.clone());
+        record_position(&pos);
+    }
+
+    for index in [white, black] {
+        players[index].process.new_game().await?;
+    }
+
+    let mut clock = match &settings.go_mode {
+        GoMode::PlayersTime(time) => Some(time.clone()),
+        _ => None,
+    };
+    let move_time = match settings.go_mode {
+        GoMode::Time(time) => Some(Duration::from_millis(time as u64)),
+        _ => None,
+    };
+    let mut adjudicator = Adjudicator::default();
+
+    let (result, end) = loop {
+        if let Some(outcome) = pos.outcome() {
+            let end = if pos.is_checkmate() {
+                GameEnd::Checkmate
+            } else if pos.is_stalemate() {
+                GameEnd::Stalemate
+            } else {
+                GameEnd::InsufficientMaterial
+            };
+            let result = match outcome {
+                Outcome::Decisive {
+                    winner: Color::White,
+                } => "1-0",
+                Outcome::Decisive {
+                    winner: Color::Black,
+                } => "0-1",
+                Outcome::Draw => "1/2-1/2",
+            };
+            break (result, end);
+        }
+        if pos.halfmoves() >= 100 {
+            break ("1/2-1/2", GameEnd::FiftyMoves);
+        }
+        if stop.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+
+        let turn = pos.turn();
+        let player = &mut players[if turn == Color::White { white } else { black }];
+        player
+            .process
+            .set_options(EngineOptions {
+                fen: start_fen.clone(),
+                moves: moves.clone(),
+                extra_options: player.options.clone(),
+                variant: None,
+                preset: None,
+                search_moves: Vec::new(),
+                exclude_moves: Vec::new(),
+            })
+            .await?;
+
+        let limit = match &clock {
+            Some(time) => {
+                player.process.go_with_clock(time).await?;
+                let remaining = if turn == Color::White {
+                    time.white
+                } else {
+                    time.black
+                };
+                Some(Duration::from_millis(remaining as u64) + TIME_MARGIN)
+            }
+            None => {
+                player.process.go(&settings.go_mode).await?;
+                move_time.map(|time| time * 2 + TIME_MARGIN)
+            }
+        };
+
+        let started = Instant::now();
+        let outcome = read_best_move(&mut player.reader, limit).await?;
+        let elapsed = started.elapsed().as_millis() as u32;
+
+        let loser_result = if turn == Color::White { "0-1" } else { "1-0" };
+        let (uci, score) = match outcome {
+            SearchOutcome::BestMove { uci, score } => (uci, score),
+            SearchOutcome::Timeout => {
+                // Make sure the late move isn't taken as the answer to the next search
+                player.process.stop().await?;
+                let _ = read_best_move(&mut player.reader, Some(STOP_TIMEOUT)).await;
+                break (loser_result, GameEnd::TimeForfeit);
+            }
+            SearchOutcome::Disconnected => {
+                break (loser_result, GameEnd::Disconnected(player.name.clone()));
+            }
+        };
+
+        if let Some(time) = clock.as_mut() {
+            let (remaining, increment) = if turn == Color::White {
+                (&mut time.white, time.winc)
+            } else {
+                (&mut time.black, time.binc)
+            };
+            if elapsed > *remaining + TIME_MARGIN.as_millis() as u32 {
+                break (loser_result, GameEnd::TimeForfeit);
+            }
+            *remaining = remaining.saturating_sub(elapsed) + increment;
+        }
+
+        let Some(m) = UciMove::from_ascii(uci.as_bytes())
+            .ok()
+            .and_then(|uci| uci.to_move(&pos).ok())
+        else {
+            warn!("{} played an illegal move: {}", player.name, uci);
+            break (loser_result, GameEnd::IllegalMove(uci));
+        };
+
+        sans.push(SanPlus::from_move_and_play_unchecked(&mut pos, &m).to_string());
+        moves.push(uci);
+        progress(
+            &Fen::from_position(pos.clone(), EnPassantMode::Legal).to_string(),
+            &moves,
+            None,
+        );
+
+        if record_position(&pos) >= 3 {
+            break ("1/2-1/2", GameEnd::Repetition);
+        }
+        if let Some(result) = tables.and_then(|tables| tablebase_result(tables, &pos)) {
+            break (result, GameEnd::Tablebase);
+        }
+
+        let move_number = pos.fullmoves().get();
+        let white_score = score.map(|score| if turn == Color::White { score } else { -score });
+        if let Some(result) = adjudicator.update(&settings.adjudication, white_score, move_number) {
+            break (result, GameEnd::Adjudication);
+        }
+        if settings
+            .adjudication
+            .max_moves
+            .is_some_and(|max_moves| move_number > max_moves)
+        {
+            break ("1/2-1/2", GameEnd::MoveLimit);
+        }
+    };
+
+    Ok(Some(GameRecord {
+        white: players[white].name.clone(),
+        black: players[black].name.clone(),
+        fen: opening.fen.clone(),
+        sans,
+        result,
+        end,
+    }))
+}
+
+/// Plays a match or tournament between engines and returns the results of the games.
+///
+/// Games are written to the PGN and database outputs as soon as they end, so
+/// nothing is lost if the match is stopped.
+#[tauri::command]
+#[specta::specta]
+pub async fn run_engine_match(
+    id: String,
+    settings: MatchSettings,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<MatchGameResult>, Error> {
+    if settings.engines.len() < 2 {
+        return Err(Error::InvalidMatchSettings(
+            "at least two engines are needed".to_string(),
+        ));
+    }
+    if settings.go_mode == GoMode::Infinite {
+        return Err(Error::InvalidMatchSettings(
+            "engines can't search infinitely in a game".to_string(),
+        ));
+    }
+    if matches!(settings.go_mode, GoMode::Mate(_)) {
+        return Err(Error::InvalidMatchSettings(
+            "engines can't play a game with mate searches".to_string(),
+        ));
+    }
+
+    let openings = match &settings.openings {
+        Some(path) => read_openings(path)?,
+        None => vec![Opening::default()],
+    };
+    if openings.is_empty() {
+        return Err(Error::InvalidMatchSettings(
+            "the opening suite is empty".to_string(),
+        ));
+    }
+
+    let mut players = Vec::new();
+    let mut reservations = Vec::new();
+    for (index, engine) in settings.engines.iter().enumerate() {
+        let mut options = engine.options.clone();
+        let key = (id.clone(), index.to_string());
+        reservations.push(engine_health::reserve(&state, key, &mut options).await);
+        match EngineProcess::new(engine.path.clone()).await {
+            Ok((process, reader)) => players.push(MatchPlayer {
+                name: engine.name.clone(),
+                options,
+                process,
+                reader,
+            }),
+            Err(e) => {
+                quit_players(&mut players).await;
+                return Err(e);
+            }
+        }
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    state.running_matches.insert(id.clone(), stop.clone());
+
+    let tables = if settings.adjudication.tablebase {
+        state.tablebase.read().await.clone()
+    } else {
+        None
+    };
+
+    let result = run_games(
+        &id,
+        &settings,
+        &openings,
+        &mut players,
+        tables.as_deref(),
+        &stop,
+        &app,
+        &state,
+    )
+    .await;
+
+    quit_players(&mut players).await;
+    state.running_matches.remove(&id);
+
+    result
+}
+
+async fn quit_players(players: &mut [MatchPlayer]) {
+    for player in players {
+        if let Err(e) = player.process.kill().await {
+            warn!("Failed to quit {}: {}", player.name, e);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_games(
+    id: &str,
+    settings: &MatchSettings,
+    openings: &[Opening],
+    players: &mut [MatchPlayer],
+    tables: Option<&Tablebase<Chess>>,
+    stop: &AtomicBool,
+    app: &tauri::AppHandle,
+    state: &tauri::State<'_, AppState>,
+) -> Result<Vec<MatchGameResult>, Error> {
+    let event = settings
+        .event
+        .clone()
+        .unwrap_or_else(|| "Engine match".to_string());
+    let pairings = pairings(players.len(), settings.format);
+    let games_per_pairing = settings.games_per_pairing.max(1);
+    let total_games = pairings.len() as u32 * games_per_pairing;
+
+    // Statistics are kept for the first engine, which plays in every pairing of a gauntlet
+    let mut counts = ResultCounts::default();
+    let mut first_games: HashMap<(usize, usize), u8> = HashMap::new();
+
+    let mut results = Vec::new();
+    for round in 0..games_per_pairing {
+        // Both colors play the same opening
+        let opening = &openings[(round / 2) as usize % openings.len()];
+        for &(a, b) in &pairings {
+            let (white, black) = if round % 2 == 0 { (a, b) } else { (b, a) };
+            let game = results.len() as u32 + 1;
+            let (white_name, black_name) =
+                (players[white].name.clone(), players[black].name.clone());
+            info!(
+                "Game {}/{}: {} - {}",
+                game, total_games, white_name, black_name
+            );
+
+            let emit = |fen: &str, moves: &[String], result: Option<&str>| {
+                let _ = MatchProgress {
+                    id: id.to_string(),
+                    game,
+                    total_games,
+                    white: white_name.clone(),
+                    black: black_name.clone(),
+                    fen: fen.to_string(),
+                    moves: moves.to_vec(),
+                    result: result.map(str::to_string),
+                    finished: false,
+                }
+                .emit(app);
+            };
+
+            let Some(record) =
+                play_game(players, white, black, opening, settings, tables, stop, emit).await?
+            else {
+                info!("Match {} stopped", id);
+                return Ok(results);
+            };
+
+            let pgn = record.to_pgn(&event, round + 1);
+            if let Some(path) = &settings.pgn_output {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                file.write_all(pgn.as_bytes())?;
+            }
+            if let Some(path) = &settings.db_output {
+                add_pgn_games(state, path, &event, &pgn)?;
+            }
+
+            results.push(MatchGameResult {
+                round: round + 1,
+                white: record.white,
+                black: record.black,
+                result: record.result.to_string(),
+                termination: record.end.to_string(),
+                plies: record.sans.len() as u32,
+            });
+
+            let _ = MatchProgress {
+                id: id.to_string(),
+                game,
+                total_games,
+                white: white_name,
+                black: black_name,
+                fen: String::new(),
+                moves: Vec::new(),
+                result: Some(record.result.to_string()),
+                finished: game == total_games,
+            }
+            .emit(app);
+
+            if a != 0 {
+                continue;
+            }
+            let half_points = match (record.result, white == 0) {
+                ("1-0", true) | ("0-1", false) => 2,
+                ("1/2-1/2", _) => 1,
+                _ => 0,
+            };
+            counts.add_game(half_points);
+            if round % 2 == 0 {
+                first_games.insert((a, b), half_points);
+            } else if let Some(first) = first_games.remove(&(a, b)) {
+                counts.add_pair(first, half_points);
+            }
+
+            let statistics = MatchStatistics::new(
+                id.to_string(),
+                players[0].name.clone(),
+                &counts,
+                settings.sprt.as_ref(),
+            );
+            let _ = statistics.emit(app);
+            if let Some(result) = statistics.sprt.and_then(|sprt| sprt.result) {
+                info!("Match {} stopped by SPRT: {:?}", id, result);
+                return Ok(results);
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn stop_engine_match(id: String, state: tauri::State<'_, AppState>) {
+    if let Some(stop) = state.running_matches.get(&id) {
+        stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tournament_pairings() {
+        assert_eq!(
+            pairings(3, TournamentFormat::RoundRobin),
+            vec![(0, 1), (0, 2), (1, 2)]
+        );
+        assert_eq!(
+            pairings(3, TournamentFormat::Gauntlet),
+            vec![(0, 1), (0, 2)]
+        );
+    }
+
+    #[test]
+    fn epd_openings() {
+        let openings = parse_epd(
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 id \"e4\";\n\n# comment\n",
+        )
+        .unwrap();
+        assert_eq!(openings.len(), 1);
+        assert_eq!(
+            openings[0].fen.as_deref(),
+            Some("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1")
+        );
+    }
+
+    #[test]
+    fn pgn_openings() {
+        let mut reader = BufferedReader::new_cursor(&b"1. e4 (1. d4) e5 2. Nf3 *\n\n1. O-O *"[..]);
+        let mut visitor = OpeningVisitor::default();
+        let opening = reader.read_game(&mut visitor).unwrap().flatten().unwrap();
+        assert_eq!(opening.moves, vec!["e2e4", "e7e5", "g1f3"]);
+        // Illegal games are skipped
+        assert_eq!(reader.read_game(&mut visitor).unwrap(), Some(None));
+    }
+
+    #[test]
+    fn adjudication() {
+        let rules = Adjudication {
+            win_score: Some(500),
+            win_moves: 2,
+            draw_score: Some(10),
+            draw_moves: 2,
+            draw_move_number: 40,
+            max_moves: None,
+            tablebase: false,
+        };
+
+        let mut adjudicator = Adjudicator::default();
+        for score in [600, 700, 800] {
+            assert_eq!(adjudicator.update(&rules, Some(score), 20), None);
+        }
+        assert_eq!(adjudicator.update(&rules, Some(900), 21), Some("1-0"));
+
+        // Scores need to be consecutive
+        let mut adjudicator = Adjudicator::default();
+        for score in [0, 0, 0] {
+            assert_eq!(adjudicator.update(&rules, Some(score), 45), None);
+        }
+        assert_eq!(adjudicator.update(&rules, None, 46), None);
+        assert_eq!(adjudicator.update(&rules, Some(0), 46), None);
+
+        // Draws are only adjudicated late in the game
+        let mut adjudicator = Adjudicator::default();
+        for _ in 0..10 {
+            assert_eq!(adjudicator.update(&rules, Some(0), 10), None);
+        }
+    }
+
+    #[test]
+    fn tablebase_adjudication() {
+        assert_eq!(wdl_result(TablebaseWdl::Win, Color::Black), Some("0-1"));
+        assert_eq!(wdl_result(TablebaseWdl::Loss, Color::Black), Some("1-0"));
+        assert_eq!(
+            wdl_result(TablebaseWdl::CursedWin, Color::White),
+            Some("1/2-1/2")
+        );
+        assert_eq!(wdl_result(TablebaseWdl::MaybeWin, Color::White), None);
+
+        // Positions outside of the tables keep being played
+        let tables = Tablebase::<Chess>::new();
+        let pos: Chess = Fen::from_ascii(b"8/8/8/8/8/2k5/8/K1R5 w - - 0 1")
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        assert_eq!(tablebase_result(&tables, &pos), None);
+    }
+
+    #[test]
+    fn pgn_record() {
+        let record = GameRecord {
+            white: "A \"Bot\"".to_string(),
+            black: "B\\C".to_string(),
+            fen: Some("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1".to_string()),
+            sans: vec!["e5".to_string(), "Nf3".to_string()],
+            result: "1-0",
+            end: GameEnd::Disconnected("B}".to_string()),
+        };
+        let pgn = record.to_pgn("Test", 1);
+        assert!(
+            pgn.contains("[FEN \"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1\"]")
+        );
+        assert!(pgn.contains("[White \"A \\\"Bot\\\"\"]\n[Black \"B\\\\C\"]"));
+        assert!(pgn.contains("[Termination \"abandoned\"]"));
+        assert!(pgn.ends_with("1... e5 2. Nf3 {B) disconnected} 1-0\n\n"));
+    }
+}
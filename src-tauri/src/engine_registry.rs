@@ -0,0 +1,383 @@
+use std::path::{Path, PathBuf};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{path::BaseDirectory, Manager};
+use vampirc_uci::UciOptionConfig;
+
+use crate::{
+    chess::{read_engine_config, EngineOption, EngineOptions},
+    error::{Error, Result},
+    AppState,
+};
+
+/// Kept next to `engines.json`, which belongs to the frontend.
+const REGISTRY_FILE: &str = "engines/registry.json";
+
+/// A named set of option values, such as "Fast" or "Limited 1800".
+#[derive(Serialize, Deserialize, Debug, Clone, Type, PartialEq, Eq)]
+pub struct EnginePreset {
+    pub name: String,
+    pub options: Vec<EngineOption>,
+}
+
+/// An engine known to the backend, with the options it reported when it was added.
+#[derive(Serialize, Deserialize, Debug, Clone, Type)]
+pub struct RegisteredEngine {
+    /// Path or address the engine is started with
+    pub path: String,
+    pub name: String,
+    pub options: Vec<UciOptionConfig>,
+    #[serde(default)]
+    pub presets: Vec<EnginePreset>,
+}
+
+impl RegisteredEngine {
+    pub fn preset(&self, name: &str) -> Result<&EnginePreset> {
+        self.presets
+            .iter()
+            .find(|preset| preset.name == name)
+            .ok_or_else(|| Error::EnginePresetNotFound(name.to_string()))
+    }
+
+    /// Checks a value against the type and bounds the engine reported for the option.
+    pub fn validate(&self, option: &EngineOption) -> Result<()> {
+        let config = self
+            .options
+            .iter()
+            .find(|config| option_name(config).eq_ignore_ascii_case(&option.name))
+            .ok_or_else(|| {
+                Error::InvalidEngineOption(format!("{} doesn't have {}", self.name, option.name))
+            })?;
+        let invalid = |reason: String| {
+            Err(Error::InvalidEngineOption(format!(
+                "{} = {}: {}",
+                option.name, option.value, reason
+            )))
+        };
+
+        match config {
+            UciOptionConfig::Check { .. } => {
+                if option.value != "true" && option.value != "false" {
+                    return invalid("expected true or false".to_string());
+                }
+            }
+            UciOptionConfig::Spin { min, max, .. } => {
+                let Ok(value) = option.value.parse::<i64>() else {
+                    return invalid("expected an integer".to_string());
+                };
+                if min.is_some_and(|min| value < min) || max.is_some_and(|max| value > max) {
+                    return invalid(format!(
+                        "out of range {}..={}",
+                        min.map_or(String::new(), |min| min.to_string()),
+                        max.map_or(String::new(), |max| max.to_string())
+                    ));
+                }
+            }
+            UciOptionConfig::Combo { var, .. } => {
+                if !var.iter().any(|v| v.eq_ignore_ascii_case(&option.value)) {
+                    return invalid(format!("expected one of {}", var.join(", ")));
+                }
+            }
+            UciOptionConfig::Button { .. } => {
+                return invalid("buttons can't be set to a value".to_string());
+            }
+            UciOptionConfig::String { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+fn option_name(config: &UciOptionConfig) -> &str {
+    match config {
+        UciOptionConfig::Check { name, .. }
+        | UciOptionConfig::Spin { name, .. }
+        | UciOptionConfig::Combo { name, .. }
+        | UciOptionConfig::Button { name }
+        | UciOptionConfig::String { name, .. } => name,
+    }
+}
+
+/// Sets the options of a preset, replacing the values already given for them.
+fn apply_preset(options: &mut Vec<EngineOption>, preset: &EnginePreset) {
+    for option in &preset.options {
+        match options
+            .iter_mut()
+            .find(|current| current.name.eq_ignore_ascii_case(&option.name))
+        {
+            Some(current) => current.value = option.value.clone(),
+            None => options.push(option.clone()),
+        }
+    }
+}
+
+/// Engines and their presets, stored in the app data directory.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct EngineRegistry {
+    engines: Vec<RegisteredEngine>,
+}
+
+impl EngineRegistry {
+    fn file(app: &tauri::AppHandle) -> Result<PathBuf> {
+        Ok(app.path().resolve(REGISTRY_FILE, BaseDirectory::AppData)?)
+    }
+
+    fn load(file: &Path) -> Result<Self> {
+        if !file.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(file)?)?)
+    }
+
+    /// Writes to a temporary file first, so a crash never leaves a truncated registry.
+    fn save(&self, file: &Path) -> Result<()> {
+        let tmp = file.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, file)?;
+        Ok(())
+    }
+
+    pub fn get(&self, path: &str) -> Result<&RegisteredEngine> {
+        self.engines
+            .iter()
+            .find(|engine| engine.path == path)
+            .ok_or_else(|| Error::EngineNotRegistered(path.to_string()))
+    }
+
+    fn get_mut(&mut self, path: &str) -> Result<&mut RegisteredEngine> {
+        self.engines
+            .iter_mut()
+            .find(|engine| engine.path == path)
+            .ok_or_else(|| Error::EngineNotRegistered(path.to_string()))
+    }
+}
+
+/// Runs `f` on the registry, loading it on first use. Changes are saved when
+/// `f` succeeds and `save` is set.
+async fn with_registry<T>(
+    app: &tauri::AppHandle,
+    save: bool,
+    f: impl FnOnce(&mut EngineRegistry) -> Result<T>,
+) -> Result<T> {
+    let state = app.state::<AppState>();
+    let mut guard = state.engine_registry.lock().await;
+    let file = EngineRegistry::file(app)?;
+    let registry = match guard.take() {
+        Some(registry) => guard.insert(registry),
+        None => guard.insert(EngineRegistry::load(&file)?),
+    };
+
+    let result = f(registry)?;
+    if save {
+        registry.save(&file)?;
+    }
+    Ok(result)
+}
+
+/// Merges the preset named in the options into their extra options.
+pub async fn resolve_preset(
+    app: &tauri::AppHandle,
+    engine: &str,
+    mut options: EngineOptions,
+) -> Result<EngineOptions> {
+    let Some(name) = &options.preset else {
+        return Ok(options);
+    };
+    let preset = with_registry(app, false, |registry| {
+        Ok(registry.get(engine)?.preset(name)?.clone())
+    })
+    .await?;
+    apply_preset(&mut options.extra_options, &preset);
+    Ok(options)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn list_registered_engines(app: tauri::AppHandle) -> Result<Vec<RegisteredEngine>> {
+    with_registry(&app, false, |registry| Ok(registry.engines.clone())).await
+}
+
+/// Adds an engine after checking it speaks UCI. Registering it again refreshes
+/// its options and keeps its presets.
+#[tauri::command]
+#[specta::specta]
+pub async fn register_engine(path: String, app: tauri::AppHandle) -> Result<RegisteredEngine> {
+    let config = read_engine_config(Path::new(&path)).await?;
+    info!(
+        "Registering engine {} ({} options): {}",
+        config.name,
+        config.options.len(),
+        path
+    );
+
+    with_registry(&app, true, |registry| {
+        match registry
+            .engines
+            .iter_mut()
+            .find(|engine| engine.path == path)
+        {
+            Some(engine) => {
+                engine.name = config.name;
+                engine.options = config.options;
+                Ok(engine.clone())
+            }
+            None => {
+                let engine = RegisteredEngine {
+                    path,
+                    name: config.name,
+                    options: config.options,
+                    presets: Vec::new(),
+                };
+                registry.engines.push(engine.clone());
+                Ok(engine)
+            }
+        }
+    })
+    .await
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn unregister_engine(path: String, app: tauri::AppHandle) -> Result<()> {
+    with_registry(&app, true, |registry| {
+        registry.get(&path)?;
+        registry.engines.retain(|engine| engine.path != path);
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn validate_engine_options(
+    path: String,
+    options: Vec<EngineOption>,
+    app: tauri::AppHandle,
+) -> Result<()> {
+    with_registry(&app, false, |registry| {
+        let engine = registry.get(&path)?;
+        options
+            .iter()
+            .try_for_each(|option| engine.validate(option))
+    })
+    .await
+}
+
+/// Saves a preset after validating its values, replacing the one with the same name.
+#[tauri::command]
+#[specta::specta]
+pub async fn save_engine_preset(
+    path: String,
+    preset: EnginePreset,
+    app: tauri::AppHandle,
+) -> Result<()> {
+    with_registry(&app, true, |registry| {
+        let engine = registry.get_mut(&path)?;
+        for option in &preset.options {
+            engine.validate(option)?;
+        }
+        match engine.presets.iter_mut().find(|p| p.name == preset.name) {
+            Some(current) => *current = preset,
+            None => engine.presets.push(preset),
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn delete_engine_preset(path: String, name: String, app: tauri::AppHandle) -> Result<()> {
+    with_registry(&app, true, |registry| {
+        let engine = registry.get_mut(&path)?;
+        engine.preset(&name)?;
+        engine.presets.retain(|preset| preset.name != name);
+        Ok(())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(name: &str, value: &str) -> EngineOption {
+        EngineOption {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn engine() -> RegisteredEngine {
+        RegisteredEngine {
+            path: "stockfish".to_string(),
+            name: "Stockfish".to_string(),
+            options: vec![
+                UciOptionConfig::Spin {
+                    name: "Threads".to_string(),
+                    default: Some(1),
+                    min: Some(1),
+                    max: Some(1024),
+                },
+                UciOptionConfig::Check {
+                    name: "UCI_LimitStrength".to_string(),
+                    default: Some(false),
+                },
+                UciOptionConfig::Combo {
+                    name: "UCI_Variant".to_string(),
+                    default: Some("chess".to_string()),
+                    var: vec!["chess".to_string(), "atomic".to_string()],
+                },
+                UciOptionConfig::Button {
+                    name: "Clear Hash".to_string(),
+                },
+                UciOptionConfig::String {
+                    name: "SyzygyPath".to_string(),
+                    default: None,
+                },
+            ],
+            presets: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn validate_options() {
+        let engine = engine();
+        assert!(engine.validate(&option("Threads", "8")).is_ok());
+        assert!(engine.validate(&option("threads", "8")).is_ok());
+        assert!(engine.validate(&option("Threads", "0")).is_err());
+        assert!(engine.validate(&option("Threads", "many")).is_err());
+        assert!(engine
+            .validate(&option("UCI_LimitStrength", "true"))
+            .is_ok());
+        assert!(engine.validate(&option("UCI_LimitStrength", "1")).is_err());
+        assert!(engine.validate(&option("UCI_Variant", "Atomic")).is_ok());
+        assert!(engine.validate(&option("UCI_Variant", "horde")).is_err());
+        assert!(engine.validate(&option("Clear Hash", "")).is_err());
+        assert!(engine.validate(&option("SyzygyPath", "/tb")).is_ok());
+        assert!(engine.validate(&option("Contempt", "0")).is_err());
+    }
+
+    #[test]
+    fn presets_override_options() {
+        let preset = EnginePreset {
+            name: "Limited 1800".to_string(),
+            options: vec![
+                option("UCI_LimitStrength", "true"),
+                option("UCI_Elo", "1800"),
+            ],
+        };
+        let mut options = vec![option("Threads", "4"), option("uci_limitstrength", "false")];
+        apply_preset(&mut options, &preset);
+        assert_eq!(
+            options,
+            vec![
+                option("Threads", "4"),
+                option("uci_limitstrength", "true"),
+                option("UCI_Elo", "1800"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use log::{debug, error};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader, Lines},
+    net::TcpStream,
+    process::{Child, Command},
+};
+
+use crate::{error::Error, xboard};
+
+#[cfg(target_os = "windows")]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+/// Commands sent to an engine, whatever the transport.
+pub type EngineWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Lines written by an engine, whatever the transport.
+pub type EngineReader = Lines<BufReader<Box<dyn AsyncRead + Send + Unpin>>>;
+
+/// How to reach a UCI engine.
+///
+/// Engines are identified by their path everywhere in the app, remote engines
+/// use a URL instead: `tcp://host:port` or `ssh://[user@]host[:port]/command`.
+/// Engines speaking CECP instead of UCI are prefixed with `xboard:`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineTransport {
+    Local(PathBuf),
+    Tcp {
+        host: String,
+        port: u16,
+    },
+    Ssh {
+        destination: String,
+        port: Option<u16>,
+        command: String,
+    },
+    /// A CECP engine, reached through another transport
+    Xboard(Box<EngineTransport>),
+}
+
+/// An open connection to an engine.
+pub struct EngineIo {
+    pub writer: EngineWriter,
+    pub reader: EngineReader,
+    /// The local process, if any: the engine itself or the `ssh` client
+    pub child: Option<Child>,
+}
+
+impl EngineTransport {
+    pub fn parse(path: &Path) -> Result<Self, Error> {
+        let Some(address) = path.to_str() else {
+            return Ok(EngineTransport::Local(path.to_path_buf()));
+        };
+
+        if let Some(address) = address.strip_prefix("xboard:") {
+            let inner = Self::parse(Path::new(address))?;
+            return Ok(EngineTransport::Xboard(Box::new(inner)));
+        }
+
+        if let Some(address) = address.strip_prefix("tcp://") {
+            let (host, port) =
+                split_port(address.trim_end_matches('/')).map_err(|_| invalid(path))?;
+            let port = port.ok_or_else(|| invalid(path))?;
+            if host.is_empty() {
+                return Err(invalid(path));
+            }
+            return Ok(EngineTransport::Tcp { host, port });
+        }
+
+        if let Some(address) = address.strip_prefix("ssh://") {
+            let slash = address.find('/').ok_or_else(|| invalid(path))?;
+            let (authority, command) = address.split_at(slash);
+            // `ssh://host/~/engine` runs an engine relative to the home directory
+            let command = command
+                .strip_prefix("/~")
+                .map_or(command.to_string(), |c| format!("~{c}"));
+            let (destination, port) = split_port(authority).map_err(|_| invalid(path))?;
+            if destination.is_empty() || command.len() <= 1 {
+                return Err(invalid(path));
+            }
+            return Ok(EngineTransport::Ssh {
+                destination,
+                port,
+                command,
+            });
+        }
+
+        Ok(EngineTransport::Local(path.to_path_buf()))
+    }
+
+    /// Whether the engine speaks CECP behind the UCI adapter.
+    pub fn is_xboard(&self) -> bool {
+        matches!(self, EngineTransport::Xboard(_))
+    }
+
+    /// Whether the connection can drop while the engine keeps running.
+    pub fn is_remote(&self) -> bool {
+        match self {
+            EngineTransport::Local(_) => false,
+            EngineTransport::Xboard(inner) => inner.is_remote(),
+            _ => true,
+        }
+    }
+
+    pub async fn open(&self) -> Result<EngineIo, Error> {
+        match self {
+            EngineTransport::Xboard(inner) => {
+                let io = Box::pin(inner.open()).await?;
+                Ok(xboard::adapt(io))
+            }
+            EngineTransport::Local(path) => {
+                let mut command = Command::new(path);
+                command.current_dir(path.parent().unwrap_or_else(|| Path::new(".")));
+                spawn(command, self)
+            }
+            EngineTransport::Ssh {
+                destination,
+                port,
+                command: remote,
+            } => {
+                let mut command = Command::new("ssh");
+                // No terminal and no password prompt, and notice dead connections
+                command.args(["-T", "-o", "BatchMode=yes", "-o", "ServerAliveInterval=15"]);
+                if let Some(port) = port {
+                    command.arg("-p").arg(port.to_string());
+                }
+                command.arg(destination).arg(remote);
+                spawn(command, self)
+            }
+            EngineTransport::Tcp { host, port } => {
+                debug!("Connecting to engine at {}", self);
+                let stream = TcpStream::connect((host.as_str(), *port))
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to connect to engine {}: {}", self, e);
+                        Error::Io(e)
+                    })?;
+                // Commands are small and must reach the engine right away
+                stream.set_nodelay(true)?;
+                let (read, write) = stream.into_split();
+                Ok(EngineIo {
+                    writer: Box::new(write),
+                    reader: lines(Box::new(read)),
+                    child: None,
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for EngineTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineTransport::Local(path) => write!(f, "{}", path.display()),
+            EngineTransport::Tcp { host, port } => write!(f, "tcp://{host}:{port}"),
+            EngineTransport::Ssh {
+                destination,
+                port: Some(port),
+                command,
+            } => write!(f, "ssh://{destination}:{port}{command}"),
+            EngineTransport::Ssh {
+                destination,
+                port: None,
+                command,
+            } => write!(f, "ssh://{destination}{command}"),
+            EngineTransport::Xboard(inner) => write!(f, "xboard:{inner}"),
+        }
+    }
+}
+
+fn invalid(path: &Path) -> Error {
+    Error::InvalidEngineAddress(path.display().to_string())
+}
+
+/// Splits `host:port`, with IPv6 hosts in brackets.
+fn split_port(authority: &str) -> Result<(String, Option<u16>), std::num::ParseIntError> {
+    if let Some(rest) = authority.strip_prefix('[') {
+        if let Some((host, after)) = rest.split_once(']') {
+            let port = match after.strip_prefix(':') {
+                Some(port) => Some(port.parse()?),
+                None => None,
+            };
+            return Ok((host.to_string(), port));
+        }
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) => Ok((host.to_string(), Some(port.parse()?))),
+        None => Ok((authority.to_string(), None)),
+    }
+}
+
+pub(crate) fn lines(read: Box<dyn AsyncRead + Send + Unpin>) -> EngineReader {
+    // Use a small buffer for more responsive reading
+    BufReader::with_capacity(1024, read).lines()
+}
+
+fn spawn(mut command: Command, transport: &EngineTransport) -> Result<EngineIo, Error> {
+    debug!("Spawning engine process: {}", transport);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .env("TERM", "dumb"); // Prevent terminal feature usage
+
+    #[cfg(target_os = "windows")]
+    command.creation_flags(CREATE_NO_WINDOW);
+
+    let mut child = command.spawn().map_err(|e| {
+        error!("Failed to spawn engine process {}: {}", transport, e);
+        Error::Io(e)
+    })?;
+
+    let stdin = child.stdin.take().ok_or_else(|| {
+        error!("Failed to get stdin handle from engine process");
+        Error::NoStdin
+    })?;
+    let stdout = child.stdout.take().ok_or_else(|| {
+        error!("Failed to get stdout handle from engine process");
+        Error::NoStdout
+    })?;
+
+    debug!("Engine process spawned successfully");
+    Ok(EngineIo {
+        writer: Box::new(stdin),
+        reader: lines(Box::new(stdout)),
+        child: Some(child),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::chess::{restart_stopped, EngineOptions, EngineProcess, GoMode, MAX_RECONNECTS};
+
+    #[test]
+    fn parse_addresses() {
+        let parse = |s: &str| EngineTransport::parse(Path::new(s));
+
+        assert_eq!(
+            parse("/usr/bin/stockfish").unwrap(),
+            EngineTransport::Local(PathBuf::from("/usr/bin/stockfish"))
+        );
+        assert_eq!(
+            parse("tcp://192.168.1.10:9000").unwrap(),
+            EngineTransport::Tcp {
+                host: "192.168.1.10".to_string(),
+                port: 9000
+            }
+        );
+        assert_eq!(
+            parse("tcp://[::1]:9000").unwrap(),
+            EngineTransport::Tcp {
+                host: "::1".to_string(),
+                port: 9000
+            }
+        );
+        assert_eq!(
+            parse("ssh://me@server:2222/opt/engines/stockfish -t 64").unwrap(),
+            EngineTransport::Ssh {
+                destination: "me@server".to_string(),
+                port: Some(2222),
+                command: "/opt/engines/stockfish -t 64".to_string()
+            }
+        );
+        assert_eq!(
+            parse("ssh://server/~/stockfish").unwrap(),
+            EngineTransport::Ssh {
+                destination: "server".to_string(),
+                port: None,
+                command: "~/stockfish".to_string()
+            }
+        );
+        assert_eq!(
+            parse("xboard:tcp://server:9000").unwrap(),
+            EngineTransport::Xboard(Box::new(EngineTransport::Tcp {
+                host: "server".to_string(),
+                port: 9000
+            }))
+        );
+        assert!(parse("tcp://server").is_err());
+        assert!(parse("ssh://server").is_err());
+    }
+
+    /// Answers like a UCI engine on one connection and returns the commands it received.
+    ///
+    /// The connection is dropped on `go` unless `finish` is set, in which case the
+    /// search ends with a best move.
+    async fn fake_engine(stream: TcpStream, finish: bool) -> Vec<String> {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut received = Vec::new();
+        while let Ok(Some(line)) = lines.next_line().await {
+            received.push(line.clone());
+            let answer = match line.as_str() {
+                "uci" => "id name Fake\nuciok\n",
+                "isready" => "readyok\n",
+                go if go.starts_with("go") && finish => "bestmove e2e4\n",
+                go if go.starts_with("go") => break,
+                _ => continue,
+            };
+            if write.write_all(answer.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn reconnect_resumes_search() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (first, _) = listener.accept().await.unwrap();
+            let first = fake_engine(first, false).await;
+            let (second, _) = listener.accept().await.unwrap();
+            (first, fake_engine(second, true).await)
+        });
+
+        let path = PathBuf::from(format!("tcp://127.0.0.1:{port}"));
+        let (mut process, mut reader) = EngineProcess::new(path).await.unwrap();
+        process
+            .set_options(EngineOptions {
+                fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string(),
+                moves: vec!["e2e4".to_string()],
+                extra_options: Vec::new(),
+                variant: None,
+                preset: None,
+                search_moves: Vec::new(),
+                exclude_moves: Vec::new(),
+            })
+            .await
+            .unwrap();
+        process.go(&GoMode::Depth(5)).await.unwrap();
+        assert!(!matches!(reader.next_line().await, Ok(Some(_))));
+
+        let mut reader = process.reconnect().await.unwrap();
+        assert_eq!(
+            reader.next_line().await.unwrap().as_deref(),
+            Some("bestmove e2e4")
+        );
+        process.kill().await.unwrap();
+        drop(process);
+
+        let (first, second) = server.await.unwrap();
+        assert_eq!(first.last().map(String::as_str), Some("go depth 5"));
+        assert_eq!(&second[..2], ["uci", "isready"]);
+        assert!(second
+            .iter()
+            .any(|c| c.starts_with("position fen") && c.ends_with("moves e2e4")));
+        assert!(second.contains(&"go depth 5".to_string()));
+    }
+
+    #[tokio::test]
+    async fn restart_crashed_engine() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                fake_engine(stream, false).await;
+            }
+        });
+
+        let path = PathBuf::from(format!("tcp://127.0.0.1:{port}"));
+        let (mut process, mut reader) = EngineProcess::new(path).await.unwrap();
+        process
+            .set_options(EngineOptions {
+                fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string(),
+                moves: Vec::new(),
+                extra_options: Vec::new(),
+                variant: None,
+                preset: None,
+                search_moves: Vec::new(),
+                exclude_moves: Vec::new(),
+            })
+            .await
+            .unwrap();
+        process.go(&GoMode::Depth(5)).await.unwrap();
+        assert!(!matches!(reader.next_line().await, Ok(Some(_))));
+
+        // The last restart allowed
+        let mut reconnects = MAX_RECONNECTS - 1;
+        let (exit_code, reader) = restart_stopped(&mut process, &mut reconnects)
+            .await
+            .unwrap();
+        assert_eq!(exit_code, None);
+        assert_eq!(reconnects, MAX_RECONNECTS);
+        let mut reader = reader.unwrap();
+        assert!(!matches!(reader.next_line().await, Ok(Some(_))));
+
+        let (_, reader) = restart_stopped(&mut process, &mut reconnects)
+            .await
+            .unwrap();
+        assert!(reader.is_none());
+        assert_eq!(reconnects, MAX_RECONNECTS);
+
+        // The connection is already closed, so sending quit may fail
+        let _ = process.kill().await;
+        assert!(restart_stopped(&mut process, &mut reconnects)
+            .await
+            .is_none());
+        server.await.unwrap();
+    }
+}
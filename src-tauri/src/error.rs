@@ -36,6 +36,9 @@ pub enum Error {
     #[error(transparent)]
     ChessPosition(#[from] shakmaty::PositionError<Chess>),
 
+    #[error(transparent)]
+    VariantPosition(#[from] shakmaty::PositionError<shakmaty::variant::VariantPosition>),
+
     #[error(transparent)]
     IllegalUciMove(#[from] shakmaty::uci::IllegalUciMoveError),
 
@@ -69,6 +72,9 @@ pub enum Error {
     #[error(transparent)]
     FormatError(#[from] std::fmt::Error),
 
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
     #[error("No stdin")]
     NoStdin,
 
@@ -107,6 +113,48 @@ pub enum Error {
 
     #[error("Engine timeout")]
     EngineTimeout,
+
+    #[error("Invalid match settings: {0}")]
+    InvalidMatchSettings(String),
+
+    #[error("Game session not found: {0}")]
+    GameSessionNotFound(String),
+
+    #[error("Invalid game session move: {0}")]
+    InvalidSessionMove(String),
+
+    #[error("No tablebase loaded")]
+    NoTablebase,
+
+    #[error("Too many pieces for the loaded tablebases (at most {0})")]
+    TooManyPiecesForTablebase(usize),
+
+    #[error("Invalid engine address: {0}")]
+    InvalidEngineAddress(String),
+
+    #[error("Unsupported variant: {0}")]
+    UnsupportedVariant(String),
+
+    #[error("Not a UCI engine: {0}")]
+    InvalidEngine(String),
+
+    #[error("Engine not registered: {0}")]
+    EngineNotRegistered(String),
+
+    #[error("Engine preset not found: {0}")]
+    EnginePresetNotFound(String),
+
+    #[error("Invalid engine option: {0}")]
+    InvalidEngineOption(String),
+
+    #[error("Invalid book name: {0}")]
+    InvalidBookName(String),
+
+    #[error("Unsupported book format: {0}, only Polyglot books can be read")]
+    UnsupportedBookFormat(String),
+
+    #[error("XBoard engines can't restrict the moves they search")]
+    SearchMovesUnsupported,
 }
 
 impl serde::Serialize for Error {
@@ -0,0 +1,591 @@
+use std::{path::PathBuf, sync::Arc, time::Instant};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen::Fen,
+    san::SanPlus,
+    uci::UciMove,
+    zobrist::{Zobrist64, ZobristHash},
+    CastlingMode, Chess, Color, EnPassantMode, Outcome, Position,
+};
+use specta::Type;
+use tauri_specta::Event;
+use tokio::sync::Mutex;
+
+use crate::{
+    chess::{EngineOption, EngineOptions, EngineProcess, PlayersTime},
+    engine_health::{self, EngineReservation},
+    engine_match::{read_best_move, SearchOutcome, STOP_TIMEOUT, TIME_MARGIN},
+    engine_transport::EngineReader,
+    error::Error,
+    AppState,
+};
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Type, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    White,
+    Black,
+}
+
+impl From<Side> for Color {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::White => Color::White,
+            Side::Black => Color::Black,
+        }
+    }
+}
+
+impl From<Color> for Side {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::White => Side::White,
+            Color::Black => Side::Black,
+        }
+    }
+}
+
+/// Weakens the engine through its standard UCI options.
+#[derive(Deserialize, Debug, Clone, Default, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineStrength {
+    /// Sets `UCI_LimitStrength` and `UCI_Elo`
+    pub elo: Option<u32>,
+    /// Sets `Skill Level`, as supported by Stockfish and others
+    pub skill_level: Option<u32>,
+}
+
+impl EngineStrength {
+    fn options(&self) -> Vec<EngineOption> {
+        let mut options = Vec::new();
+        if let Some(elo) = self.elo {
+            options.push(EngineOption {
+                name: "UCI_LimitStrength".to_string(),
+                value: "true".to_string(),
+            });
+            options.push(EngineOption {
+                name: "UCI_Elo".to_string(),
+                value: elo.to_string(),
+            });
+        }
+        if let Some(skill_level) = self.skill_level {
+            options.push(EngineOption {
+                name: "Skill Level".to_string(),
+                value: skill_level.to_string(),
+            });
+        }
+        options
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSessionSettings {
+    pub engine: PathBuf,
+    pub engine_options: Vec<EngineOption>,
+    #[serde(default)]
+    pub strength: EngineStrength,
+    pub engine_side: Side,
+    pub fen: Option<String>,
+    /// Starting times and increments, in milliseconds
+    pub time: PlayersTime,
+}
+
+#[derive(Serialize, Debug, Clone, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSessionState {
+    pub id: String,
+    pub fen: String,
+    pub moves: Vec<String>,
+    pub sans: Vec<String>,
+    pub turn: Side,
+    /// Remaining times in milliseconds, when the state was sent
+    pub white_time: u32,
+    pub black_time: u32,
+    pub engine_thinking: bool,
+    pub result: Option<String>,
+    pub termination: Option<String>,
+}
+
+/// The game itself: moves, clocks and result.
+///
+/// Times are passed in by the session, so this doesn't depend on the system clock.
+#[derive(Debug, Clone)]
+struct GameState {
+    start_fen: String,
+    start: Chess,
+    pos: Chess,
+    moves: Vec<String>,
+    sans: Vec<String>,
+    /// Hashes of every position of the game, to detect repetitions
+    hashes: Vec<u64>,
+    clock: PlayersTime,
+    result: Option<(&'static str, String)>,
+}
+
+fn position_hash(pos: &Chess) -> u64 {
+    pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0
+}
+
+fn loss(color: Color) -> &'static str {
+    match color {
+        Color::White => "0-1",
+        Color::Black => "1-0",
+    }
+}
+
+impl GameState {
+    fn new(fen: Option<&str>, clock: PlayersTime) -> Result<Self, Error> {
+        let start_fen = fen.unwrap_or(START_FEN).to_string();
+        let start: Chess =
+            Fen::from_ascii(start_fen.as_bytes())?.into_position(CastlingMode::Chess960)?;
+        let mut state = GameState {
+            start_fen,
+            pos: start.clone(),
+            hashes: vec![position_hash(&start)],
+            start,
+            moves: Vec::new(),
+            sans: Vec::new(),
+            clock,
+            result: None,
+        };
+        state.check_end();
+        Ok(state)
+    }
+
+    fn turn(&self) -> Color {
+        self.pos.turn()
+    }
+
+    fn remaining(&self, color: Color) -> u32 {
+        match color {
+            Color::White => self.clock.white,
+            Color::Black => self.clock.black,
+        }
+    }
+
+    /// Ends the game if the side to move has used more than its remaining time.
+    fn check_flag(&mut self, elapsed: u32) -> bool {
+        if self.result.is_none() && elapsed > self.remaining(self.turn()) {
+            self.result = Some((loss(self.turn()), "time forfeit".to_string()));
+        }
+        self.result.is_some()
+    }
+
+    /// Plays a move of the side to move, which thought for `elapsed` milliseconds.
+    fn play(&mut self, uci: &str, elapsed: u32) -> Result<(), Error> {
+        if self.result.is_some() {
+            return Err(Error::InvalidSessionMove("the game is over".to_string()));
+        }
+        let m = UciMove::from_ascii(uci.as_bytes())?.to_move(&self.pos)?;
+        if self.check_flag(elapsed) {
+            return Ok(());
+        }
+
+        let turn = self.turn();
+        let (remaining, increment) = match turn {
+            Color::White => (&mut self.clock.white, self.clock.winc),
+            Color::Black => (&mut self.clock.black, self.clock.binc),
+        };
+        *remaining = *remaining - elapsed + increment;
+
+        self.sans
+            .push(SanPlus::from_move_and_play_unchecked(&mut self.pos, &m).to_string());
+        self.moves
+            .push(m.to_uci(CastlingMode::Standard).to_string());
+        self.hashes.push(position_hash(&self.pos));
+        self.check_end();
+        Ok(())
+    }
+
+    fn check_end(&mut self) {
+        if let Some(outcome) = self.pos.outcome() {
+            let result = match outcome {
+                Outcome::Decisive { winner } => loss(!winner),
+                Outcome::Draw => "1/2-1/2",
+            };
+            let termination = if self.pos.is_checkmate() {
+                "checkmate"
+            } else if self.pos.is_stalemate() {
+                "stalemate"
+            } else {
+                "insufficient material"
+            };
+            self.result = Some((result, termination.to_string()));
+        } else if self.pos.halfmoves() >= 100 {
+            self.result = Some(("1/2-1/2", "fifty moves rule".to_string()));
+        } else {
+            let current = self.hashes.last().copied();
+            if self.hashes.iter().filter(|h| Some(**h) == current).count() >= 3 {
+                self.result = Some(("1/2-1/2", "threefold repetition".to_string()));
+            }
+        }
+    }
+
+    /// Takes back the last `plies` moves, the clocks are left untouched.
+    fn takeback(&mut self, plies: usize) -> Result<(), Error> {
+        let len = self.moves.len().saturating_sub(plies);
+        self.moves.truncate(len);
+        self.sans.truncate(len);
+        self.hashes.truncate(len + 1);
+        self.result = None;
+
+        self.pos = self.start.clone();
+        for uci in &self.moves {
+            let m = UciMove::from_ascii(uci.as_bytes())?.to_move(&self.pos)?;
+            self.pos.play_unchecked(&m);
+        }
+        Ok(())
+    }
+}
+
+pub struct GameSession {
+    engine: EngineProcess,
+    engine_color: Color,
+    engine_options: Vec<EngineOption>,
+    /// Counts the engine in the limits of the others while the session is open
+    _reservation: EngineReservation,
+    game: GameState,
+    /// When the side to move started thinking
+    turn_start: Instant,
+    engine_thinking: bool,
+    /// Incremented on takebacks, so searches started before them are ignored
+    generation: u32,
+}
+
+impl GameSession {
+    fn elapsed(&self) -> u32 {
+        self.turn_start.elapsed().as_millis() as u32
+    }
+
+    fn state(&self, id: &str) -> GameSessionState {
+        let elapsed = if self.game.result.is_none() {
+            self.elapsed()
+        } else {
+            0
+        };
+        let remaining = |color: Color| {
+            let time = self.game.remaining(color);
+            if color == self.game.turn() {
+                time.saturating_sub(elapsed)
+            } else {
+                time
+            }
+        };
+        GameSessionState {
+            id: id.to_string(),
+            fen: Fen::from_position(self.game.pos.clone(), EnPassantMode::Legal).to_string(),
+            moves: self.game.moves.clone(),
+            sans: self.game.sans.clone(),
+            turn: self.game.turn().into(),
+            white_time: remaining(Color::White),
+            black_time: remaining(Color::Black),
+            engine_thinking: self.engine_thinking,
+            result: self.game.result.as_ref().map(|(r, _)| r.to_string()),
+            termination: self.game.result.as_ref().map(|(_, t)| t.clone()),
+        }
+    }
+}
+
+/// A game session and the output of its engine, which are locked separately
+/// so the session can be used while the engine is thinking.
+pub struct GameSessionHandle {
+    session: Mutex<GameSession>,
+    reader: Mutex<EngineReader>,
+}
+
+fn get_session(
+    id: &str,
+    state: &tauri::State<'_, AppState>,
+) -> Result<Arc<GameSessionHandle>, Error> {
+    state
+        .game_sessions
+        .get(id)
+        .map(|handle| handle.clone())
+        .ok_or_else(|| Error::GameSessionNotFound(id.to_string()))
+}
+
+/// Starts the engine search if it is its turn, the move is played in the background.
+async fn start_engine_move(
+    id: String,
+    handle: Arc<GameSessionHandle>,
+    app: tauri::AppHandle,
+) -> Result<(), Error> {
+    let (generation, limit) = {
+        let mut session = handle.session.lock().await;
+        if session.game.result.is_some() || session.game.turn() != session.engine_color {
+            return Ok(());
+        }
+        let options = EngineOptions {
+            fen: session.game.start_fen.clone(),
+            moves: session.game.moves.clone(),
+            extra_options: session.engine_options.clone(),
+            variant: None,
+            preset: None,
+            search_moves: Vec::new(),
+            exclude_moves: Vec::new(),
+        };
+        let clock = session.game.clock.clone();
+        session.engine.set_options(options).await?;
+        session.engine.go_with_clock(&clock).await?;
+        session.engine_thinking = true;
+
+        let remaining = session.game.remaining(session.engine_color) as u64;
+        (
+            session.generation,
+            std::time::Duration::from_millis(remaining) + TIME_MARGIN,
+        )
+    };
+
+    tokio::spawn(async move {
+        let outcome = {
+            let mut reader = handle.reader.lock().await;
+            read_best_move(&mut reader, Some(limit)).await
+        };
+
+        let mut session = handle.session.lock().await;
+        if session.generation != generation {
+            // The position was taken back while the engine was thinking
+            return;
+        }
+        session.engine_thinking = false;
+
+        let elapsed = session.elapsed();
+        let engine_color = session.engine_color;
+        match outcome {
+            Ok(SearchOutcome::BestMove { uci, .. }) => {
+                if let Err(e) = session.game.play(&uci, elapsed) {
+                    warn!("Engine played an invalid move {}: {}", uci, e);
+                    session.game.result = Some((loss(engine_color), format!("illegal move {uci}")));
+                }
+            }
+            Ok(SearchOutcome::Timeout) => {
+                // Make sure the late move isn't taken as the answer to the next search
+                let _ = session.engine.stop().await;
+                let mut reader = handle.reader.lock().await;
+                let _ = read_best_move(&mut reader, Some(STOP_TIMEOUT)).await;
+                session.game.result = Some((loss(engine_color), "time forfeit".to_string()));
+            }
+            Ok(SearchOutcome::Disconnected) | Err(_) => {
+                session.game.result = Some((loss(engine_color), "engine disconnected".to_string()));
+            }
+        }
+        session.turn_start = Instant::now();
+
+        let _ = session.state(&id).emit(&app);
+    });
+
+    Ok(())
+}
+
+/// Starts a game against an engine, which moves first if it plays white.
+#[tauri::command]
+#[specta::specta]
+pub async fn start_game_session(
+    id: String,
+    settings: GameSessionSettings,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<GameSessionState, Error> {
+    let game = GameState::new(settings.fen.as_deref(), settings.time)?;
+
+    let mut engine_options = settings.engine_options;
+    engine_options.extend(settings.strength.options());
+    let key = (id.clone(), settings.engine.display().to_string());
+    let reservation = engine_health::reserve(&state, key, &mut engine_options).await;
+
+    let (mut engine, reader) = EngineProcess::new(settings.engine).await?;
+    engine.new_game().await?;
+
+    let handle = Arc::new(GameSessionHandle {
+        session: Mutex::new(GameSession {
+            engine,
+            engine_color: settings.engine_side.into(),
+            engine_options,
+            _reservation: reservation,
+            game,
+            turn_start: Instant::now(),
+            engine_thinking: false,
+            generation: 0,
+        }),
+        reader: Mutex::new(reader),
+    });
+
+    if let Some(previous) = state.game_sessions.insert(id.clone(), handle.clone()) {
+        let _ = previous.session.lock().await.engine.kill().await;
+    }
+    info!("Started game session {}", id);
+
+    start_engine_move(id.clone(), handle.clone(), app).await?;
+    let session = handle.session.lock().await;
+    Ok(session.state(&id))
+}
+
+/// Plays the move of the player, the engine answers through a `GameSessionState` event.
+#[tauri::command]
+#[specta::specta]
+pub async fn play_session_move(
+    id: String,
+    uci: String,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<GameSessionState, Error> {
+    let handle = get_session(&id, &state)?;
+    {
+        let mut session = handle.session.lock().await;
+        if session.game.turn() == session.engine_color {
+            return Err(Error::InvalidSessionMove(
+                "it is the engine's turn".to_string(),
+            ));
+        }
+        let elapsed = session.elapsed();
+        session.game.play(&uci, elapsed)?;
+        session.turn_start = Instant::now();
+    }
+
+    start_engine_move(id.clone(), handle.clone(), app).await?;
+    let session = handle.session.lock().await;
+    Ok(session.state(&id))
+}
+
+/// Takes back the last move of the player, along with the engine's answer.
+#[tauri::command]
+#[specta::specta]
+pub async fn takeback_session_move(
+    id: String,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<GameSessionState, Error> {
+    let handle = get_session(&id, &state)?;
+    {
+        let mut session = handle.session.lock().await;
+        if session.engine_thinking {
+            session.engine.stop().await?;
+            session.engine_thinking = false;
+        }
+        session.generation += 1;
+
+        let plies = if session.game.turn() == session.engine_color {
+            1
+        } else {
+            2
+        };
+        session.game.takeback(plies)?;
+        session.turn_start = Instant::now();
+    }
+
+    // The engine moves again if it had played the only move of the game
+    start_engine_move(id.clone(), handle.clone(), app).await?;
+    let session = handle.session.lock().await;
+    Ok(session.state(&id))
+}
+
+/// Returns the current state of the game, ending it if the player ran out of time.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_game_session(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<GameSessionState, Error> {
+    let handle = get_session(&id, &state)?;
+    let mut session = handle.session.lock().await;
+    if session.game.turn() != session.engine_color {
+        let elapsed = session.elapsed();
+        session.game.check_flag(elapsed);
+    }
+    Ok(session.state(&id))
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn close_game_session(
+    id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<(), Error> {
+    if let Some((_, handle)) = state.game_sessions.remove(&id) {
+        handle.session.lock().await.engine.kill().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(time: u32, inc: u32) -> PlayersTime {
+        PlayersTime {
+            white: time,
+            black: time,
+            winc: inc,
+            binc: inc,
+        }
+    }
+
+    #[test]
+    fn clocks_and_increments() {
+        let mut game = GameState::new(None, clock(60_000, 1_000)).unwrap();
+        game.play("e2e4", 5_000).unwrap();
+        assert_eq!(game.clock.white, 56_000);
+        game.play("e7e5", 2_000).unwrap();
+        assert_eq!(game.clock.black, 59_000);
+
+        // Moves played after the flag fell don't count
+        game.play("g1f3", 60_000).unwrap();
+        assert_eq!(game.moves.len(), 2);
+        assert_eq!(game.result, Some(("0-1", "time forfeit".to_string())));
+        assert!(game.play("g1f3", 0).is_err());
+    }
+
+    #[test]
+    fn game_end() {
+        let mut game = GameState::new(None, clock(60_000, 0)).unwrap();
+        for uci in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+            game.play(uci, 0).unwrap();
+        }
+        assert_eq!(game.result, Some(("0-1", "checkmate".to_string())));
+
+        let mut game = GameState::new(None, clock(60_000, 0)).unwrap();
+        for uci in [
+            "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8",
+        ] {
+            game.play(uci, 0).unwrap();
+        }
+        assert_eq!(
+            game.result,
+            Some(("1/2-1/2", "threefold repetition".to_string()))
+        );
+    }
+
+    #[test]
+    fn takebacks() {
+        let mut game = GameState::new(None, clock(60_000, 0)).unwrap();
+        for uci in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+            game.play(uci, 0).unwrap();
+        }
+        game.takeback(2).unwrap();
+        assert_eq!(game.result, None);
+        assert_eq!(game.moves, vec!["f2f3", "e7e5"]);
+        assert_eq!(game.turn(), Color::White);
+        assert_eq!(game.hashes.len(), 3);
+
+        game.takeback(5).unwrap();
+        assert!(game.moves.is_empty());
+        assert_eq!(game.pos.board(), Chess::default().board());
+    }
+
+    #[test]
+    fn strength_options() {
+        let strength = EngineStrength {
+            elo: Some(1500),
+            skill_level: None,
+        };
+        let options = strength.options();
+        assert_eq!(options.len(), 2);
+        assert_eq!(options[1].name, "UCI_Elo");
+        assert_eq!(options[1].value, "1500");
+    }
+}
@@ -3,54 +3,92 @@
     windows_subsystem = "windows"
 )]
 
+mod analysis_cache;
 mod chess;
+mod consensus;
 mod db;
+mod engine_health;
+mod engine_match;
+mod engine_registry;
+mod engine_transport;
 mod error;
 mod fide;
 mod fs;
+mod game_session;
 mod lexer;
+mod match_stats;
 mod oauth;
 mod opening;
 mod package_manager;
 mod pgn;
+mod polyglot;
 mod puzzle;
+mod tablebase;
+mod tablebase_server;
 mod telemetry;
+mod variant;
+mod wdl;
+mod xboard;
 
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::{atomic::AtomicBool, Arc, Mutex};
 use std::{fs::create_dir_all};
 
 use fs_extra::dir::{copy, CopyOptions};
 
-use chess::{BestMovesPayload, EngineProcess, ReportProgress};
+use chess::{BestMovesPayload, EngineProcess, MatchStatistics, ReportProgress};
+use consensus::ConsensusPayload;
 use dashmap::DashMap;
 use db::{DatabaseProgress, GameQueryJs, NormalizedGame, PositionStats};
 use derivative::Derivative;
+use engine_health::{EngineCrashed, EngineLimits, ReservedEngine};
+use engine_match::MatchProgress;
+use engine_registry::EngineRegistry;
 use fide::FidePlayer;
+use game_session::{GameSessionHandle, GameSessionState};
 use log::LevelFilter;
 use oauth::AuthState;
+use shakmaty::Chess;
+use shakmaty_syzygy::Tablebase;
 use specta_typescript::{BigIntExportBehavior, Typescript};
 use sysinfo::SystemExt;
 use tauri::path::BaseDirectory;
 use tauri::{AppHandle, Manager, Window};
 
+use crate::analysis_cache::{clear_analysis_cache, export_analysis_cache, AnalysisCache};
 use crate::chess::{
     analyze_game, get_engine_config, get_engine_logs, kill_engine, kill_engines, stop_engine,
 };
+use crate::consensus::{run_consensus_analysis, stop_consensus_analysis};
 use crate::db::{
-    clear_games, convert_pgn, create_indexes, delete_database, delete_db_game, delete_empty_games,
-    delete_indexes, export_to_pgn, get_player, get_players_game_info, get_tournaments,
+    analyze_game_accuracy, annotate_game, clear_games, convert_pgn, create_indexes,
+    delete_database, delete_db_game, delete_empty_games, delete_indexes, explore_position,
+    export_polyglot_book, export_to_pgn, get_player, get_players_game_info, get_tournaments,
     search_position,
 };
+use crate::engine_health::{get_engine_limits, get_engine_stats, set_engine_limits};
+use crate::engine_match::{run_engine_match, stop_engine_match};
+use crate::engine_registry::{
+    delete_engine_preset, list_registered_engines, register_engine, save_engine_preset,
+    unregister_engine, validate_engine_options,
+};
 use crate::fide::{download_fide_db, find_fide_player};
 use crate::fs::{set_file_as_executable, DownloadProgress};
+use crate::game_session::{
+    close_game_session, get_game_session, play_session_move, start_game_session,
+    takeback_session_move,
+};
 use crate::lexer::lex_pgn;
+use crate::match_stats::get_match_statistics;
 use crate::oauth::authenticate;
 use crate::package_manager::{
     check_package_installed, check_package_manager_available, find_executable_path, install_package,
 };
 use crate::pgn::{count_pgn_games, delete_game, read_games, write_game};
+use crate::polyglot::get_book_moves;
 use crate::puzzle::{get_puzzle, get_puzzle_db_info, get_puzzle_rating_range};
+use crate::tablebase::{load_tablebases, probe_tablebase};
+use crate::tablebase_server::{start_tablebase_server, stop_tablebase_server};
 use crate::telemetry::{get_telemetry_config, get_telemetry_enabled, handle_initial_run_telemetry, set_telemetry_enabled, get_user_country_api, get_user_country_locale, get_user_id_command, get_platform_info_command};
 use crate::{
     chess::get_best_moves,
@@ -73,6 +111,8 @@ pub type GameData = (
     i32,
     i32,
     i32,
+    Option<i32>,
+    Option<i32>,
 );
 
 #[derive(Derivative)]
@@ -90,11 +130,26 @@ pub struct AppState {
     fide_players: RwLock<Vec<FidePlayer>>,
     engine_processes: DashMap<(String, String), Arc<tokio::sync::Mutex<EngineProcess>>>,
     auth: AuthState,
+    running_matches: DashMap<String, Arc<AtomicBool>>,
+    running_consensus: DashMap<String, Arc<AtomicBool>>,
+    game_sessions: DashMap<String, Arc<GameSessionHandle>>,
+    tablebase: RwLock<Option<Arc<Tablebase<Chess>>>>,
+    tablebase_server: tokio::sync::Mutex<Option<tokio::sync::oneshot::Sender<()>>>,
+    engine_registry: tokio::sync::Mutex<Option<EngineRegistry>>,
+    engine_limits: RwLock<EngineLimits>,
+    /// Engines of matches, games and analyses, counted in the engine limits
+    engine_reservations: Arc<DashMap<u64, ReservedEngine>>,
+    /// Opened on first use, and only used from blocking tasks
+    analysis_cache: Mutex<Option<AnalysisCache>>,
+    /// Kept between calls, as CPU usage is measured from one refresh to the next
+    #[derivative(Default(value = "tokio::sync::Mutex::new(sysinfo::System::new())"))]
+    system: tokio::sync::Mutex<sysinfo::System>,
 }
 
 const REQUIRED_DIRS: &[(BaseDirectory, &str)] = &[
     (BaseDirectory::AppData, "engines"),
     (BaseDirectory::AppData, "db"),
+    (BaseDirectory::AppData, "books"),
     (BaseDirectory::AppData, "presets"),
     (BaseDirectory::AppData, "puzzles"),
     (BaseDirectory::AppData, "documents"),
@@ -321,10 +376,28 @@ async fn main() {
             find_fide_player,
             get_best_moves,
             analyze_game,
+            clear_analysis_cache,
+            export_analysis_cache,
+            load_tablebases,
+            probe_tablebase,
+            start_tablebase_server,
+            stop_tablebase_server,
+            run_consensus_analysis,
+            stop_consensus_analysis,
+            annotate_game,
+            analyze_game_accuracy,
             stop_engine,
             kill_engine,
             kill_engines,
             get_engine_logs,
+            run_engine_match,
+            stop_engine_match,
+            get_match_statistics,
+            start_game_session,
+            play_session_move,
+            takeback_session_move,
+            get_game_session,
+            close_game_session,
             memory_size,
             get_puzzle,
             search_opening_name,
@@ -332,6 +405,15 @@ async fn main() {
             get_opening_from_name,
             get_players_game_info,
             get_engine_config,
+            list_registered_engines,
+            register_engine,
+            unregister_engine,
+            validate_engine_options,
+            save_engine_preset,
+            delete_engine_preset,
+            get_engine_stats,
+            get_engine_limits,
+            set_engine_limits,
             file_exists,
             get_file_metadata,
             merge_players,
@@ -352,6 +434,8 @@ async fn main() {
             delete_db_game,
             delete_database,
             export_to_pgn,
+            export_polyglot_book,
+            get_book_moves,
             authenticate,
             write_game,
             download_fide_db,
@@ -362,6 +446,7 @@ async fn main() {
             get_game,
             update_game,
             search_position,
+            explore_position,
             get_players,
             get_puzzle_db_info,
             get_puzzle_rating_range,
@@ -380,8 +465,13 @@ async fn main() {
         ))
         .events(tauri_specta::collect_events!(
             BestMovesPayload,
+            ConsensusPayload,
             DatabaseProgress,
             DownloadProgress,
+            EngineCrashed,
+            GameSessionState,
+            MatchProgress,
+            MatchStatistics,
             ReportProgress
         ));
 
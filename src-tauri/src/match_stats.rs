@@ -0,0 +1,382 @@
+use std::{fs::File, path::PathBuf};
+
+use pgn_reader::{BufferedReader, RawHeader, Skip, Visitor};
+
+use crate::{
+    chess::{MatchStatistics, SprtResult, SprtSettings, SprtStatus},
+    error::Error,
+};
+
+/// Quantile of the normal distribution for a 95% confidence interval.
+const Z_95: f64 = 1.959_963_984_540_054;
+
+/// Counts the results of an engine, in half points scored per game.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ResultCounts {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    pub pentanomial: [u32; 5],
+}
+
+impl ResultCounts {
+    pub fn add_game(&mut self, half_points: u8) {
+        match half_points {
+            2 => self.wins += 1,
+            1 => self.draws += 1,
+            _ => self.losses += 1,
+        }
+    }
+
+    /// Records the two games played with the same opening and swapped colors.
+    pub fn add_pair(&mut self, first: u8, second: u8) {
+        self.pentanomial[(first.min(2) + second.min(2)) as usize] += 1;
+    }
+
+    fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Mean score, variance and sample size of the results.
+    ///
+    /// Game pairs are used when there are any, since they account for the
+    /// correlation between the two games of an opening.
+    fn distribution(&self) -> Option<(f64, f64, f64)> {
+        let pairs: u32 = self.pentanomial.iter().sum();
+        let samples: Vec<(f64, u32)> = if pairs > 0 {
+            self.pentanomial
+                .iter()
+                .enumerate()
+                .map(|(i, n)| (i as f64 / 4.0, *n))
+                .collect()
+        } else {
+            vec![(1.0, self.wins), (0.5, self.draws), (0.0, self.losses)]
+        };
+
+        let n: u32 = samples.iter().map(|(_, n)| n).sum();
+        if n == 0 {
+            return None;
+        }
+        let n = n as f64;
+        let mean = samples.iter().map(|(x, c)| x * *c as f64).sum::<f64>() / n;
+        let variance = samples
+            .iter()
+            .map(|(x, c)| (x - mean).powi(2) * *c as f64)
+            .sum::<f64>()
+            / n;
+        Some((mean, variance, n))
+    }
+}
+
+/// Elo difference corresponding to an expected score.
+pub fn elo_from_score(score: f64) -> Option<f64> {
+    (score > 0.0 && score < 1.0).then(|| -400.0 * (1.0 / score - 1.0).log10())
+}
+
+/// Expected score for an Elo difference.
+pub fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10_f64.powf(-elo / 400.0))
+}
+
+/// Error function, Abramowitz and Stegun formula 7.1.26 (error below 1.5e-7).
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let y = 1.0 - poly * (-x * x).exp();
+    if x < 0.0 {
+        -y
+    } else {
+        y
+    }
+}
+
+/// Likelihood of superiority, draws don't tell anything about which engine is stronger.
+pub fn los(wins: u32, losses: u32) -> f64 {
+    if wins + losses == 0 {
+        return 0.5;
+    }
+    let (wins, losses) = (wins as f64, losses as f64);
+    0.5 * (1.0 + erf((wins - losses) / (2.0 * (wins + losses)).sqrt()))
+}
+
+/// Log likelihood ratio of the generalized SPRT, using the normal approximation.
+pub fn sprt_llr(mean: f64, variance: f64, n: f64, elo0: f64, elo1: f64) -> f64 {
+    if variance <= 0.0 {
+        return 0.0;
+    }
+    let (s0, s1) = (score_from_elo(elo0), score_from_elo(elo1));
+    n * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance)
+}
+
+impl SprtSettings {
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    pub fn status(&self, counts: &ResultCounts) -> SprtStatus {
+        let llr = counts
+            .distribution()
+            .map(|(mean, variance, n)| sprt_llr(mean, variance, n, self.elo0, self.elo1))
+            .unwrap_or(0.0);
+        let (lower_bound, upper_bound) = self.bounds();
+        let result = if llr >= upper_bound {
+            Some(SprtResult::AcceptH1)
+        } else if llr <= lower_bound {
+            Some(SprtResult::AcceptH0)
+        } else {
+            None
+        };
+        SprtStatus {
+            llr,
+            lower_bound,
+            upper_bound,
+            result,
+        }
+    }
+}
+
+impl MatchStatistics {
+    pub fn new(
+        id: String,
+        engine: String,
+        counts: &ResultCounts,
+        sprt: Option<&SprtSettings>,
+    ) -> Self {
+        let games = counts.games();
+        let score = if games > 0 {
+            (counts.wins as f64 + counts.draws as f64 / 2.0) / games as f64
+        } else {
+            0.5
+        };
+
+        let (elo, elo_error) = match counts.distribution() {
+            Some((mean, variance, n)) => {
+                let margin = Z_95 * (variance / n).sqrt();
+                let error = elo_from_score(mean + margin)
+                    .zip(elo_from_score(mean - margin))
+                    .map(|(upper, lower)| (upper - lower) / 2.0);
+                (elo_from_score(mean), error)
+            }
+            None => (None, None),
+        };
+
+        MatchStatistics {
+            id,
+            engine,
+            wins: counts.wins,
+            draws: counts.draws,
+            losses: counts.losses,
+            pentanomial: counts.pentanomial,
+            score,
+            elo,
+            elo_error,
+            los: los(counts.wins, counts.losses),
+            sprt: sprt.map(|sprt| sprt.status(counts)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct PgnResult {
+    white: String,
+    black: String,
+    result: String,
+}
+
+/// Only reads the players and result of the games.
+#[derive(Default)]
+struct ResultVisitor {
+    game: PgnResult,
+}
+
+impl Visitor for ResultVisitor {
+    type Result = PgnResult;
+
+    fn begin_game(&mut self) {
+        self.game = PgnResult::default();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        let value = value.decode_utf8_lossy().into_owned();
+        match key {
+            b"White" => self.game.white = value,
+            b"Black" => self.game.black = value,
+            b"Result" => self.game.result = value,
+            _ => {}
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        std::mem::take(&mut self.game)
+    }
+}
+
+/// Counts the results of an engine in a list of games.
+///
+/// Consecutive games against the same opponent with swapped colors are
+/// considered a pair played with the same opening.
+fn count_results(games: &[PgnResult], engine: &str) -> ResultCounts {
+    let mut counts = ResultCounts::default();
+    let mut pending: Option<(&str, bool, u8)> = None;
+
+    for game in games {
+        let (is_white, opponent) = if game.white == engine {
+            (true, game.black.as_str())
+        } else if game.black == engine {
+            (false, game.white.as_str())
+        } else {
+            continue;
+        };
+        let half_points = match (game.result.as_str(), is_white) {
+            ("1-0", true) | ("0-1", false) => 2,
+            ("1/2-1/2", _) => 1,
+            ("1-0", false) | ("0-1", true) => 0,
+            _ => continue,
+        };
+        counts.add_game(half_points);
+
+        pending = match pending {
+            Some((first_opponent, first_white, first))
+                if first_opponent == opponent && first_white != is_white =>
+            {
+                counts.add_pair(first, half_points);
+                None
+            }
+            _ => Some((opponent, is_white, half_points)),
+        };
+    }
+    counts
+}
+
+/// Computes the statistics of an engine from a PGN file of its games.
+#[tauri::command]
+#[specta::specta]
+pub async fn get_match_statistics(
+    file: PathBuf,
+    engine: String,
+    sprt: Option<SprtSettings>,
+) -> Result<MatchStatistics, Error> {
+    let mut reader = BufferedReader::new(File::open(&file)?);
+    let mut visitor = ResultVisitor::default();
+    let mut games = Vec::new();
+    while let Some(game) = reader.read_game(&mut visitor)? {
+        games.push(game);
+    }
+
+    let counts = count_results(&games, &engine);
+    Ok(MatchStatistics::new(
+        file.to_string_lossy().into_owned(),
+        engine,
+        &counts,
+        sprt.as_ref(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(white: &str, black: &str, result: &str) -> PgnResult {
+        PgnResult {
+            white: white.to_string(),
+            black: black.to_string(),
+            result: result.to_string(),
+        }
+    }
+
+    #[test]
+    fn elo_conversions() {
+        assert_eq!(elo_from_score(0.5), Some(0.0));
+        assert_eq!(elo_from_score(1.0), None);
+        assert!((elo_from_score(0.75).unwrap() - 190.85).abs() < 0.01);
+        assert!((score_from_elo(190.85) - 0.75).abs() < 1e-4);
+    }
+
+    #[test]
+    fn likelihood_of_superiority() {
+        assert_eq!(los(0, 0), 0.5);
+        assert!((los(10, 10) - 0.5).abs() < 1e-9);
+        // 60 wins and 40 losses: z = 20 / sqrt(100) = 2
+        assert!((los(60, 40) - 0.97725).abs() < 1e-4);
+    }
+
+    #[test]
+    fn pairs_from_pgn() {
+        let games = [
+            game("A", "B", "1-0"),
+            game("B", "A", "1/2-1/2"),
+            game("A", "C", "0-1"),
+            game("C", "A", "1-0"),
+            game("B", "C", "1-0"),
+            game("A", "B", "*"),
+        ];
+        let counts = count_results(&games, "A");
+        assert_eq!((counts.wins, counts.draws, counts.losses), (1, 1, 2));
+        assert_eq!(counts.pentanomial, [1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn statistics() {
+        let mut counts = ResultCounts::default();
+        for _ in 0..30 {
+            counts.add_game(2);
+        }
+        for _ in 0..40 {
+            counts.add_game(1);
+        }
+        for _ in 0..30 {
+            counts.add_game(0);
+        }
+        let stats = MatchStatistics::new(String::new(), "A".to_string(), &counts, None);
+        assert_eq!(stats.score, 0.5);
+        assert_eq!(stats.elo, Some(0.0));
+        // Variance of 0.15 per game, a score margin of 1.96 * sqrt(0.0015)
+        assert!((stats.elo_error.unwrap() - 53.16).abs() < 0.01);
+
+        let mut counts = ResultCounts::default();
+        counts.add_game(2);
+        let stats = MatchStatistics::new(String::new(), "A".to_string(), &counts, None);
+        assert_eq!((stats.elo, stats.elo_error), (None, None));
+    }
+
+    #[test]
+    fn sprt_decisions() {
+        let sprt = SprtSettings {
+            elo0: 0.0,
+            elo1: 10.0,
+            alpha: 0.05,
+            beta: 0.05,
+        };
+        let (lower, upper) = sprt.bounds();
+        assert!((upper - 2.944).abs() < 1e-3);
+        assert!((lower + 2.944).abs() < 1e-3);
+
+        let mut counts = ResultCounts::default();
+        assert_eq!(sprt.status(&counts).result, None);
+
+        // A clearly stronger engine
+        for _ in 0..400 {
+            counts.add_pair(2, 1);
+            counts.add_pair(1, 1);
+        }
+        assert_eq!(sprt.status(&counts).result, Some(SprtResult::AcceptH1));
+
+        // A clearly weaker engine
+        let mut counts = ResultCounts::default();
+        for _ in 0..400 {
+            counts.add_pair(0, 1);
+            counts.add_pair(1, 1);
+        }
+        assert_eq!(sprt.status(&counts).result, Some(SprtResult::AcceptH0));
+    }
+}
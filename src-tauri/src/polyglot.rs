@@ -0,0 +1,303 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Component, PathBuf},
+};
+
+use serde::Serialize;
+use shakmaty::{
+    fen::Fen,
+    san::SanPlus,
+    zobrist::{Zobrist64, ZobristHash},
+    CastlingMode, Chess, EnPassantMode, Move, Position, Role,
+};
+use specta::Type;
+use tauri::{path::BaseDirectory, Manager};
+
+use crate::{error::Error, opening::get_opening_from_setup};
+
+/// Size in bytes of an entry in a Polyglot book.
+pub const ENTRY_SIZE: usize = 16;
+
+/// A single entry of a Polyglot book.
+///
+/// Books are a flat list of these entries sorted by key, so all the moves of
+/// a position are stored next to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookEntry {
+    pub key: u64,
+    pub mv: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+impl BookEntry {
+    pub fn to_bytes(&self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.mv.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; ENTRY_SIZE]) -> Self {
+        BookEntry {
+            key: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            mv: u16::from_be_bytes(bytes[8..10].try_into().unwrap()),
+            weight: u16::from_be_bytes(bytes[10..12].try_into().unwrap()),
+            learn: u32::from_be_bytes(bytes[12..16].try_into().unwrap()),
+        }
+    }
+}
+
+/// Returns the Polyglot key of a position.
+///
+/// shakmaty uses the Polyglot random numbers, so its Zobrist hash is the book
+/// key. Polyglot only hashes the en passant file if a pawn of the side to
+/// move stands next to the pushed pawn, which is the pseudo legal mode.
+pub fn polyglot_key(pos: &Chess) -> u64 {
+    pos.zobrist_hash::<Zobrist64>(EnPassantMode::PseudoLegal).0
+}
+
+/// Encodes a move the way Polyglot does: destination in bits 0-5, origin in
+/// bits 6-11 and the promotion piece in bits 12-14. Castling is stored as the
+/// king capturing its own rook.
+pub fn encode_move(m: &Move) -> u16 {
+    let (from, to) = match *m {
+        Move::Castle { king, rook } => (king, rook),
+        _ => (m.from().expect("no drops in standard chess"), m.to()),
+    };
+    let promotion = match m.promotion() {
+        Some(Role::Knight) => 1,
+        Some(Role::Bishop) => 2,
+        Some(Role::Rook) => 3,
+        Some(Role::Queen) => 4,
+        _ => 0,
+    };
+    u16::from(to) | (u16::from(from) << 6) | (promotion << 12)
+}
+
+/// Finds the legal move of the position matching a Polyglot move.
+pub fn decode_move(mv: u16, pos: &Chess) -> Option<Move> {
+    pos.legal_moves().into_iter().find(|m| encode_move(m) == mv)
+}
+
+/// Reads all the entries of a position from a book sorted by key.
+pub fn read_entries<R: Read + Seek>(reader: &mut R, key: u64) -> std::io::Result<Vec<BookEntry>> {
+    let len = reader.seek(SeekFrom::End(0))? / ENTRY_SIZE as u64;
+    let mut buf = [0; ENTRY_SIZE];
+
+    let mut read_at = |reader: &mut R, index: u64| -> std::io::Result<BookEntry> {
+        reader.seek(SeekFrom::Start(index * ENTRY_SIZE as u64))?;
+        reader.read_exact(&mut buf)?;
+        Ok(BookEntry::from_bytes(&buf))
+    };
+
+    // Binary search for the first entry of the position
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if read_at(reader, mid)?.key < key {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    let mut entries = Vec::new();
+    for index in low..len {
+        let entry = read_at(reader, index)?;
+        if entry.key != key {
+            break;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Sorts entries the way Polyglot expects them: by key, then by decreasing weight.
+pub fn sort_entries(entries: &mut [BookEntry]) {
+    entries.sort_by(|a, b| a.key.cmp(&b.key).then(b.weight.cmp(&a.weight)));
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct BookMove {
+    pub uci: String,
+    pub san: String,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct BookMoves {
+    pub opening: Option<String>,
+    pub moves: Vec<BookMove>,
+}
+
+/// Returns the moves of a position stored in a Polyglot book of the `books`
+/// directory, sorted by decreasing weight.
+#[tauri::command]
+#[specta::specta]
+pub fn get_book_moves(fen: &str, book: PathBuf, app: tauri::AppHandle) -> Result<BookMoves, Error> {
+    // Only books of the directory can be read, not any file given by its path
+    let mut components = book.components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        return Err(Error::InvalidBookName(book.display().to_string()));
+    }
+    // ChessBase books are split in .ctg, .ctb and .cto files of another format
+    if book
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ctg"))
+    {
+        return Err(Error::UnsupportedBookFormat(book.display().to_string()));
+    }
+
+    let path = app
+        .path()
+        .resolve(PathBuf::from("books").join(book), BaseDirectory::AppData)?;
+
+    let fen: Fen = fen.parse()?;
+    let opening = get_opening_from_setup(fen.clone().into_setup()).ok();
+    let pos: Chess = fen.into_position(CastlingMode::Chess960)?;
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut entries = read_entries(&mut reader, polyglot_key(&pos))?;
+    entries.sort_by(|a, b| b.weight.cmp(&a.weight));
+
+    let moves = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let m = decode_move(entry.mv, &pos)?;
+            Some(BookMove {
+                uci: m.to_uci(CastlingMode::Standard).to_string(),
+                san: SanPlus::from_move(pos.clone(), &m).to_string(),
+                weight: entry.weight,
+                learn: entry.learn,
+            })
+        })
+        .collect();
+
+    Ok(BookMoves { opening, moves })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shakmaty::{uci::UciMove, CastlingMode, Position};
+
+    fn play(pos: &mut Chess, uci: &str) -> Move {
+        let m = uci.parse::<UciMove>().unwrap().to_move(pos).unwrap();
+        pos.play_unchecked(&m);
+        m
+    }
+
+    #[test]
+    fn known_keys() {
+        // Reference values from the Polyglot book format specification
+        let mut pos = Chess::default();
+        assert_eq!(polyglot_key(&pos), 0x463b96181691fc9c);
+        play(&mut pos, "e2e4");
+        assert_eq!(polyglot_key(&pos), 0x823c9b50fd114196);
+        play(&mut pos, "d7d5");
+        assert_eq!(polyglot_key(&pos), 0x0756b94461c50fb0);
+        play(&mut pos, "e4e5");
+        play(&mut pos, "f7f5");
+        assert_eq!(polyglot_key(&pos), 0x22a48b5a8e47ff78);
+    }
+
+    #[test]
+    fn move_encoding() {
+        let mut pos = Chess::default();
+        let m = play(&mut pos, "e2e4");
+        assert_eq!(encode_move(&m), (12 << 6) | 28);
+
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let pos: Chess = fen
+            .parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let castle = pos
+            .legal_moves()
+            .into_iter()
+            .find(|m| m.castling_side() == Some(shakmaty::CastlingSide::KingSide))
+            .unwrap();
+        // e1h1
+        assert_eq!(encode_move(&castle), (4 << 6) | 7);
+
+        let pos: Chess = "8/P7/8/8/8/8/8/k6K w - - 0 1"
+            .parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let promotion = "a7a8q".parse::<UciMove>().unwrap().to_move(&pos).unwrap();
+        assert_eq!(encode_move(&promotion), (4 << 12) | (48 << 6) | 56);
+    }
+
+    #[test]
+    fn entry_roundtrip() {
+        let entry = BookEntry {
+            key: 0x463b96181691fc9c,
+            mv: 796,
+            weight: 42,
+            learn: 7,
+        };
+        let bytes = entry.to_bytes();
+        assert_eq!(&bytes[0..2], &[0x46, 0x3b]);
+        assert_eq!(BookEntry::from_bytes(&bytes), entry);
+    }
+
+    #[test]
+    fn read_position_entries() {
+        let mut entries = vec![
+            BookEntry {
+                key: 1,
+                mv: 10,
+                weight: 1,
+                learn: 0,
+            },
+            BookEntry {
+                key: 3,
+                mv: 30,
+                weight: 1,
+                learn: 0,
+            },
+            BookEntry {
+                key: 2,
+                mv: 20,
+                weight: 5,
+                learn: 0,
+            },
+            BookEntry {
+                key: 2,
+                mv: 21,
+                weight: 7,
+                learn: 3,
+            },
+        ];
+        sort_entries(&mut entries);
+        let bytes: Vec<u8> = entries.iter().flat_map(|e| e.to_bytes()).collect();
+        let mut reader = std::io::Cursor::new(bytes);
+
+        let found = read_entries(&mut reader, 2).unwrap();
+        assert_eq!(found.iter().map(|e| e.mv).collect::<Vec<_>>(), vec![21, 20]);
+        assert_eq!(found[0].learn, 3);
+        assert!(read_entries(&mut reader, 4).unwrap().is_empty());
+        assert_eq!(read_entries(&mut reader, 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn decode_castling() {
+        let pos: Chess = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"
+            .parse::<shakmaty::fen::Fen>()
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let m = decode_move((4 << 6) | 7, &pos).unwrap();
+        assert_eq!(m.to_uci(CastlingMode::Standard).to_string(), "e1g1");
+    }
+}
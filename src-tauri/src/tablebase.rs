@@ -0,0 +1,360 @@
+use std::{path::PathBuf, sync::Arc};
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use shakmaty::{fen::Fen, san::SanPlus, uci::UciMove, CastlingMode, Chess, Color, Move, Position};
+use shakmaty_syzygy::{AmbiguousWdl, MaybeRounded, Tablebase};
+use specta::Type;
+use tauri::Manager;
+use vampirc_uci::uci::ScoreValue;
+
+use crate::{
+    chess::BestMoves,
+    error::{Error, Result},
+    wdl::Wdl,
+    AppState,
+};
+
+/// Centipawn score given to tablebase wins, high enough to never be confused
+/// with an engine evaluation but below mate scores.
+const TB_WIN_CP: i32 = 20_000;
+
+/// Outcome of a position with perfect play, from the point of view of the side to move.
+///
+/// Cursed wins and blessed losses are drawn because of the 50 move rule. The
+/// `Maybe` variants are wins or losses that could also be drawn by it, when
+/// the position was reached after some moves without a capture or pawn move.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum TablebaseWdl {
+    Loss,
+    MaybeLoss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    MaybeWin,
+    Win,
+}
+
+impl TablebaseWdl {
+    pub(crate) fn from_ambiguous(wdl: AmbiguousWdl) -> Self {
+        match wdl {
+            AmbiguousWdl::Loss => TablebaseWdl::Loss,
+            AmbiguousWdl::MaybeLoss => TablebaseWdl::MaybeLoss,
+            AmbiguousWdl::BlessedLoss => TablebaseWdl::BlessedLoss,
+            AmbiguousWdl::Draw => TablebaseWdl::Draw,
+            AmbiguousWdl::CursedWin => TablebaseWdl::CursedWin,
+            AmbiguousWdl::MaybeWin => TablebaseWdl::MaybeWin,
+            AmbiguousWdl::Win => TablebaseWdl::Win,
+        }
+    }
+
+    /// The same outcome seen by the other side.
+    pub fn flip(self) -> Self {
+        match self {
+            TablebaseWdl::Loss => TablebaseWdl::Win,
+            TablebaseWdl::MaybeLoss => TablebaseWdl::MaybeWin,
+            TablebaseWdl::BlessedLoss => TablebaseWdl::CursedWin,
+            TablebaseWdl::Draw => TablebaseWdl::Draw,
+            TablebaseWdl::CursedWin => TablebaseWdl::BlessedLoss,
+            TablebaseWdl::MaybeWin => TablebaseWdl::MaybeLoss,
+            TablebaseWdl::Win => TablebaseWdl::Loss,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TablebaseMove {
+    pub uci: String,
+    pub san: String,
+    /// Outcome after the move, for the side playing it
+    pub wdl: Option<TablebaseWdl>,
+    /// Plies until the next capture or pawn move, negative when losing
+    pub dtz: Option<i32>,
+    /// Whether `dtz` is exact, some tables only store it rounded
+    pub precise_dtz: bool,
+    pub zeroing: bool,
+    pub checkmate: bool,
+    pub stalemate: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TablebaseProbe {
+    pub wdl: Option<TablebaseWdl>,
+    pub dtz: Option<i32>,
+    pub precise_dtz: bool,
+    /// Legal moves, best first
+    pub moves: Vec<TablebaseMove>,
+}
+
+#[derive(Serialize, Debug, Clone, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct TablebaseInfo {
+    pub files: u32,
+    pub max_pieces: u32,
+}
+
+/// Returns the outcome of a position, its DTZ and whether the DTZ is precise.
+fn probe_position(
+    tables: &Tablebase<Chess>,
+    pos: &Chess,
+) -> (Option<TablebaseWdl>, Option<i32>, bool) {
+    let wdl = tables
+        .probe_wdl(pos)
+        .map(TablebaseWdl::from_ambiguous)
+        .map_err(|e| debug!("WDL probe failed: {}", e))
+        .ok();
+    match tables.probe_dtz(pos) {
+        Ok(MaybeRounded::Precise(dtz)) => (wdl, Some(dtz.0), true),
+        Ok(MaybeRounded::Rounded(dtz)) => (wdl, Some(dtz.0), false),
+        Err(e) => {
+            debug!("DTZ probe failed: {}", e);
+            (wdl, None, false)
+        }
+    }
+}
+
+fn probe_move(tables: &Tablebase<Chess>, pos: &Chess, m: &Move) -> TablebaseMove {
+    let mut after = pos.clone();
+    let san = SanPlus::from_move_and_play_unchecked(&mut after, m);
+
+    let (wdl, dtz, precise_dtz) = if after.is_checkmate() {
+        (Some(TablebaseWdl::Win), Some(1), true)
+    } else if after.is_stalemate() {
+        (Some(TablebaseWdl::Draw), Some(0), true)
+    } else {
+        let (wdl, dtz, precise) = probe_position(tables, &after);
+        (wdl.map(TablebaseWdl::flip), dtz.map(|dtz| -dtz), precise)
+    };
+
+    TablebaseMove {
+        uci: m.to_uci(CastlingMode::Standard).to_string(),
+        san: san.to_string(),
+        wdl,
+        dtz,
+        precise_dtz,
+        zeroing: m.is_zeroing(),
+        checkmate: after.is_checkmate(),
+        stalemate: after.is_stalemate(),
+    }
+}
+
+/// Orders moves from best to worst: the best outcome first, then the fastest
+/// win or the slowest loss.
+fn sort_moves(moves: &mut [TablebaseMove]) {
+    moves.sort_by_key(|m| {
+        let wdl = std::cmp::Reverse(m.wdl);
+        let dtz = match m.wdl {
+            Some(TablebaseWdl::Win | TablebaseWdl::CursedWin | TablebaseWdl::MaybeWin) => {
+                m.dtz.map_or(i32::MAX, |dtz| dtz.abs())
+            }
+            _ => m.dtz.map_or(0, |dtz| -dtz.abs()),
+        };
+        (wdl, !m.checkmate, dtz, !m.zeroing)
+    });
+}
+
+/// Replaces the score of the engine's lines with the tablebase outcome of
+/// their first move. Mate scores are kept since they are exact already.
+///
+/// Returns whether any line was rescored.
+pub fn rescore_lines(tables: &Tablebase<Chess>, pos: &Chess, lines: &mut [BestMoves]) -> bool {
+    if pos.board().occupied().count() > tables.max_pieces() {
+        return false;
+    }
+
+    let mut rescored = false;
+    for line in lines {
+        if matches!(line.score.value, ScoreValue::Mate(_)) {
+            continue;
+        }
+        let Some(m) = line
+            .uci_moves
+            .first()
+            .and_then(|uci| UciMove::from_ascii(uci.as_bytes()).ok())
+            .and_then(|uci| uci.to_move(pos).ok())
+        else {
+            continue;
+        };
+
+        let probe = probe_move(tables, pos, &m);
+        let cp = match (probe.wdl, probe.dtz) {
+            (Some(TablebaseWdl::Win), dtz) => TB_WIN_CP - dtz.unwrap_or(0).abs(),
+            (Some(TablebaseWdl::Loss), dtz) => -TB_WIN_CP + dtz.unwrap_or(0).abs(),
+            (Some(TablebaseWdl::Draw | TablebaseWdl::CursedWin | TablebaseWdl::BlessedLoss), _) => {
+                0
+            }
+            // Could be drawn by the 50 move rule, the engine knows better
+            _ => continue,
+        };
+        // Scores are from white's point of view
+        let cp = match pos.turn() {
+            Color::White => cp,
+            Color::Black => -cp,
+        };
+        line.score.value = ScoreValue::Cp(cp);
+        line.score.wdl = None;
+        line.wdl = match cp.signum() {
+            1 => Wdl::WIN,
+            -1 => Wdl::LOSS,
+            _ => Wdl::DRAW,
+        };
+        line.expected_score = line.wdl.expected_score();
+        line.tablebase = true;
+        rescored = true;
+    }
+    rescored
+}
+
+/// Rescores lines with the tablebases loaded in the app, if any.
+pub async fn rescore_with_app_tables(
+    app: &tauri::AppHandle,
+    fen: &str,
+    moves: &[String],
+    lines: &mut [BestMoves],
+) {
+    let state = app.state::<AppState>();
+    let Some(tables) = state.tablebase.read().await.clone() else {
+        return;
+    };
+
+    match position_after(fen, moves) {
+        Ok(pos) => {
+            rescore_lines(&tables, &pos, lines);
+        }
+        Err(e) => warn!("Failed to rescore lines with the tablebases: {}", e),
+    }
+}
+
+fn position_after(fen: &str, moves: &[String]) -> Result<Chess> {
+    let mut pos: Chess = Fen::from_ascii(fen.as_bytes())?.into_position(CastlingMode::Chess960)?;
+    for uci in moves {
+        let m = UciMove::from_ascii(uci.as_bytes())?.to_move(&pos)?;
+        pos.play_unchecked(&m);
+    }
+    Ok(pos)
+}
+
+/// Probes a position and each of its legal moves, best moves first.
+pub fn probe(tables: &Tablebase<Chess>, pos: &Chess) -> TablebaseProbe {
+    let (wdl, dtz, precise_dtz) = probe_position(tables, pos);
+    let mut moves: Vec<TablebaseMove> = pos
+        .legal_moves()
+        .iter()
+        .map(|m| probe_move(tables, pos, m))
+        .collect();
+    sort_moves(&mut moves);
+
+    TablebaseProbe {
+        wdl,
+        dtz,
+        precise_dtz,
+        moves,
+    }
+}
+
+/// Loads the Syzygy files of the given directories, replacing the previous tablebases.
+#[tauri::command]
+#[specta::specta]
+pub async fn load_tablebases(
+    paths: Vec<PathBuf>,
+    state: tauri::State<'_, AppState>,
+) -> Result<TablebaseInfo> {
+    let mut tables = Tablebase::new();
+    let mut files = 0;
+    for path in &paths {
+        files += tables.add_directory(path)?;
+    }
+    let max_pieces = tables.max_pieces();
+    info!(
+        "Loaded {} tablebase files with up to {} pieces from {:?}",
+        files, max_pieces, paths
+    );
+
+    *state.tablebase.write().await = (files > 0).then(|| Arc::new(tables));
+    Ok(TablebaseInfo {
+        files: files as u32,
+        max_pieces: max_pieces as u32,
+    })
+}
+
+/// Probes the outcome of a position and of each of its legal moves.
+#[tauri::command]
+#[specta::specta]
+pub async fn probe_tablebase(
+    fen: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<TablebaseProbe> {
+    let tables = state
+        .tablebase
+        .read()
+        .await
+        .clone()
+        .ok_or(Error::NoTablebase)?;
+
+    let pos: Chess = Fen::from_ascii(fen.as_bytes())?.into_position(CastlingMode::Chess960)?;
+    if pos.board().occupied().count() > tables.max_pieces() {
+        return Err(Error::TooManyPiecesForTablebase(tables.max_pieces()));
+    }
+
+    Ok(probe(&tables, &pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tb_move(uci: &str, wdl: TablebaseWdl, dtz: i32) -> TablebaseMove {
+        TablebaseMove {
+            uci: uci.to_string(),
+            san: String::new(),
+            wdl: Some(wdl),
+            dtz: Some(dtz),
+            precise_dtz: true,
+            zeroing: false,
+            checkmate: false,
+            stalemate: false,
+        }
+    }
+
+    #[test]
+    fn flip_outcomes() {
+        for wdl in [
+            TablebaseWdl::Loss,
+            TablebaseWdl::MaybeLoss,
+            TablebaseWdl::BlessedLoss,
+            TablebaseWdl::Draw,
+            TablebaseWdl::CursedWin,
+            TablebaseWdl::MaybeWin,
+            TablebaseWdl::Win,
+        ] {
+            assert_eq!(wdl.flip().flip(), wdl);
+        }
+        assert_eq!(TablebaseWdl::CursedWin.flip(), TablebaseWdl::BlessedLoss);
+    }
+
+    #[test]
+    fn best_moves_first() {
+        let mut moves = vec![
+            tb_move("a", TablebaseWdl::Draw, 0),
+            tb_move("b", TablebaseWdl::Win, 15),
+            tb_move("c", TablebaseWdl::Loss, -4),
+            tb_move("d", TablebaseWdl::Win, 3),
+            tb_move("e", TablebaseWdl::Loss, -20),
+            tb_move("f", TablebaseWdl::CursedWin, 101),
+        ];
+        sort_moves(&mut moves);
+        let order: Vec<&str> = moves.iter().map(|m| m.uci.as_str()).collect();
+        assert_eq!(order, vec!["d", "b", "f", "a", "e", "c"]);
+    }
+
+    #[test]
+    fn no_tables_keeps_scores() {
+        let tables = Tablebase::<Chess>::new();
+        let mut lines = vec![BestMoves::default()];
+        assert!(!rescore_lines(&tables, &Chess::default(), &mut lines));
+        assert!(!lines[0].tablebase);
+    }
+}
@@ -0,0 +1,273 @@
+use std::net::{SocketAddr, TcpListener};
+
+use axum::{
+    extract::Query,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use shakmaty::{fen::Fen, CastlingMode, Chess, Position};
+use tauri::Manager;
+use tokio::sync::oneshot;
+
+use crate::{
+    error::Result,
+    tablebase::{probe, TablebaseMove, TablebaseProbe, TablebaseWdl},
+    AppState,
+};
+
+/// Category of a position in the Lichess tablebase API, for the side to move.
+fn category(wdl: Option<TablebaseWdl>) -> &'static str {
+    match wdl {
+        Some(TablebaseWdl::Win) => "win",
+        Some(TablebaseWdl::MaybeWin) => "maybe-win",
+        Some(TablebaseWdl::CursedWin) => "cursed-win",
+        Some(TablebaseWdl::Draw) => "draw",
+        Some(TablebaseWdl::BlessedLoss) => "blessed-loss",
+        Some(TablebaseWdl::MaybeLoss) => "maybe-loss",
+        Some(TablebaseWdl::Loss) => "loss",
+        None => "unknown",
+    }
+}
+
+/// A move as returned by the Lichess tablebase API, described from the point
+/// of view of the side to move after it.
+#[derive(Serialize, Debug)]
+struct LichessMove {
+    uci: String,
+    san: String,
+    zeroing: bool,
+    conversion: bool,
+    checkmate: bool,
+    stalemate: bool,
+    variant_win: bool,
+    variant_loss: bool,
+    insufficient_material: bool,
+    dtz: Option<i32>,
+    precise_dtz: Option<i32>,
+    dtm: Option<i32>,
+    dtw: Option<i32>,
+    dtc: Option<i32>,
+    category: &'static str,
+}
+
+/// Response of the `/standard` endpoint of the Lichess tablebase API.
+///
+/// Only Syzygy tables are available locally, so the distances to mate are
+/// always missing.
+#[derive(Serialize, Debug)]
+struct LichessResponse {
+    checkmate: bool,
+    stalemate: bool,
+    variant_win: bool,
+    variant_loss: bool,
+    insufficient_material: bool,
+    dtz: Option<i32>,
+    precise_dtz: Option<i32>,
+    dtm: Option<i32>,
+    dtw: Option<i32>,
+    dtc: Option<i32>,
+    category: &'static str,
+    moves: Vec<LichessMove>,
+}
+
+impl LichessMove {
+    fn new(pos: &Chess, m: &TablebaseMove) -> Self {
+        let after = m
+            .uci
+            .parse::<shakmaty::uci::UciMove>()
+            .ok()
+            .and_then(|uci| uci.to_move(pos).ok())
+            .map(|mv| {
+                let conversion = mv.is_capture() || mv.is_promotion();
+                let mut after = pos.clone();
+                after.play_unchecked(&mv);
+                (conversion, after.is_insufficient_material())
+            });
+        let (conversion, insufficient_material) = after.unwrap_or_default();
+
+        // The mated side is at zero plies from the end of the game
+        let dtz = if m.checkmate || m.stalemate {
+            Some(0)
+        } else {
+            m.dtz.map(|dtz| -dtz)
+        };
+        LichessMove {
+            uci: m.uci.clone(),
+            san: m.san.clone(),
+            zeroing: m.zeroing,
+            conversion,
+            checkmate: m.checkmate,
+            stalemate: m.stalemate,
+            variant_win: false,
+            variant_loss: false,
+            insufficient_material,
+            dtz,
+            precise_dtz: dtz.filter(|_| m.precise_dtz),
+            dtm: None,
+            dtw: None,
+            dtc: None,
+            category: category(m.wdl.map(TablebaseWdl::flip)),
+        }
+    }
+}
+
+impl LichessResponse {
+    fn new(pos: &Chess, probe: &TablebaseProbe) -> Self {
+        LichessResponse {
+            checkmate: pos.is_checkmate(),
+            stalemate: pos.is_stalemate(),
+            variant_win: false,
+            variant_loss: false,
+            insufficient_material: pos.is_insufficient_material(),
+            dtz: probe.dtz,
+            precise_dtz: probe.dtz.filter(|_| probe.precise_dtz),
+            dtm: None,
+            dtw: None,
+            dtc: None,
+            category: category(probe.wdl),
+            moves: probe
+                .moves
+                .iter()
+                .map(|m| LichessMove::new(pos, m))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ProbeQuery {
+    fen: String,
+}
+
+async fn standard(app: Extension<tauri::AppHandle>, query: Query<ProbeQuery>) -> Response {
+    let headers = [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")];
+
+    let Some(tables) = app.state::<AppState>().tablebase.read().await.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            headers,
+            "no tablebase loaded",
+        )
+            .into_response();
+    };
+
+    // The API also accepts underscores instead of spaces
+    let fen = query.fen.replace('_', " ");
+    let pos = Fen::from_ascii(fen.trim().as_bytes())
+        .ok()
+        .and_then(|fen| fen.into_position::<Chess>(CastlingMode::Chess960).ok());
+    let Some(pos) = pos else {
+        return (StatusCode::BAD_REQUEST, headers, "invalid fen").into_response();
+    };
+
+    let response = LichessResponse::new(&pos, &probe(&tables, &pos));
+    (headers, Json(response)).into_response()
+}
+
+/// Serves the local tablebases with the Lichess tablebase API on localhost.
+///
+/// Returns the port of the server, a random one is picked when `port` is 0.
+/// A running server is replaced.
+#[tauri::command]
+#[specta::specta]
+pub async fn start_tablebase_server(
+    port: u16,
+    state: tauri::State<'_, AppState>,
+    app: tauri::AppHandle,
+) -> Result<u16> {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port)))?;
+    listener.set_nonblocking(true)?;
+    let addr = listener.local_addr()?;
+
+    let router = Router::new()
+        .route("/standard", get(standard))
+        .layer(Extension(app));
+    let server = axum::Server::from_tcp(listener)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .serve(router.into_make_service());
+
+    let (shutdown, stopped) = oneshot::channel::<()>();
+    if let Some(previous) = state.tablebase_server.lock().await.replace(shutdown) {
+        let _ = previous.send(());
+    }
+
+    tauri::async_runtime::spawn(async move {
+        let server = server.with_graceful_shutdown(async {
+            let _ = stopped.await;
+        });
+        if let Err(e) = server.await {
+            error!("Tablebase server error: {}", e);
+        }
+        info!("Tablebase server on {} stopped", addr);
+    });
+
+    info!("Tablebase server listening on {}", addr);
+    Ok(addr.port())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn stop_tablebase_server(state: tauri::State<'_, AppState>) -> Result<()> {
+    if let Some(shutdown) = state.tablebase_server.lock().await.take() {
+        let _ = shutdown.send(());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_from_the_opponent_point_of_view() {
+        let pos: Chess = Fen::from_ascii(b"4k3/8/4K3/8/8/8/8/7R w - - 0 1")
+            .unwrap()
+            .into_position(CastlingMode::Chess960)
+            .unwrap();
+        let probe = TablebaseProbe {
+            wdl: Some(TablebaseWdl::Win),
+            dtz: Some(1),
+            precise_dtz: false,
+            moves: vec![
+                TablebaseMove {
+                    uci: "h1h8".to_string(),
+                    san: "Rh8#".to_string(),
+                    wdl: Some(TablebaseWdl::Win),
+                    dtz: Some(1),
+                    precise_dtz: true,
+                    zeroing: false,
+                    checkmate: true,
+                    stalemate: false,
+                },
+                TablebaseMove {
+                    uci: "h1h2".to_string(),
+                    san: "Rh2".to_string(),
+                    wdl: Some(TablebaseWdl::Win),
+                    dtz: Some(5),
+                    precise_dtz: false,
+                    zeroing: false,
+                    checkmate: false,
+                    stalemate: false,
+                },
+            ],
+        };
+
+        let response = LichessResponse::new(&pos, &probe);
+        assert_eq!(response.category, "win");
+        assert_eq!(response.precise_dtz, None);
+
+        let mate = &response.moves[0];
+        assert_eq!(
+            (mate.category, mate.dtz, mate.checkmate),
+            ("loss", Some(0), true)
+        );
+        let waiting = &response.moves[1];
+        assert_eq!((waiting.category, waiting.dtz), ("loss", Some(-5)));
+        assert_eq!(waiting.precise_dtz, None);
+        assert!(!waiting.conversion);
+    }
+}
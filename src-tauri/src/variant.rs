@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+use shakmaty::{
+    fen::Fen,
+    variant::{Variant, VariantPosition},
+    CastlingMode, PositionError,
+};
+use specta::Type;
+
+use crate::error::{Error, Result};
+
+/// The rules a game is played with.
+///
+/// Chess960 is not a variant of its own here: it only differs from standard
+/// chess by its starting position, which is stored as a FEN.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub enum GameVariant {
+    #[default]
+    Standard,
+    Crazyhouse,
+    ThreeCheck,
+    KingOfTheHill,
+    Atomic,
+    Antichess,
+    Horde,
+    RacingKings,
+}
+
+impl GameVariant {
+    /// Parses the value of a PGN `[Variant]` header, as written by Lichess,
+    /// ChessBase and most other tools.
+    pub fn from_pgn(name: &str) -> Option<Self> {
+        let name: String = name
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-' && *c != '_')
+            .collect::<String>()
+            .to_lowercase();
+        match name.as_str() {
+            "standard" | "chess" | "normal" | "chess960" | "fischerandom" | "fromposition" => {
+                Some(GameVariant::Standard)
+            }
+            "crazyhouse" | "zh" => Some(GameVariant::Crazyhouse),
+            "threecheck" | "3check" => Some(GameVariant::ThreeCheck),
+            "kingofthehill" | "koth" => Some(GameVariant::KingOfTheHill),
+            "atomic" => Some(GameVariant::Atomic),
+            "antichess" | "giveaway" => Some(GameVariant::Antichess),
+            "horde" => Some(GameVariant::Horde),
+            "racingkings" => Some(GameVariant::RacingKings),
+            _ => None,
+        }
+    }
+
+    /// Name of the variant in the PGN `[Variant]` header.
+    pub fn pgn_name(self) -> &'static str {
+        match self {
+            GameVariant::Standard => "Standard",
+            GameVariant::Crazyhouse => "Crazyhouse",
+            GameVariant::ThreeCheck => "Three-check",
+            GameVariant::KingOfTheHill => "King of the Hill",
+            GameVariant::Atomic => "Atomic",
+            GameVariant::Antichess => "Antichess",
+            GameVariant::Horde => "Horde",
+            GameVariant::RacingKings => "Racing Kings",
+        }
+    }
+
+    /// Values of the `UCI_Variant` option engines use for this variant, most
+    /// common first.
+    pub fn uci_names(self) -> &'static [&'static str] {
+        match self {
+            GameVariant::Standard => &["chess", "standard"],
+            GameVariant::Crazyhouse => &["crazyhouse"],
+            GameVariant::ThreeCheck => &["3check", "threecheck"],
+            GameVariant::KingOfTheHill => &["kingofthehill", "koth"],
+            GameVariant::Atomic => &["atomic"],
+            GameVariant::Antichess => &["antichess", "giveaway"],
+            GameVariant::Horde => &["horde"],
+            GameVariant::RacingKings => &["racingkings"],
+        }
+    }
+
+    pub fn is_standard(self) -> bool {
+        self == GameVariant::Standard
+    }
+
+    /// Returns the starting position of a game, `fen` being `None` for the
+    /// initial position of the variant.
+    pub fn position(self, fen: Option<&str>) -> Result<VariantPosition> {
+        let variant = Variant::from(self);
+        match fen {
+            Some(fen) => {
+                let fen = Fen::from_ascii(fen.as_bytes())?;
+                Ok(
+                    VariantPosition::from_setup(variant, fen.into_setup(), CastlingMode::Chess960)
+                        .or_else(PositionError::ignore_too_much_material)?,
+                )
+            }
+            None => Ok(VariantPosition::new(variant)),
+        }
+    }
+}
+
+impl From<GameVariant> for Variant {
+    fn from(variant: GameVariant) -> Self {
+        match variant {
+            GameVariant::Standard => Variant::Chess,
+            GameVariant::Crazyhouse => Variant::Crazyhouse,
+            GameVariant::ThreeCheck => Variant::ThreeCheck,
+            GameVariant::KingOfTheHill => Variant::KingOfTheHill,
+            GameVariant::Atomic => Variant::Atomic,
+            GameVariant::Antichess => Variant::Antichess,
+            GameVariant::Horde => Variant::Horde,
+            GameVariant::RacingKings => Variant::RacingKings,
+        }
+    }
+}
+
+impl From<Variant> for GameVariant {
+    fn from(variant: Variant) -> Self {
+        match variant {
+            Variant::Chess => GameVariant::Standard,
+            Variant::Crazyhouse => GameVariant::Crazyhouse,
+            Variant::ThreeCheck => GameVariant::ThreeCheck,
+            Variant::KingOfTheHill => GameVariant::KingOfTheHill,
+            Variant::Atomic => GameVariant::Atomic,
+            Variant::Antichess => GameVariant::Antichess,
+            Variant::Horde => GameVariant::Horde,
+            Variant::RacingKings => GameVariant::RacingKings,
+        }
+    }
+}
+
+impl std::str::FromStr for GameVariant {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        GameVariant::from_pgn(s).ok_or_else(|| Error::UnsupportedVariant(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::Position;
+
+    use super::*;
+
+    #[test]
+    fn pgn_names_round_trip() {
+        for variant in [
+            GameVariant::Standard,
+            GameVariant::Crazyhouse,
+            GameVariant::ThreeCheck,
+            GameVariant::KingOfTheHill,
+            GameVariant::Atomic,
+            GameVariant::Antichess,
+            GameVariant::Horde,
+            GameVariant::RacingKings,
+        ] {
+            assert_eq!(GameVariant::from_pgn(variant.pgn_name()), Some(variant));
+            assert_eq!(GameVariant::from(Variant::from(variant)), variant);
+        }
+        assert_eq!(
+            GameVariant::from_pgn("3check"),
+            Some(GameVariant::ThreeCheck)
+        );
+        assert_eq!(
+            GameVariant::from_pgn("Chess960"),
+            Some(GameVariant::Standard)
+        );
+        assert_eq!(GameVariant::from_pgn("Shogi"), None);
+    }
+
+    #[test]
+    fn starting_positions() {
+        let horde = GameVariant::Horde.position(None).unwrap();
+        assert_eq!(horde.board().pawns().count(), 44);
+
+        let zh = GameVariant::Crazyhouse
+            .position(Some(
+                "rnb1kbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNB1KBNR[Qq] w KQkq - 0 3",
+            ))
+            .unwrap();
+        assert!(zh.legal_moves().len() > 50);
+    }
+}
@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use vampirc_uci::uci::ScoreValue;
+
+/// Coefficients of the third degree polynomials in the game phase giving the
+/// centre and spread of Stockfish's win rate curve, in its internal units.
+const CENTER_COEFFS: [f64; 4] = [0.38036525, -2.82015070, 23.17882135, 307.36768407];
+const SPREAD_COEFFS: [f64; 4] = [-2.29434733, 13.27689788, -14.26828904, 63.45318330];
+
+/// Internal units of a pawn. Stockfish reports 100 centipawns for the
+/// advantage winning half of the games at move 32, when the centre of the
+/// curve is the sum of its coefficients.
+const PAWN_VALUE: f64 = 328.0;
+
+/// Strength of the engines the model was fitted on.
+const REFERENCE_ELO: f64 = 3000.0;
+const MIN_ELO: f64 = 800.0;
+
+/// Win, draw and loss probabilities from white's point of view.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Type)]
+pub struct Wdl {
+    pub win: f64,
+    pub draw: f64,
+    pub loss: f64,
+}
+
+impl Wdl {
+    pub const WIN: Wdl = Wdl {
+        win: 1.0,
+        draw: 0.0,
+        loss: 0.0,
+    };
+
+    pub const DRAW: Wdl = Wdl {
+        win: 0.0,
+        draw: 1.0,
+        loss: 0.0,
+    };
+
+    pub const LOSS: Wdl = Wdl {
+        win: 0.0,
+        draw: 0.0,
+        loss: 1.0,
+    };
+
+    /// Normalizes a WDL reported by an engine, usually in per mille.
+    pub fn from_counts(win: f64, draw: f64, loss: f64) -> Option<Self> {
+        let total = win + draw + loss;
+        (total > 0.0).then(|| Wdl {
+            win: win / total,
+            draw: draw / total,
+            loss: loss / total,
+        })
+    }
+
+    /// Probabilities of an evaluation from white's point of view, after `ply`
+    /// half moves of the game.
+    ///
+    /// This is the win rate model of Stockfish, fitted on engine games. Between
+    /// weaker players draws are rarer and advantages convert less reliably: the
+    /// curve widens with the Elo gap to engine level, which matches the winning
+    /// chances Lichess derives from its human games around 1500.
+    pub fn from_score(score: &ScoreValue, ply: u32, elo: Option<u32>) -> Self {
+        let cp = match score {
+            ScoreValue::Cp(cp) => *cp as f64,
+            ScoreValue::Mate(moves) if *moves > 0 => return Wdl::WIN,
+            ScoreValue::Mate(_) => return Wdl::LOSS,
+        };
+
+        let phase = ply.min(240) as f64 / 64.0;
+        let polynomial = |c: [f64; 4]| ((c[0] * phase + c[1]) * phase + c[2]) * phase + c[3];
+        let (mut center, mut spread) = (polynomial(CENTER_COEFFS), polynomial(SPREAD_COEFFS));
+        if let Some(elo) = elo {
+            let gap = REFERENCE_ELO - (elo as f64).clamp(MIN_ELO, REFERENCE_ELO);
+            center /= 2f64.powf(gap / 800.0);
+            spread *= 2f64.powf(gap / 400.0);
+        }
+
+        let value = cp * PAWN_VALUE / 100.0;
+        let win = 1.0 / (1.0 + ((center - value) / spread).exp());
+        let loss = 1.0 / (1.0 + ((center + value) / spread).exp());
+        Wdl {
+            win,
+            draw: (1.0 - win - loss).max(0.0),
+            loss,
+        }
+    }
+
+    /// Points white is expected to score, between 0 and 1.
+    pub fn expected_score(self) -> f64 {
+        self.win + self.draw / 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 0.01, "{a} != {b}");
+    }
+
+    #[test]
+    fn pawn_wins_half_the_games_at_move_32() {
+        let wdl = Wdl::from_score(&ScoreValue::Cp(100), 64, None);
+        assert_close(wdl.win, 0.5);
+        assert_close(wdl.win + wdl.draw + wdl.loss, 1.0);
+    }
+
+    #[test]
+    fn symmetric() {
+        let white = Wdl::from_score(&ScoreValue::Cp(150), 30, Some(2000));
+        let black = Wdl::from_score(&ScoreValue::Cp(-150), 30, Some(2000));
+        assert_close(white.win, black.loss);
+        assert_close(white.draw, black.draw);
+        assert_close(
+            Wdl::from_score(&ScoreValue::Cp(0), 30, None).expected_score(),
+            0.5,
+        );
+        assert_eq!(Wdl::from_score(&ScoreValue::Mate(-2), 30, None), Wdl::LOSS);
+    }
+
+    #[test]
+    fn weaker_players_draw_less() {
+        let engines = Wdl::from_score(&ScoreValue::Cp(0), 64, None);
+        let humans = Wdl::from_score(&ScoreValue::Cp(0), 64, Some(1500));
+        assert!(engines.draw > 0.9);
+        assert!(humans.draw < 0.1);
+        // Lichess gives 59% of winning chances to a pawn up
+        let pawn_up = Wdl::from_score(&ScoreValue::Cp(100), 64, Some(1500));
+        assert!((pawn_up.expected_score() - 0.59).abs() < 0.02);
+    }
+
+    #[test]
+    fn engine_counts() {
+        let wdl = Wdl::from_counts(250.0, 500.0, 250.0).unwrap();
+        assert_close(wdl.expected_score(), 0.5);
+        assert_eq!(Wdl::from_counts(0.0, 0.0, 0.0), None);
+    }
+}
@@ -0,0 +1,956 @@
+use std::{io, time::Duration};
+
+use derivative::Derivative;
+use log::{debug, trace, warn};
+use shakmaty::{
+    fen::Fen,
+    san::SanPlus,
+    uci::UciMove,
+    variant::{Variant, VariantPosition},
+    CastlingMode, Color, EnPassantMode, Move, Position,
+};
+use tokio::{
+    io::{
+        duplex, split, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream, Lines,
+        ReadHalf,
+    },
+    time::{sleep_until, timeout, Instant},
+};
+
+use crate::{
+    engine_transport::{lines, EngineIo, EngineReader, EngineWriter},
+    variant::GameVariant,
+};
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+/// Engines that don't answer `protover 2` with `done=0` get this long to send their features
+const FEATURE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Mate in N moves is reported as `100000 + N`
+const MATE_SCORE: i32 = 100000;
+const DUPLEX_BUFFER: usize = 64 * 1024;
+
+/// Wraps an engine speaking CECP (XBoard/WinBoard protocol) so that it can be
+/// driven with UCI like any other engine.
+///
+/// Searches with a depth, node or time limit use the analyze mode and are
+/// stopped by the adapter, searches on the clock make the engine play a move.
+pub fn adapt(io: EngineIo) -> EngineIo {
+    let (gui, adapter) = duplex(DUPLEX_BUFFER);
+    let (gui_read, gui_write) = split(gui);
+    let (adapter_read, adapter_write) = split(adapter);
+
+    let EngineIo {
+        writer,
+        reader,
+        child,
+    } = io;
+    let adapter = XboardAdapter::new(writer, Box::new(adapter_write));
+    tokio::spawn(adapter.run(BufReader::new(adapter_read).lines(), reader));
+
+    EngineIo {
+        writer: Box::new(gui_write),
+        reader: lines(Box::new(gui_read)),
+        child,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum OptionKind {
+    Check(bool),
+    Spin {
+        default: i64,
+        min: i64,
+        max: i64,
+    },
+    Combo {
+        default: String,
+        values: Vec<String>,
+    },
+    String(String),
+    Button,
+}
+
+/// An engine option announced with `feature option="..."`.
+#[derive(Debug, Clone, PartialEq)]
+struct XboardOption {
+    name: String,
+    kind: OptionKind,
+}
+
+impl XboardOption {
+    /// Parses a descriptor such as `Hash Size -spin 64 1 4096`.
+    fn parse(descriptor: &str) -> Option<Self> {
+        const KINDS: [&str; 10] = [
+            "check", "spin", "slider", "combo", "string", "file", "path", "button", "save", "reset",
+        ];
+        let (name, kind, args) = descriptor.match_indices(" -").find_map(|(i, _)| {
+            let rest = &descriptor[i + 2..];
+            let (kind, args) = rest.split_once(' ').unwrap_or((rest, ""));
+            KINDS
+                .contains(&kind)
+                .then(|| (descriptor[..i].trim(), kind, args.trim()))
+        })?;
+
+        let kind = match kind {
+            "check" => OptionKind::Check(args == "1"),
+            "spin" | "slider" => {
+                let mut values = args.split_whitespace().map(|v| v.parse::<i64>());
+                let (Some(Ok(default)), Some(Ok(min)), Some(Ok(max))) =
+                    (values.next(), values.next(), values.next())
+                else {
+                    return None;
+                };
+                OptionKind::Spin { default, min, max }
+            }
+            "combo" => {
+                let values: Vec<_> = args.split("///").map(str::trim).collect();
+                // The default value is marked with a star, otherwise it's the first one
+                let default = values
+                    .iter()
+                    .find_map(|v| v.strip_prefix('*'))
+                    .or(values.first().copied())?
+                    .to_string();
+                let values = values
+                    .iter()
+                    .map(|v| v.trim_start_matches('*').to_string())
+                    .collect();
+                OptionKind::Combo { default, values }
+            }
+            "string" | "file" | "path" => OptionKind::String(args.to_string()),
+            _ => OptionKind::Button,
+        };
+        Some(XboardOption {
+            name: name.to_string(),
+            kind,
+        })
+    }
+
+    /// The `option` line a UCI engine would send for this option.
+    fn uci(&self) -> String {
+        let name = &self.name;
+        match &self.kind {
+            OptionKind::Check(default) => {
+                format!("option name {name} type check default {default}")
+            }
+            OptionKind::Spin { default, min, max } => {
+                format!("option name {name} type spin default {default} min {min} max {max}")
+            }
+            OptionKind::Combo { default, values } => {
+                let vars: String = values.iter().map(|v| format!(" var {v}")).collect();
+                format!("option name {name} type combo default {default}{vars}")
+            }
+            OptionKind::String(default) if default.is_empty() => {
+                format!("option name {name} type string default <empty>")
+            }
+            OptionKind::String(default) => {
+                format!("option name {name} type string default {default}")
+            }
+            OptionKind::Button => format!("option name {name} type button"),
+        }
+    }
+}
+
+/// Splits a `feature` command into its `name=value` pairs.
+fn parse_features(args: &str) -> Vec<(String, String)> {
+    let mut features = Vec::new();
+    let mut rest = args.trim();
+    while let Some((name, after)) = rest.split_once('=') {
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => after.split_once(char::is_whitespace).unwrap_or((after, "")),
+        };
+        features.push((name.trim().to_string(), value.to_string()));
+        rest = after.trim_start();
+    }
+    features
+}
+
+#[derive(Debug, Derivative)]
+#[derivative(Default)]
+struct Features {
+    setboard: bool,
+    usermove: bool,
+    ping: bool,
+    #[derivative(Default(value = "true"))]
+    analyze: bool,
+    san: bool,
+    memory: bool,
+    smp: bool,
+    myname: Option<String>,
+    /// Variants other than `normal` the engine plays, by their CECP name
+    variants: Vec<String>,
+    options: Vec<XboardOption>,
+    /// Set to false by `done=0`, the engine then has no time limit to send its features
+    done: Option<bool>,
+}
+
+/// A line of thinking output, sent by the engine after `post`.
+#[derive(Debug, PartialEq)]
+struct Thinking {
+    depth: u32,
+    score: i32,
+    centis: u64,
+    nodes: u64,
+    pv: String,
+}
+
+impl Thinking {
+    /// Parses `ply score time nodes pv`, where extra fields may come before a
+    /// tab preceding the PV.
+    fn parse(line: &str) -> Option<Self> {
+        let (fields, pv) = match line.split_once('\t') {
+            Some((fields, pv)) => (fields, Some(pv)),
+            None => (line, None),
+        };
+        let mut fields = fields.split_whitespace();
+        let depth = fields
+            .next()?
+            .trim_end_matches(|c: char| !c.is_ascii_digit())
+            .parse()
+            .ok()?;
+        let score = fields.next()?.parse().ok()?;
+        let centis = fields.next()?.parse().ok()?;
+        let nodes = fields.next()?.parse().ok()?;
+        let pv = pv.map_or_else(|| fields.collect::<Vec<_>>().join(" "), str::to_string);
+        Some(Thinking {
+            depth,
+            score,
+            centis,
+            nodes,
+            pv,
+        })
+    }
+
+    /// The score as sent in a UCI `info` line.
+    fn uci_score(&self) -> String {
+        if self.score.abs() >= MATE_SCORE {
+            let moves = (self.score.abs() - MATE_SCORE).clamp(1, i8::MAX as i32);
+            format!("mate {}", moves * self.score.signum())
+        } else {
+            format!("cp {}", self.score)
+        }
+    }
+}
+
+/// Reads a move written by the engine, in coordinate notation or SAN.
+fn parse_move<P: Position>(pos: &P, token: &str) -> Option<Move> {
+    if let Some(mv) = UciMove::from_ascii(token.as_bytes())
+        .ok()
+        .and_then(|uci| uci.to_move(pos).ok())
+    {
+        return Some(mv);
+    }
+    let token = token.replace("0-0", "O-O");
+    SanPlus::from_ascii(token.as_bytes())
+        .ok()?
+        .san
+        .to_move(pos)
+        .ok()
+}
+
+/// Converts a PV to UCI moves, skipping move numbers and stopping at the first
+/// token that isn't a legal move.
+fn pv_moves<P: Position + Clone>(pos: &P, pv: &str) -> Vec<String> {
+    let mut pos = pos.clone();
+    let mut moves = Vec::new();
+    for token in pv.split_whitespace() {
+        // Move numbers, as in `1. e4` or `12...Nf6`
+        let token = token.rsplit('.').next().unwrap_or(token);
+        if token.is_empty() {
+            continue;
+        }
+        let Some(mv) = parse_move(&pos, token.trim_end_matches(['!', '?'])) else {
+            break;
+        };
+        moves.push(mv.to_uci(CastlingMode::Standard).to_string());
+        pos.play_unchecked(&mv);
+    }
+    moves
+}
+
+#[derive(Debug, Default)]
+struct Search {
+    /// The engine plays a move on the clock instead of analyzing
+    clock: bool,
+    depth: Option<u32>,
+    nodes: Option<u64>,
+    deadline: Option<Instant>,
+    best: Option<String>,
+    /// Depth and index of the last line, to number the lines of MultiPV engines
+    last_line: (u32, u16),
+}
+
+struct XboardAdapter {
+    engine: EngineWriter,
+    gui: Box<dyn AsyncWrite + Send + Unpin>,
+    features: Features,
+    /// Set with the `UCI_Variant` option, sent to the engine after `new`
+    variant: GameVariant,
+    position: VariantPosition,
+    multipv: u16,
+    pings: u32,
+    search: Option<Search>,
+}
+
+impl XboardAdapter {
+    fn new(engine: EngineWriter, gui: Box<dyn AsyncWrite + Send + Unpin>) -> Self {
+        XboardAdapter {
+            engine,
+            gui,
+            features: Features::default(),
+            variant: GameVariant::Standard,
+            position: VariantPosition::new(Variant::Chess),
+            multipv: 1,
+            pings: 0,
+            search: None,
+        }
+    }
+
+    async fn run(
+        mut self,
+        mut gui_lines: Lines<BufReader<ReadHalf<DuplexStream>>>,
+        mut engine_lines: EngineReader,
+    ) {
+        loop {
+            let deadline = self.search.as_ref().and_then(|s| s.deadline);
+            let result = tokio::select! {
+                line = gui_lines.next_line() => match line {
+                    Ok(Some(line)) => self.handle_command(&line, &mut engine_lines).await,
+                    _ => {
+                        let _ = self.send("quit").await;
+                        break;
+                    }
+                },
+                line = engine_lines.next_line() => match line {
+                    Ok(Some(line)) => self.handle_output(&line).await,
+                    _ => {
+                        debug!("CECP engine closed its output");
+                        break;
+                    }
+                },
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    self.finish_search().await
+                }
+            };
+            if let Err(e) = result {
+                warn!("CECP adapter error: {}", e);
+                break;
+            }
+        }
+    }
+
+    async fn send(&mut self, command: &str) -> io::Result<()> {
+        trace!("To CECP engine: {}", command);
+        self.engine
+            .write_all(format!("{command}\n").as_bytes())
+            .await?;
+        self.engine.flush().await
+    }
+
+    async fn reply(&mut self, line: &str) -> io::Result<()> {
+        self.gui.write_all(format!("{line}\n").as_bytes()).await?;
+        self.gui.flush().await
+    }
+
+    async fn handle_command(
+        &mut self,
+        line: &str,
+        engine_lines: &mut EngineReader,
+    ) -> io::Result<()> {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "uci" => self.handshake(engine_lines).await,
+            "isready" => self.ready(engine_lines).await,
+            "setoption" => self.set_option(args).await,
+            "ucinewgame" => {
+                self.stop_search().await?;
+                self.send("new").await?;
+                self.send("force").await?;
+                self.send("post").await
+            }
+            "position" => self.set_position(args).await,
+            "go" => self.go(args).await,
+            "stop" => match self.search.as_ref().map(|s| s.clock) {
+                // Make the engine move now, the move is the best move
+                Some(true) => self.send("?").await,
+                Some(false) => self.finish_search().await,
+                None => Ok(()),
+            },
+            "quit" => self.send("quit").await,
+            _ => {
+                trace!("Ignoring UCI command for CECP engine: {}", line);
+                Ok(())
+            }
+        }
+    }
+
+    async fn handshake(&mut self, engine_lines: &mut EngineReader) -> io::Result<()> {
+        self.send("xboard").await?;
+        self.send("protover 2").await?;
+
+        loop {
+            let line = if self.features.done == Some(false) {
+                engine_lines.next_line().await?
+            } else {
+                match timeout(FEATURE_TIMEOUT, engine_lines.next_line()).await {
+                    Ok(line) => line?,
+                    Err(_) => break,
+                }
+            };
+            let Some(line) = line else {
+                return Ok(());
+            };
+            let Some(args) = line.trim().strip_prefix("feature ") else {
+                trace!("CECP engine: {}", line);
+                continue;
+            };
+            for (name, value) in parse_features(args) {
+                let answer = if self.accept_feature(&name, &value) {
+                    "accepted"
+                } else {
+                    "rejected"
+                };
+                self.send(&format!("{answer} {name}")).await?;
+            }
+            if self.features.done == Some(true) {
+                break;
+            }
+        }
+        debug!("CECP engine features: {:?}", self.features);
+
+        self.send("new").await?;
+        self.send("force").await?;
+        self.send("post").await?;
+        // No pondering, the engine only thinks when asked to
+        self.send("easy").await?;
+
+        if let Some(name) = self.features.myname.clone() {
+            self.reply(&format!("id name {name}")).await?;
+        }
+        let mut options = Vec::new();
+        if self.features.memory && self.option("Hash").is_none() {
+            options.push("option name Hash type spin default 64 min 1 max 65536".to_string());
+        }
+        if self.features.smp && self.option("Threads").is_none() {
+            options.push("option name Threads type spin default 1 min 1 max 1024".to_string());
+        }
+        if !self.features.variants.is_empty() {
+            let vars: String = self
+                .features
+                .variants
+                .iter()
+                .map(|v| format!(" var {v}"))
+                .collect();
+            options.push(format!(
+                "option name UCI_Variant type combo default chess var chess{vars}"
+            ));
+        }
+        options.extend(self.features.options.iter().map(XboardOption::uci));
+        for option in options {
+            self.reply(&option).await?;
+        }
+        self.reply("uciok").await
+    }
+
+    fn accept_feature(&mut self, name: &str, value: &str) -> bool {
+        let enabled = value == "1";
+        match name {
+            "setboard" => self.features.setboard = enabled,
+            "usermove" => self.features.usermove = enabled,
+            "ping" => self.features.ping = enabled,
+            "analyze" => self.features.analyze = enabled,
+            "san" => self.features.san = enabled,
+            "memory" => self.features.memory = enabled,
+            "smp" => self.features.smp = enabled,
+            "myname" => self.features.myname = Some(value.to_string()),
+            "variants" => {
+                self.features.variants = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|v| *v != "normal" && GameVariant::from_pgn(v).is_some())
+                    .map(str::to_string)
+                    .collect()
+            }
+            "done" => self.features.done = Some(enabled),
+            "option" => match XboardOption::parse(value) {
+                Some(option) => self.features.options.push(option),
+                None => return false,
+            },
+            // Nothing to do, the adapter never relies on them
+            "sigint" | "sigterm" | "reuse" | "colors" | "time" | "draw" | "debug" | "name"
+            | "nps" => {}
+            _ => return false,
+        }
+        true
+    }
+
+    /// CECP name of the selected variant, `None` for standard chess.
+    fn variant_name(&self) -> Option<&str> {
+        if self.variant.is_standard() {
+            return None;
+        }
+        self.features
+            .variants
+            .iter()
+            .find(|v| GameVariant::from_pgn(v) == Some(self.variant))
+            .map(String::as_str)
+    }
+
+    fn option(&self, name: &str) -> Option<&XboardOption> {
+        self.features.options.iter().find(|o| o.name == name)
+    }
+
+    async fn ready(&mut self, engine_lines: &mut EngineReader) -> io::Result<()> {
+        if self.features.ping {
+            self.pings += 1;
+            let pong = format!("pong {}", self.pings);
+            self.send(&format!("ping {}", self.pings)).await?;
+            while let Some(line) = engine_lines.next_line().await? {
+                if line.trim() == pong {
+                    break;
+                }
+                self.handle_output(&line).await?;
+            }
+        }
+        self.reply("readyok").await
+    }
+
+    async fn set_option(&mut self, args: &str) -> io::Result<()> {
+        let args = args.strip_prefix("name ").unwrap_or(args);
+        let (name, value) = args.split_once(" value ").unwrap_or((args, ""));
+        let (name, value) = (name.trim(), value.trim());
+
+        if name == "MultiPV" {
+            self.multipv = value.parse().unwrap_or(1);
+        }
+        if name == "UCI_Variant" {
+            // Takes effect with the next position
+            self.variant = GameVariant::from_pgn(value).unwrap_or_default();
+            return Ok(());
+        }
+        let command = match self.option(name).map(|o| &o.kind) {
+            Some(OptionKind::Check(_)) => {
+                format!("option {name}={}", if value == "true" { 1 } else { 0 })
+            }
+            Some(OptionKind::Button) => format!("option {name}"),
+            Some(_) => format!("option {name}={value}"),
+            None if name == "Hash" && self.features.memory => format!("memory {value}"),
+            None if name == "Threads" && self.features.smp => format!("cores {value}"),
+            None => {
+                debug!("CECP engine has no option {}", name);
+                if name == "MultiPV" {
+                    self.multipv = 1;
+                }
+                return Ok(());
+            }
+        };
+        self.send(&command).await
+    }
+
+    async fn set_position(&mut self, args: &str) -> io::Result<()> {
+        let (fen, moves) = if let Some(rest) = args.strip_prefix("startpos") {
+            (START_FEN, rest)
+        } else if let Some(rest) = args.strip_prefix("fen ") {
+            rest.split_once(" moves").unwrap_or((rest, ""))
+        } else {
+            warn!("Invalid position command: {}", args);
+            return Ok(());
+        };
+        let fen = fen.trim();
+        let moves = moves.trim_start();
+        let moves = moves.strip_prefix("moves").unwrap_or(moves);
+
+        let Ok(mut position) = self.variant.position(Some(fen)) else {
+            warn!("Invalid position for CECP engine: {}", fen);
+            return Ok(());
+        };
+        let initial = VariantPosition::new(self.variant.into());
+        let is_initial = fen == START_FEN && self.variant.is_standard()
+            || fen == Fen::from_position(initial, EnPassantMode::Legal).to_string();
+
+        self.stop_search().await?;
+        self.send("force").await?;
+        if is_initial || !self.features.setboard || !self.variant.is_standard() {
+            if !is_initial && !self.features.setboard {
+                warn!("CECP engine can't set up positions, using the initial position");
+            }
+            self.send("new").await?;
+            // The variant is reset by `new`
+            if let Some(name) = self.variant_name() {
+                self.send(&format!("variant {name}")).await?;
+            }
+            self.send("force").await?;
+            self.send("post").await?;
+        }
+        if !is_initial && self.features.setboard {
+            self.send(&format!("setboard {fen}")).await?;
+        }
+
+        for uci in moves.split_whitespace() {
+            let Some(mv) = UciMove::from_ascii(uci.as_bytes())
+                .ok()
+                .and_then(|uci| uci.to_move(&position).ok())
+            else {
+                warn!("Illegal move for CECP engine: {}", uci);
+                break;
+            };
+            let notation = if self.features.san {
+                SanPlus::from_move(position.clone(), &mv).to_string()
+            } else {
+                mv.to_uci(CastlingMode::Standard).to_string()
+            };
+            let command = if self.features.usermove {
+                format!("usermove {notation}")
+            } else {
+                notation
+            };
+            self.send(&command).await?;
+            position.play_unchecked(&mv);
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    async fn go(&mut self, args: &str) -> io::Result<()> {
+        let mut search = Search::default();
+        let mut clock = [None; 4];
+        let mut movetime = None;
+        let mut infinite = false;
+        let mut tokens = args.split_whitespace();
+        while let Some(token) = tokens.next() {
+            let mut value = || tokens.next().and_then(|v| v.parse::<u64>().ok());
+            match token {
+                "depth" => search.depth = value().map(|v| v as u32),
+                "nodes" => search.nodes = value(),
+                "movetime" => movetime = value(),
+                "wtime" => clock[0] = value(),
+                "btime" => clock[1] = value(),
+                "winc" => clock[2] = value(),
+                "binc" => clock[3] = value(),
+                "infinite" => infinite = true,
+                _ => {}
+            }
+        }
+
+        self.stop_search().await?;
+        if let [Some(wtime), Some(btime), winc, binc] = clock {
+            if movetime.is_none() && !infinite {
+                let (ours, theirs, inc) = match self.position.turn() {
+                    Color::White => (wtime, btime, winc.unwrap_or(0)),
+                    Color::Black => (btime, wtime, binc.unwrap_or(0)),
+                };
+                let secs = ours / 1000;
+                let inc = if inc % 1000 == 0 {
+                    (inc / 1000).to_string()
+                } else {
+                    format!("{:.1}", inc as f64 / 1000.0)
+                };
+                self.send(&format!("level 0 {}:{:02} {inc}", secs / 60, secs % 60))
+                    .await?;
+                self.send(&format!("time {}", ours / 10)).await?;
+                self.send(&format!("otim {}", theirs / 10)).await?;
+                self.send("go").await?;
+                search.clock = true;
+                self.search = Some(search);
+                return Ok(());
+            }
+        }
+
+        search.deadline = movetime.map(|ms| Instant::now() + Duration::from_millis(ms));
+        if self.features.analyze {
+            self.send("post").await?;
+            self.send("analyze").await?;
+        } else {
+            // Without analyze mode the engine searches its best move for the side to move
+            if let Some(depth) = search.depth {
+                self.send(&format!("sd {depth}")).await?;
+            }
+            if let Some(ms) = movetime {
+                self.send(&format!("st {}", (ms / 1000).max(1))).await?;
+            }
+            self.send("go").await?;
+            search.clock = true;
+        }
+        self.search = Some(search);
+        Ok(())
+    }
+
+    /// Leaves the current search without reporting a best move.
+    async fn stop_search(&mut self) -> io::Result<()> {
+        match self.search.take() {
+            Some(search) if search.clock => self.send("force").await,
+            Some(_) => self.send("exit").await,
+            None => Ok(()),
+        }
+    }
+
+    /// Ends an analysis and reports its best move.
+    async fn finish_search(&mut self) -> io::Result<()> {
+        let Some(search) = self.search.take() else {
+            return Ok(());
+        };
+        if search.clock {
+            self.send("?").await?;
+            self.search = Some(Search {
+                deadline: None,
+                ..search
+            });
+            return Ok(());
+        }
+
+        self.send("exit").await?;
+        let best = search.best.or_else(|| {
+            self.position
+                .legal_moves()
+                .first()
+                .map(|mv| mv.to_uci(CastlingMode::Standard).to_string())
+        });
+        match best {
+            Some(best) => self.reply(&format!("bestmove {best}")).await,
+            None => self.reply("bestmove 0000").await,
+        }
+    }
+
+    async fn handle_output(&mut self, line: &str) -> io::Result<()> {
+        let line = line.trim();
+        trace!("CECP engine: {}", line);
+
+        if let Some(mv) = line.strip_prefix("move ") {
+            if !self.search.as_ref().is_some_and(|s| s.clock) {
+                return Ok(());
+            }
+            self.search = None;
+            // Keep the engine from playing on after its move
+            self.send("force").await?;
+            return match parse_move(&self.position, mv.trim()) {
+                Some(mv) => {
+                    let best = mv.to_uci(CastlingMode::Standard).to_string();
+                    self.reply(&format!("bestmove {best}")).await
+                }
+                None => {
+                    warn!("Illegal move from CECP engine: {}", mv);
+                    self.reply("bestmove 0000").await
+                }
+            };
+        }
+
+        if let Some(thinking) = Thinking::parse(line) {
+            return self.report(thinking).await;
+        }
+
+        if line.starts_with("Error")
+            || line.starts_with("Illegal move")
+            || line.starts_with("tellusererror")
+        {
+            warn!("CECP engine: {}", line);
+        }
+        Ok(())
+    }
+
+    /// Sends a line of thinking output as a UCI `info` line.
+    async fn report(&mut self, thinking: Thinking) -> io::Result<()> {
+        let moves = pv_moves(&self.position, &thinking.pv);
+        let multipv = self.multipv.max(1);
+        let Some(search) = self.search.as_mut() else {
+            return Ok(());
+        };
+        if moves.is_empty() {
+            return Ok(());
+        }
+
+        // MultiPV engines send the lines of a depth one after the other
+        let (last_depth, last_index) = search.last_line;
+        let index = if thinking.depth == last_depth && last_index < multipv {
+            last_index + 1
+        } else {
+            1
+        };
+        search.last_line = (thinking.depth, index);
+        if index == 1 {
+            search.best = moves.first().cloned();
+        }
+
+        let finished = index == multipv
+            && (search.depth.is_some_and(|depth| thinking.depth >= depth)
+                || search.nodes.is_some_and(|nodes| thinking.nodes >= nodes));
+
+        let ms = thinking.centis * 10;
+        let nps = if ms > 0 {
+            thinking.nodes * 1000 / ms
+        } else {
+            0
+        };
+        let info = format!(
+            "info depth {} multipv {} score {} time {} nodes {} nps {} pv {}",
+            thinking.depth,
+            index,
+            thinking.uci_score(),
+            ms,
+            thinking.nodes,
+            nps,
+            moves.join(" ")
+        );
+        self.reply(&info).await?;
+
+        if finished {
+            self.finish_search().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::Chess;
+
+    use super::*;
+
+    #[test]
+    fn parse_options() {
+        let option = |s: &str| XboardOption::parse(s).unwrap();
+        assert_eq!(
+            option("Hash Size -spin 64 1 4096").uci(),
+            "option name Hash Size type spin default 64 min 1 max 4096"
+        );
+        assert_eq!(
+            option("Ponder -check 1").uci(),
+            "option name Ponder type check default true"
+        );
+        assert_eq!(
+            option("Style -combo Solid /// *Normal /// Risky").uci(),
+            "option name Style type combo default Normal var Solid var Normal var Risky"
+        );
+        assert_eq!(
+            option("Book File -file ").uci(),
+            "option name Book File type string default <empty>"
+        );
+        assert_eq!(
+            option("Clear Hash -button").uci(),
+            "option name Clear Hash type button"
+        );
+        assert!(XboardOption::parse("Unknown -widget 3").is_none());
+
+        assert_eq!(
+            parse_features(
+                r#"myname="Crafty 25.2" setboard=1 option="Threads -spin 1 1 64" done=1"#
+            ),
+            [
+                ("myname".to_string(), "Crafty 25.2".to_string()),
+                ("setboard".to_string(), "1".to_string()),
+                ("option".to_string(), "Threads -spin 1 1 64".to_string()),
+                ("done".to_string(), "1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn supported_variants() {
+        let mut adapter =
+            XboardAdapter::new(Box::new(tokio::io::sink()), Box::new(tokio::io::sink()));
+        assert!(adapter.accept_feature("variants", "normal,crazyhouse,xiangqi,3check"));
+        assert_eq!(adapter.features.variants, ["crazyhouse", "3check"]);
+
+        adapter.variant = GameVariant::ThreeCheck;
+        assert_eq!(adapter.variant_name(), Some("3check"));
+        adapter.variant = GameVariant::Standard;
+        assert_eq!(adapter.variant_name(), None);
+    }
+
+    #[test]
+    fn thinking_to_uci() {
+        let thinking = Thinking::parse("12 -35 150 123456 1. e4 e5 2. Nf3 <HT>").unwrap();
+        assert_eq!(
+            (thinking.depth, thinking.score, thinking.centis),
+            (12, -35, 150)
+        );
+        assert_eq!(thinking.uci_score(), "cp -35");
+        assert_eq!(
+            pv_moves(&Chess::default(), &thinking.pv),
+            ["e2e4", "e7e5", "g1f3"]
+        );
+
+        let thinking = Thinking::parse("9& 100003 20 5000 7 0 0\te2e4 e7e5").unwrap();
+        assert_eq!(thinking.depth, 9);
+        assert_eq!(thinking.pv, "e2e4 e7e5");
+        assert_eq!(thinking.uci_score(), "mate 3");
+        let thinking = Thinking::parse("5 -100003 20 5000 e2e4").unwrap();
+        assert_eq!(thinking.uci_score(), "mate -3");
+
+        assert!(Thinking::parse("1-0 {White mates}").is_none());
+        assert!(Thinking::parse("move e2e4").is_none());
+    }
+
+    #[tokio::test]
+    async fn analyze_with_depth_limit() {
+        let (engine, fake) = duplex(DUPLEX_BUFFER);
+        let (engine_read, engine_write) = split(engine);
+        let io = adapt(EngineIo {
+            writer: Box::new(engine_write),
+            reader: lines(Box::new(engine_read)),
+            child: None,
+        });
+        let EngineIo {
+            mut writer,
+            mut reader,
+            ..
+        } = io;
+
+        let fake_engine = tokio::spawn(async move {
+            let (read, mut write) = split(fake);
+            let mut commands = BufReader::new(read).lines();
+            let mut received = Vec::new();
+            while let Ok(Some(command)) = commands.next_line().await {
+                let answer = match command.as_str() {
+                    "protover 2" => "feature myname=\"Fake\" setboard=1 ping=1 done=1\n",
+                    "ping 1" => "pong 1\n",
+                    "analyze" => "1 20 0 100 e5\n2 -15 1 300 1... d5 2. d4\n",
+                    _ => "",
+                };
+                received.push(command.clone());
+                write.write_all(answer.as_bytes()).await.unwrap();
+                if command == "quit" {
+                    break;
+                }
+            }
+            received
+        });
+
+        for command in [
+            "uci",
+            "isready",
+            "position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 moves g1f3",
+            "go depth 2",
+        ] {
+            writer
+                .write_all(format!("{command}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+
+        let mut output = Vec::new();
+        while let Some(line) = reader.next_line().await.unwrap() {
+            let done = line.starts_with("bestmove");
+            output.push(line);
+            if done {
+                break;
+            }
+        }
+        assert_eq!(
+            output,
+            [
+                "id name Fake",
+                "uciok",
+                "readyok",
+                "info depth 1 multipv 1 score cp 20 time 0 nodes 100 nps 0 pv e7e5",
+                "info depth 2 multipv 1 score cp -15 time 10 nodes 300 nps 30000 pv d7d5 d2d4",
+                "bestmove d7d5",
+            ]
+        );
+
+        writer.write_all(b"quit\n").await.unwrap();
+        let received = fake_engine.await.unwrap();
+        assert!(received.contains(&"g1f3".to_string()));
+        assert!(received.contains(&"exit".to_string()));
+    }
+}